@@ -0,0 +1,53 @@
+//! Color spaces and quantization.
+//!
+//! Images are stored in linear sRGB with straight alpha, so blending works on
+//! light intensities. Colors are converted with the sRGB transfer function
+//! when reading or writing integer image formats and when displaying, while
+//! float formats such as EXR hold linear values already.
+use crate::ImagePixel;
+
+/// Decode an sRGB encoded channel to linear.
+pub fn srgb_to_linear(value: f32) -> f32 {
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Encode a linear channel to sRGB.
+pub fn linear_to_srgb(value: f32) -> f32 {
+    if value <= 0.0031308 {
+        value * 12.92
+    } else {
+        1.055 * value.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+/// Convert an sRGB encoded pixel to linear, keeping its alpha.
+pub fn pixel_to_linear(pixel: &ImagePixel) -> ImagePixel {
+    let [r, g, b, a] = pixel.0;
+    ImagePixel::from([srgb_to_linear(r), srgb_to_linear(g), srgb_to_linear(b), a])
+}
+
+/// Convert a linear pixel to sRGB encoded, keeping its alpha.
+pub fn pixel_to_srgb(pixel: &ImagePixel) -> ImagePixel {
+    let [r, g, b, a] = pixel.0;
+    ImagePixel::from([linear_to_srgb(r), linear_to_srgb(g), linear_to_srgb(b), a])
+}
+
+/// 4x4 Bayer matrix for ordered dithering.
+const BAYER: [[u8; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+
+/// Quantize an encoded channel (0.0 - 1.0) to 8 bits, with ordered
+/// dithering at pixel (x, y).
+///
+/// The dither spreads the rounding error of smooth gradients into a fixed
+/// pattern instead of visible bands. It is deterministic, so the same image
+/// always quantizes the same way.
+pub fn quantize(value: f32, x: u32, y: u32) -> u8 {
+    let threshold = (BAYER[(y % 4) as usize][(x % 4) as usize] as f32 + 0.5) / 16.0 - 0.5;
+    (value.clamp(0.0, 1.0) * u8::MAX as f32 + threshold)
+        .round()
+        .clamp(0.0, u8::MAX as f32) as u8
+}
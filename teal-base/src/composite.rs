@@ -0,0 +1,271 @@
+//! Compositing of painted pixels and layers.
+//!
+//! This follows the W3C compositing model on straight alpha pixels: the
+//! blend mode mixes the source and backdrop colors where both are present,
+//! and the result is composited with source-over (or destination-over for
+//! `Behind`).
+use crate::ImagePixel;
+
+/// Blend mode for painting a source pixel onto a backdrop pixel.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum PaintMode {
+    /// Source over backdrop
+    #[default]
+    Normal,
+
+    /// Product of the colors, darkening
+    Multiply,
+
+    /// Inverse product of the inverse colors, lightening
+    Screen,
+
+    /// Multiply dark and screen light backdrop colors
+    Overlay,
+
+    /// Sum of the colors
+    Add,
+
+    /// Backdrop minus source, down to 0
+    Subtract,
+
+    /// Darker of the colors
+    Darken,
+
+    /// Lighter of the colors
+    Lighten,
+
+    /// Hue and saturation of the source with the luminosity of the backdrop
+    Color,
+
+    /// Luminosity of the source with the hue and saturation of the backdrop
+    Luminosity,
+
+    /// Paint only where the backdrop is transparent
+    Behind,
+}
+
+impl PaintMode {
+    /// All modes, in cycling order.
+    pub const ALL: [PaintMode; 11] = [
+        PaintMode::Normal,
+        PaintMode::Multiply,
+        PaintMode::Screen,
+        PaintMode::Overlay,
+        PaintMode::Add,
+        PaintMode::Subtract,
+        PaintMode::Darken,
+        PaintMode::Lighten,
+        PaintMode::Color,
+        PaintMode::Luminosity,
+        PaintMode::Behind,
+    ];
+
+    /// Next mode in cycling order.
+    pub fn next(self) -> PaintMode {
+        let index = PaintMode::ALL
+            .iter()
+            .position(|mode| *mode == self)
+            .unwrap_or(0);
+        PaintMode::ALL[(index + 1) % PaintMode::ALL.len()]
+    }
+
+    /// Name of the mode.
+    pub fn name(self) -> &'static str {
+        match self {
+            PaintMode::Normal => "normal",
+            PaintMode::Multiply => "multiply",
+            PaintMode::Screen => "screen",
+            PaintMode::Overlay => "overlay",
+            PaintMode::Add => "add",
+            PaintMode::Subtract => "subtract",
+            PaintMode::Darken => "darken",
+            PaintMode::Lighten => "lighten",
+            PaintMode::Color => "color",
+            PaintMode::Luminosity => "luminosity",
+            PaintMode::Behind => "behind",
+        }
+    }
+
+    /// Look up a mode by name.
+    pub fn from_name(name: &str) -> Option<PaintMode> {
+        PaintMode::ALL.into_iter().find(|mode| mode.name() == name)
+    }
+
+    /// Mix backdrop and source colors.
+    fn mix(self, backdrop: [f32; 3], source: [f32; 3]) -> [f32; 3] {
+        let separable = |f: fn(f32, f32) -> f32| {
+            [
+                f(backdrop[0], source[0]),
+                f(backdrop[1], source[1]),
+                f(backdrop[2], source[2]),
+            ]
+        };
+        match self {
+            PaintMode::Normal | PaintMode::Behind => source,
+            PaintMode::Multiply => separable(|cb, cs| cb * cs),
+            PaintMode::Screen => separable(|cb, cs| cb + cs - cb * cs),
+            PaintMode::Overlay => separable(|cb, cs| {
+                if cb <= 0.5 {
+                    2.0 * cb * cs
+                } else {
+                    let cb = 2.0 * cb - 1.0;
+                    cb + cs - cb * cs
+                }
+            }),
+            PaintMode::Add => separable(|cb, cs| cb + cs),
+            PaintMode::Subtract => separable(|cb, cs| (cb - cs).max(0.0)),
+            PaintMode::Darken => separable(f32::min),
+            PaintMode::Lighten => separable(f32::max),
+            PaintMode::Color => set_lum(source, lum(backdrop)),
+            PaintMode::Luminosity => set_lum(backdrop, lum(source)),
+        }
+    }
+
+    /// Composite a source pixel onto a backdrop pixel with an extra opacity
+    /// factor.
+    pub fn composite(self, backdrop: &ImagePixel, source: &ImagePixel, opacity: f32) -> ImagePixel {
+        let src_a = source.0[3] * opacity;
+        let dst_a = backdrop.0[3];
+        let out_a = src_a + dst_a * (1.0 - src_a);
+        if out_a <= 0.0 {
+            return ImagePixel::from([0.0, 0.0, 0.0, 0.0]);
+        }
+
+        let cb = [backdrop.0[0], backdrop.0[1], backdrop.0[2]];
+        let cs = [source.0[0], source.0[1], source.0[2]];
+        let mixed = self.mix(cb, cs);
+        let mut out = [0.0, 0.0, 0.0, out_a];
+        for (i, channel) in out.iter_mut().take(3).enumerate() {
+            *channel = if self == PaintMode::Behind {
+                (dst_a * cb[i] + src_a * (1.0 - dst_a) * cs[i]) / out_a
+            } else {
+                let blended = (1.0 - dst_a) * cs[i] + dst_a * mixed[i];
+                (src_a * blended + dst_a * (1.0 - src_a) * cb[i]) / out_a
+            };
+        }
+        ImagePixel::from(out)
+    }
+}
+
+/// Luminosity of a color.
+fn lum(color: [f32; 3]) -> f32 {
+    0.3 * color[0] + 0.59 * color[1] + 0.11 * color[2]
+}
+
+/// Set the luminosity of a color, keeping it in gamut.
+fn set_lum(color: [f32; 3], lum_value: f32) -> [f32; 3] {
+    let d = lum_value - lum(color);
+    let color = color.map(|channel| channel + d);
+
+    // Clip the color to 0.0 - 1.0 while keeping its luminosity.
+    let l = lum(color);
+    let min = color[0].min(color[1]).min(color[2]);
+    let max = color[0].max(color[1]).max(color[2]);
+    color.map(|mut channel| {
+        if min < 0.0 && l - min > 0.0 {
+            channel = l + (channel - l) * l / (l - min);
+        }
+        if max > 1.0 && max - l > 0.0 {
+            channel = l + (channel - l) * (1.0 - l) / (max - l);
+        }
+        channel
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn px(pixel: [f32; 4]) -> ImagePixel {
+        ImagePixel::from(pixel)
+    }
+
+    fn assert_near(a: ImagePixel, b: [f32; 4]) {
+        for (a, b) in a.0.iter().zip(b) {
+            assert!((a - b).abs() < 1e-5, "{a:?} != {b:?}");
+        }
+    }
+
+    #[test]
+    fn normal_blends_by_opacity() {
+        let backdrop = px([0.0, 0.0, 1.0, 1.0]);
+        let source = px([1.0, 0.0, 0.0, 1.0]);
+        assert_near(
+            PaintMode::Normal.composite(&backdrop, &source, 1.0),
+            [1.0, 0.0, 0.0, 1.0],
+        );
+        assert_near(
+            PaintMode::Normal.composite(&backdrop, &source, 0.5),
+            [0.5, 0.0, 0.5, 1.0],
+        );
+        assert_near(
+            PaintMode::Normal.composite(&backdrop, &source, 0.0),
+            [0.0, 0.0, 1.0, 1.0],
+        );
+    }
+
+    #[test]
+    fn modes_mix_opaque_colors() {
+        let backdrop = px([0.5, 1.0, 0.0, 1.0]);
+        let source = px([0.5, 0.5, 0.5, 1.0]);
+        let cases = [
+            (PaintMode::Multiply, [0.25, 0.5, 0.0, 1.0]),
+            (PaintMode::Screen, [0.75, 1.0, 0.5, 1.0]),
+            (PaintMode::Add, [1.0, 1.5, 0.5, 1.0]),
+            (PaintMode::Subtract, [0.0, 0.5, 0.0, 1.0]),
+            (PaintMode::Darken, [0.5, 0.5, 0.0, 1.0]),
+            (PaintMode::Lighten, [0.5, 1.0, 0.5, 1.0]),
+        ];
+        for (mode, expected) in cases {
+            assert_near(mode.composite(&backdrop, &source, 1.0), expected);
+        }
+    }
+
+    #[test]
+    fn source_is_kept_over_a_transparent_backdrop() {
+        let backdrop = px([0.0; 4]);
+        let source = px([0.2, 0.4, 0.6, 0.5]);
+        for mode in PaintMode::ALL {
+            assert_near(mode.composite(&backdrop, &source, 1.0), source.0);
+        }
+        assert_near(
+            PaintMode::Normal.composite(&backdrop, &backdrop, 1.0),
+            [0.0; 4],
+        );
+    }
+
+    #[test]
+    fn behind_only_paints_transparent_areas() {
+        let source = px([1.0, 0.0, 0.0, 1.0]);
+        let opaque = px([0.0, 0.0, 1.0, 1.0]);
+        assert_near(
+            PaintMode::Behind.composite(&opaque, &source, 1.0),
+            [0.0, 0.0, 1.0, 1.0],
+        );
+        let half = px([0.0, 0.0, 1.0, 0.5]);
+        assert_near(
+            PaintMode::Behind.composite(&half, &source, 1.0),
+            [0.5, 0.0, 0.5, 1.0],
+        );
+    }
+
+    #[test]
+    fn color_keeps_the_backdrop_luminosity() {
+        let backdrop = px([0.2, 0.2, 0.2, 1.0]);
+        let source = px([1.0, 0.0, 0.0, 1.0]);
+        let out = PaintMode::Color.composite(&backdrop, &source, 1.0);
+        assert!((lum([out.0[0], out.0[1], out.0[2]]) - 0.2).abs() < 1e-5);
+        assert!(out.0[0] > out.0[1] && out.0[1] == out.0[2]);
+
+        let out = PaintMode::Luminosity.composite(&backdrop, &source, 1.0);
+        assert_near(out, [0.3, 0.3, 0.3, 1.0]);
+    }
+
+    #[test]
+    fn names_round_trip() {
+        for mode in PaintMode::ALL {
+            assert_eq!(PaintMode::from_name(mode.name()), Some(mode));
+        }
+        assert_eq!(PaintMode::from_name("unknown"), None);
+    }
+}
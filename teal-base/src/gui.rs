@@ -1,4 +1,5 @@
 //! Generic GUI traits and data structures.
+use serde::{Deserialize, Serialize};
 use std::process::ExitCode;
 
 /// Options for the GUI.
@@ -23,10 +24,67 @@ pub trait GUI {
 /// This is where most communication occurs in the run/event handling closure.
 pub trait GUIContext {
     fn screen(&mut self) -> impl crate::ScreenBuffer;
+
+    /// Show a message to the user, such as the result of a command.
+    fn show_message(&mut self, kind: MessageKind, text: &str);
+
+    /// Update the persistent status fields.
+    fn set_status(&mut self, status: &Status);
+
+    /// Request the image on the system clipboard. It is read asynchronously
+    /// and sent as an `Event::ClipboardImage`, if there is one.
+    fn request_clipboard_image(&mut self);
+
+    /// Time of the current event in milliseconds, from an arbitrary start.
+    fn time_ms(&self) -> u64;
+}
+
+/// Persistent status fields shown by the GUI.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Status {
+    /// Image coordinates under the cursor, if it is over the image.
+    pub cursor: Option<(u32, u32)>,
+
+    /// Zoom level in percent.
+    pub zoom_percent: f64,
+
+    /// Name of the selected brush.
+    pub brush: Option<String>,
+
+    /// Brush size in percent of the brush image size.
+    pub brush_size_percent: f64,
+
+    /// Is the brush erasing instead of painting?
+    pub eraser: bool,
+
+    /// Is the fill tool selected instead of the brush?
+    pub fill: bool,
+
+    /// Selection tool and operation, if a selection tool is selected.
+    pub selection: Option<String>,
+
+    /// Current color (RGBA).
+    pub color: [f32; 4],
+
+    /// Number of operations that can be undone.
+    pub undo_count: usize,
+
+    /// Number of operations that can be redone.
+    pub redo_count: usize,
+}
+
+/// Kind of message shown to the user.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum MessageKind {
+    /// Informational message
+    Info,
+
+    /// Error message
+    Error,
 }
 
 /// Enum representing various types and sequences of key presses
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub enum Key {
     /// An entered key sequence with possible modifiers
     Sequence {
@@ -43,7 +101,7 @@ pub enum Key {
 }
 
 /// Key event
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum KeyEvent {
     /// Key press
     Press(Key),
@@ -52,20 +110,63 @@ pub enum KeyEvent {
     Release(Key),
 }
 
+/// Input device data for a single drag sample
+#[derive(Copy, Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct InputSample {
+    /// Pressure from 0.0 to 1.0 (1.0 for devices without pressure)
+    pub pressure: f64,
+
+    /// Tilt along the x axis, from -1.0 to 1.0
+    pub tilt_x: f64,
+
+    /// Tilt along the y axis, from -1.0 to 1.0
+    pub tilt_y: f64,
+
+    /// Event time in milliseconds, from an arbitrary starting point
+    pub time_ms: u32,
+}
+
+impl Default for InputSample {
+    fn default() -> InputSample {
+        InputSample {
+            pressure: 1.0,
+            tilt_x: 0.0,
+            tilt_y: 0.0,
+            time_ms: 0,
+        }
+    }
+}
+
+impl InputSample {
+    /// Interpolate linearly between this sample and another one.
+    pub fn lerp(&self, other: &InputSample, t: f64) -> InputSample {
+        let time = self.time_ms as f64 + (other.time_ms as f64 - self.time_ms as f64) * t;
+        InputSample {
+            pressure: self.pressure + (other.pressure - self.pressure) * t,
+            tilt_x: self.tilt_x + (other.tilt_x - self.tilt_x) * t,
+            tilt_y: self.tilt_y + (other.tilt_y - self.tilt_y) * t,
+            time_ms: time.round() as u32,
+        }
+    }
+}
+
 /// Drag event
-#[derive(Debug)]
+///
+/// `Begin` carries the screen point the drag starts at, while `Update` and
+/// `End` carry offsets from that start point rather than screen points.
+#[derive(Debug, Serialize, Deserialize)]
 pub enum DragEvent {
-    /// Start of a drag gesture
-    Begin(f64, f64),
+    /// Start of a drag gesture, at a screen point
+    Begin(f64, f64, InputSample),
 
-    /// Update drag gesture
-    Update(f64, f64),
+    /// Update drag gesture, with the offset from the start point
+    Update(f64, f64, InputSample),
 
-    /// Finish drag gesture
-    End(f64, f64),
+    /// Finish drag gesture, with the offset from the start point
+    End(f64, f64, InputSample),
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 pub enum Event {
     /// A key event (press or release)
     Key(KeyEvent),
@@ -73,9 +174,23 @@ pub enum Event {
     /// Drag event motion
     Drag(DragEvent),
 
-    /// A new color was chosen
+    /// Pointer motion without a drag, in screen coordinates
+    Motion(f64, f64),
+
+    /// A new color was chosen, in sRGB
     ColorUpdate { r: f32, g: f32, b: f32, a: f32 },
 
-    /// Window resize
-    Resize,
+    /// Window resize, with the new screen dimensions
+    Resize { width: u32, height: u32 },
+
+    /// A command entered on the command line (without the leading ':')
+    Command(String),
+
+    /// An image read from the system clipboard, as 8-bit sRGB RGBA pixels
+    /// in row order with straight alpha
+    ClipboardImage {
+        width: u32,
+        height: u32,
+        pixels: Vec<u8>,
+    },
 }
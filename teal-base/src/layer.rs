@@ -0,0 +1,483 @@
+//! Layer stack and layer compositing.
+use crate::{Image, ImagePixel, PaintMode, Selection};
+use serde::{Deserialize, Serialize};
+use std::collections::HashSet;
+
+/// Unique identifier for a layer in a stack.
+///
+/// Identifiers stay stable while layers are added, removed and reordered, so
+/// operations can keep referring to the layer they modified.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct LayerId(pub u32);
+
+/// Blend mode used when compositing a layer over the layers below it.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum BlendMode {
+    Normal,
+    Multiply,
+    Screen,
+    Add,
+}
+
+impl BlendMode {
+    /// All blend modes, in cycling order.
+    pub const ALL: [BlendMode; 4] = [
+        BlendMode::Normal,
+        BlendMode::Multiply,
+        BlendMode::Screen,
+        BlendMode::Add,
+    ];
+
+    /// Return the next blend mode, wrapping around at the end.
+    pub fn next(self) -> BlendMode {
+        let idx = BlendMode::ALL
+            .iter()
+            .position(|mode| *mode == self)
+            .unwrap();
+        BlendMode::ALL[(idx + 1) % BlendMode::ALL.len()]
+    }
+
+    /// Paint mode that composites like this blend mode.
+    fn paint_mode(self) -> PaintMode {
+        match self {
+            BlendMode::Normal => PaintMode::Normal,
+            BlendMode::Multiply => PaintMode::Multiply,
+            BlendMode::Screen => PaintMode::Screen,
+            BlendMode::Add => PaintMode::Add,
+        }
+    }
+}
+
+/// A single named layer.
+#[derive(Clone, Debug)]
+pub struct Layer {
+    /// Stable layer identifier.
+    pub id: LayerId,
+
+    /// Display name of the layer.
+    pub name: String,
+
+    /// Pixel data for this layer.
+    pub image: Image,
+
+    /// Layer opacity, from 0.0 - 1.0.
+    pub opacity: f32,
+
+    /// Is the layer shown in the composite?
+    pub visible: bool,
+
+    /// Locked layers cannot be painted on.
+    pub locked: bool,
+
+    /// Blend mode used when compositing this layer.
+    pub blend_mode: BlendMode,
+}
+
+/// Ordered stack of layers, from bottom (index 0) to top.
+///
+/// All layers in the stack share the same dimensions.
+#[derive(Clone, Debug)]
+pub struct LayerStack {
+    layers: Vec<Layer>,
+
+    /// Index of the active layer.
+    active: usize,
+
+    /// Next layer identifier to hand out.
+    next_id: u32,
+
+    /// Selection masking where layers are painted, if any.
+    selection: Option<Selection>,
+}
+
+impl LayerStack {
+    /// Create a new stack with a single layer holding the given image.
+    pub fn new(image: Image) -> LayerStack {
+        let mut stack = LayerStack {
+            layers: vec![],
+            active: 0,
+            next_id: 0,
+            selection: None,
+        };
+        let id = stack.alloc_id();
+        stack.layers.push(Layer {
+            id,
+            name: "Background".to_string(),
+            image,
+            opacity: 1.0,
+            visible: true,
+            locked: false,
+            blend_mode: BlendMode::Normal,
+        });
+        stack
+    }
+
+    /// Create a stack from existing layers, bottom first, handing out ids
+    /// from `next_id` for new layers.
+    ///
+    /// Returns None if there are no layers, the layer dimensions differ, the
+    /// active index is out of range, or an id is repeated or not below
+    /// `next_id`.
+    pub fn from_layers(layers: Vec<Layer>, active: usize, next_id: u32) -> Option<LayerStack> {
+        let first = layers.first()?;
+        let dims = first.image.dimensions();
+        if active >= layers.len() || layers.iter().any(|layer| layer.image.dimensions() != dims) {
+            return None;
+        }
+        let mut ids = HashSet::new();
+        if !layers
+            .iter()
+            .all(|layer| layer.id.0 < next_id && ids.insert(layer.id))
+        {
+            return None;
+        }
+        Some(LayerStack {
+            layers,
+            active,
+            next_id,
+            selection: None,
+        })
+    }
+
+    /// Next layer identifier to hand out. Identifiers are never reused, so
+    /// operations can't apply to a new layer in place of a removed one.
+    pub fn next_id(&self) -> u32 {
+        self.next_id
+    }
+
+    fn alloc_id(&mut self) -> LayerId {
+        let id = LayerId(self.next_id);
+        self.next_id += 1;
+        id
+    }
+
+    /// Width of every layer in the stack.
+    pub fn width(&self) -> u32 {
+        self.layers[0].image.width()
+    }
+
+    /// Height of every layer in the stack.
+    pub fn height(&self) -> u32 {
+        self.layers[0].image.height()
+    }
+
+    /// Layers from bottom to top.
+    pub fn layers(&self) -> &[Layer] {
+        &self.layers
+    }
+
+    /// Index of the active layer.
+    pub fn active_index(&self) -> usize {
+        self.active
+    }
+
+    /// Get the active layer.
+    pub fn active(&self) -> &Layer {
+        &self.layers[self.active]
+    }
+
+    /// Get the active layer mutably.
+    pub fn active_mut(&mut self) -> &mut Layer {
+        &mut self.layers[self.active]
+    }
+
+    /// Get the active layer's image.
+    pub fn active_image(&self) -> &Image {
+        &self.layers[self.active].image
+    }
+
+    /// Look up a layer by id.
+    pub fn get(&self, id: LayerId) -> Option<&Layer> {
+        self.layers.iter().find(|layer| layer.id == id)
+    }
+
+    /// Look up a layer by id, mutably.
+    pub fn get_mut(&mut self, id: LayerId) -> Option<&mut Layer> {
+        self.layers.iter_mut().find(|layer| layer.id == id)
+    }
+
+    /// Look up a layer by id, mutably, along with the selection that
+    /// painting on it is masked by.
+    pub fn get_mut_with_selection(
+        &mut self,
+        id: LayerId,
+    ) -> Option<(&mut Layer, Option<&Selection>)> {
+        let layer = self.layers.iter_mut().find(|layer| layer.id == id)?;
+        Some((layer, self.selection.as_ref()))
+    }
+
+    /// Current selection, or None if the whole canvas can be painted.
+    pub fn selection(&self) -> Option<&Selection> {
+        self.selection.as_ref()
+    }
+
+    /// Set the selection. An empty selection is the same as no selection.
+    pub fn set_selection(&mut self, selection: Option<Selection>) {
+        self.selection = selection.filter(|selection| !selection.is_empty());
+    }
+
+    /// Add a new transparent layer above the active one and make it active.
+    pub fn add_layer(&mut self, name: &str) -> LayerId {
+        let id = self.alloc_id();
+        let layer = Layer {
+            id,
+            name: name.to_string(),
+            image: Image::new(self.width(), self.height()),
+            opacity: 1.0,
+            visible: true,
+            locked: false,
+            blend_mode: BlendMode::Normal,
+        };
+        self.active += 1;
+        self.layers.insert(self.active, layer);
+        id
+    }
+
+    /// Remove the active layer. The last remaining layer cannot be removed.
+    pub fn remove_active(&mut self) -> Option<Layer> {
+        self.remove_layer(self.active)
+    }
+
+    /// Remove the layer at index. The active layer stays the same if it is
+    /// another one, and becomes the layer below otherwise. The last remaining
+    /// layer cannot be removed.
+    pub fn remove_layer(&mut self, index: usize) -> Option<Layer> {
+        if self.layers.len() == 1 || index >= self.layers.len() {
+            return None;
+        }
+        let layer = self.layers.remove(index);
+        if self.active >= index && self.active > 0 {
+            self.active -= 1;
+        }
+        Some(layer)
+    }
+
+    /// Insert a layer at index and make it active, such as one that was
+    /// removed before.
+    ///
+    /// Gives the layer back, leaving the stack unchanged, if the index is out
+    /// of range, the layer dimensions differ from the stack or its id is in
+    /// use.
+    pub fn insert_layer(&mut self, index: usize, layer: Layer) -> Result<(), Layer> {
+        if index > self.layers.len()
+            || layer.image.dimensions() != (self.width(), self.height())
+            || self.get(layer.id).is_some()
+        {
+            return Err(layer);
+        }
+        self.next_id = self.next_id.max(layer.id.0 + 1);
+        self.layers.insert(index, layer);
+        self.active = index;
+        Ok(())
+    }
+
+    /// Index of a layer in the stack by id.
+    pub fn index_of(&self, id: LayerId) -> Option<usize> {
+        self.layers.iter().position(|layer| layer.id == id)
+    }
+
+    /// Select the layer at index as active. Returns false if out of range.
+    pub fn select(&mut self, index: usize) -> bool {
+        if index < self.layers.len() {
+            self.active = index;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Resize the canvas of every layer and the selection, keeping the top
+    /// left corner in place. Added areas are transparent and not selected.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let transparent = ImagePixel::from([0.0, 0.0, 0.0, 0.0]);
+        for layer in self.layers.iter_mut() {
+            let image = Image::from_fn(width, height, |x, y| {
+                layer
+                    .image
+                    .get_pixel_checked(x, y)
+                    .copied()
+                    .unwrap_or(transparent)
+            });
+            layer.image = image;
+        }
+        if let Some(mut selection) = self.selection.take() {
+            selection.resize(width, height);
+            self.set_selection(Some(selection));
+        }
+    }
+
+    /// Composite all visible layers at image coordinates (x, y).
+    pub fn composite_pixel(&self, x: u32, y: u32) -> ImagePixel {
+        let mut out = ImagePixel::from([0.0, 0.0, 0.0, 0.0]);
+        for layer in self.layers.iter().filter(|layer| layer.visible) {
+            let pixel = layer.image.get_pixel(x, y);
+            out = layer
+                .blend_mode
+                .paint_mode()
+                .composite(&out, pixel, layer.opacity);
+        }
+        out
+    }
+
+    /// Flatten the visible layers into a single image.
+    pub fn flatten(&self) -> Image {
+        Image::from_fn(self.width(), self.height(), |x, y| {
+            self.composite_pixel(x, y)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: [f32; 4] = [1.0, 0.0, 0.0, 1.0];
+
+    /// A 2x2 stack with an opaque red background.
+    fn stack() -> LayerStack {
+        LayerStack::new(Image::from_pixel(2, 2, ImagePixel::from(RED)))
+    }
+
+    fn names(layers: &LayerStack) -> Vec<&str> {
+        layers
+            .layers()
+            .iter()
+            .map(|layer| layer.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn layers_are_added_above_the_active_one() {
+        let mut layers = stack();
+        let a = layers.add_layer("a");
+        layers.select(0);
+        let b = layers.add_layer("b");
+        assert_eq!(names(&layers), ["Background", "b", "a"]);
+        assert_eq!(layers.active_index(), 1);
+        assert_ne!(a, b);
+        assert_eq!(layers.index_of(a), Some(2));
+        assert_eq!(layers.active().image.get_pixel(0, 0).0, [0.0; 4]);
+    }
+
+    #[test]
+    fn the_last_layer_cannot_be_removed() {
+        let mut layers = stack();
+        assert!(layers.remove_active().is_none());
+        assert!(layers.remove_layer(1).is_none());
+        assert_eq!(layers.layers().len(), 1);
+    }
+
+    #[test]
+    fn removing_a_layer_keeps_the_active_one() {
+        let mut layers = stack();
+        layers.add_layer("a");
+        let b = layers.add_layer("b");
+        assert_eq!(layers.remove_layer(1).unwrap().name, "a");
+        assert_eq!(layers.active().id, b);
+
+        // Removing the active layer makes the layer below active.
+        assert_eq!(layers.remove_active().unwrap().id, b);
+        assert_eq!(layers.active_index(), 0);
+    }
+
+    #[test]
+    fn removed_layers_are_inserted_back() {
+        let mut layers = stack();
+        let a = layers.add_layer("a");
+        layers.add_layer("b");
+        let removed = layers.remove_layer(1).unwrap();
+        assert!(layers.insert_layer(1, removed).is_ok());
+        assert_eq!(names(&layers), ["Background", "a", "b"]);
+        assert_eq!(layers.active().id, a);
+
+        // Ids in use and mismatched dimensions are rejected.
+        let copy = layers.active().clone();
+        assert!(layers.insert_layer(0, copy).is_err());
+        let mut other = stack();
+        other.resize(3, 3);
+        let other = other.active().clone();
+        assert!(layers.insert_layer(0, other).is_err());
+        assert_eq!(layers.layers().len(), 3);
+    }
+
+    #[test]
+    fn inserted_ids_are_not_reused() {
+        let mut layers = stack();
+        let a = layers.add_layer("a");
+        let removed = layers.remove_active().unwrap();
+        let mut other = stack();
+        assert!(other.insert_layer(1, removed).is_ok());
+        assert_ne!(other.add_layer("b"), a);
+    }
+
+    #[test]
+    fn select_rejects_out_of_range_indices() {
+        let mut layers = stack();
+        layers.add_layer("a");
+        assert!(layers.select(0));
+        assert!(!layers.select(2));
+        assert_eq!(layers.active_index(), 0);
+    }
+
+    #[test]
+    fn from_layers_validates_the_stack() {
+        let mut layers = stack();
+        layers.add_layer("a");
+        let layers = layers.layers().to_vec();
+        assert!(LayerStack::from_layers(vec![], 0, 2).is_none());
+        assert!(LayerStack::from_layers(layers.clone(), 2, 2).is_none());
+        // Ids must be below the next id.
+        assert!(LayerStack::from_layers(layers.clone(), 0, 1).is_none());
+        let mut duplicate = layers.clone();
+        duplicate[1].id = duplicate[0].id;
+        assert!(LayerStack::from_layers(duplicate, 0, 2).is_none());
+
+        let mut layers = LayerStack::from_layers(layers, 1, 5).unwrap();
+        assert_eq!(layers.active().name, "a");
+        assert_eq!(layers.add_layer("b"), LayerId(5));
+        assert_eq!(layers.next_id(), 6);
+    }
+
+    #[test]
+    fn layers_are_composited_with_their_properties() {
+        let mut layers = stack();
+        layers.add_layer("blue");
+        let blue = ImagePixel::from([0.0, 0.0, 1.0, 1.0]);
+        layers.active_mut().image.put_pixel(0, 0, blue);
+        assert_eq!(layers.composite_pixel(0, 0).0, [0.0, 0.0, 1.0, 1.0]);
+        // Transparent pixels show the layer below.
+        assert_eq!(layers.composite_pixel(1, 0).0, RED);
+
+        layers.active_mut().opacity = 0.5;
+        assert_eq!(layers.composite_pixel(0, 0).0, [0.5, 0.0, 0.5, 1.0]);
+
+        let layer = layers.active_mut();
+        layer.opacity = 1.0;
+        layer.blend_mode = BlendMode::Add;
+        assert_eq!(layers.composite_pixel(0, 0).0, [1.0, 0.0, 1.0, 1.0]);
+
+        layers.active_mut().visible = false;
+        assert_eq!(layers.composite_pixel(0, 0).0, RED);
+        assert_eq!(layers.flatten().get_pixel(0, 0).0, RED);
+    }
+
+    #[test]
+    fn blend_modes_cycle() {
+        let mut mode = BlendMode::Normal;
+        for expected in BlendMode::ALL.iter().cycle().skip(1).take(4) {
+            mode = mode.next();
+            assert_eq!(mode, *expected);
+        }
+    }
+
+    #[test]
+    fn resize_keeps_the_top_left_corner() {
+        let mut layers = stack();
+        layers.resize(3, 1);
+        let image = layers.active_image();
+        assert_eq!(image.dimensions(), (3, 1));
+        assert_eq!(image.get_pixel(1, 0).0, RED);
+        assert_eq!(image.get_pixel(2, 0).0, [0.0; 4]);
+    }
+}
@@ -5,13 +5,29 @@
 //! for communication between the backend application and the GUI and are
 //! designed primarily to keep the GUI and the backend separated for easy future
 //! updates.
+use image::codecs::{jpeg::JpegEncoder, png::PngEncoder, tiff::TiffEncoder};
+use image::{ColorType, DynamicImage, ImageDecoder, ImageEncoder, ImageError, ImageFormat};
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::path::Path;
 
 /// Rexport the image crate
 pub use image;
 
+pub mod color;
+mod composite;
+pub use composite::PaintMode;
 mod gui;
-pub use gui::{DragEvent, Event, GUIContext, GUIOptions, Key, KeyEvent, GUI};
+pub use gui::{
+    DragEvent, Event, GUIContext, GUIOptions, InputSample, Key, KeyEvent, MessageKind, Status, GUI,
+};
+mod layer;
+pub use layer::{BlendMode, Layer, LayerId, LayerStack};
+mod selection;
+pub use selection::{Selection, SelectionOp};
+mod tip;
+pub use tip::{TipParams, TipShape};
 
 /// Image pixel type
 pub type ImagePixel = image::Rgba<f32>;
@@ -20,18 +36,109 @@ pub type ImagePixel = image::Rgba<f32>;
 pub type Image = image::ImageBuffer<ImagePixel, Vec<<ImagePixel as image::Pixel>::Subpixel>>;
 
 /// Load an image file and convert it to the proper format.
-pub fn load_image<P: AsRef<Path>>(path: P) -> Option<Image> {
-    if let Ok(image) = image::io::Reader::open(path) {
-        let image = image
-            .with_guessed_format()
-            .expect("failed to determine image format")
-            .decode()
-            .expect("failed to decode image")
-            .into_rgba32f();
-        Some(image)
-    } else {
-        None
+///
+/// Integer formats are decoded from sRGB into the linear working space,
+/// while float formats are taken as linear already. Returns the image with
+/// the ICC profile embedded in the file, if any, or None if the file can't
+/// be opened.
+pub fn load_image<P: AsRef<Path>>(path: P) -> image::ImageResult<Option<(Image, Option<Vec<u8>>)>> {
+    let Ok(image) = image::io::Reader::open(path) else {
+        return Ok(None);
+    };
+    let mut decoder = image.with_guessed_format()?.into_decoder()?;
+    let icc_profile = decoder.icc_profile().unwrap_or(None);
+    let image = DynamicImage::from_decoder(decoder)?;
+    let linear = matches!(image.color(), ColorType::Rgb32F | ColorType::Rgba32F);
+    let mut image = image.into_rgba32f();
+    if !linear {
+        for pixel in image.pixels_mut() {
+            *pixel = color::pixel_to_linear(pixel);
+        }
     }
+    Ok(Some((image, icc_profile)))
+}
+
+/// Save an image, converting it to a pixel format supported by the file type.
+///
+/// Float formats are written linear, and integer formats are encoded to
+/// sRGB, with dithering for 8-bit formats. The ICC profile is embedded if
+/// the format supports it.
+pub fn save_image<P: AsRef<Path>>(
+    image: &Image,
+    path: P,
+    icc_profile: Option<&[u8]>,
+) -> image::ImageResult<()> {
+    let path = path.as_ref();
+    let format = ImageFormat::from_path(path)?;
+    if format == ImageFormat::OpenExr {
+        return write_image(
+            &DynamicImage::ImageRgba32F(image.clone()),
+            path,
+            format,
+            None,
+        );
+    }
+
+    let srgb = Image::from_fn(image.width(), image.height(), |x, y| {
+        color::pixel_to_srgb(image.get_pixel(x, y))
+    });
+    let dithered = || {
+        image::RgbaImage::from_fn(srgb.width(), srgb.height(), |x, y| {
+            image::Rgba(
+                srgb.get_pixel(x, y)
+                    .0
+                    .map(|value| color::quantize(value, x, y)),
+            )
+        })
+    };
+    let image = match format {
+        ImageFormat::Png | ImageFormat::Tiff => {
+            DynamicImage::ImageRgba16(DynamicImage::ImageRgba32F(srgb).to_rgba16())
+        }
+        ImageFormat::Jpeg => {
+            DynamicImage::ImageRgb8(DynamicImage::ImageRgba8(dithered()).to_rgb8())
+        }
+        _ => DynamicImage::ImageRgba8(dithered()),
+    };
+    write_image(&image, path, format, icc_profile)
+}
+
+/// Write an image, embedding an ICC profile where the format allows it.
+fn write_image(
+    image: &DynamicImage,
+    path: &Path,
+    format: ImageFormat,
+    icc_profile: Option<&[u8]>,
+) -> image::ImageResult<()> {
+    let Some(icc_profile) = icc_profile else {
+        return image.save_with_format(path, format);
+    };
+    let mut writer = BufWriter::new(File::create(path)?);
+    let icc_profile = icc_profile.to_vec();
+    match format {
+        ImageFormat::Png => encode_with_profile(image, PngEncoder::new(&mut writer), icc_profile)?,
+        ImageFormat::Jpeg => {
+            encode_with_profile(image, JpegEncoder::new(&mut writer), icc_profile)?
+        }
+        ImageFormat::Tiff => {
+            encode_with_profile(image, TiffEncoder::new(&mut writer), icc_profile)?
+        }
+        _ => image.write_to(&mut writer, format)?,
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Encode an image with an embedded ICC profile.
+fn encode_with_profile(
+    image: &DynamicImage,
+    mut encoder: impl ImageEncoder,
+    icc_profile: Vec<u8>,
+) -> image::ImageResult<()> {
+    encoder
+        .set_icc_profile(icc_profile)
+        .map_err(ImageError::Unsupported)?;
+    image.write_with_encoder(encoder)
 }
 
 /// Pixel to be used for display.
@@ -43,13 +150,29 @@ pub struct DisplayPixel {
 }
 
 impl DisplayPixel {
-    /// Do a lossy conversion from the image pixel type.
-    fn from_image_pixel(pixel: &ImagePixel) -> DisplayPixel {
+    /// Do a lossy conversion from the linear image pixel type at screen
+    /// coordinates (x, y), compositing it over a background pixel with its
+    /// alpha.
+    ///
+    /// Channels are clamped to 0.0 - 1.0 first, like when exporting to a
+    /// format with alpha, so the screen shows what an export looks like over
+    /// the same background. The result is encoded to sRGB and dithered.
+    fn from_image_pixel(
+        pixel: &ImagePixel,
+        background: &DisplayPixel,
+        x: u32,
+        y: u32,
+    ) -> DisplayPixel {
+        let alpha = pixel.0[3].clamp(0.0, 1.0);
+        let channel = |value: f32, background: u8| {
+            let background = color::srgb_to_linear(background as f32 / u8::MAX as f32);
+            let value = value.clamp(0.0, 1.0) * alpha + background * (1.0 - alpha);
+            color::quantize(color::linear_to_srgb(value), x, y)
+        };
         DisplayPixel {
-            r: (pixel.0[0] * (u8::MAX as f32)) as u8,
-            g: (pixel.0[1] * (u8::MAX as f32)) as u8,
-            b: (pixel.0[2] * (u8::MAX as f32)) as u8,
-            // The alpha channel is ignored
+            r: channel(pixel.0[0], background.r),
+            g: channel(pixel.0[1], background.g),
+            b: channel(pixel.0[2], background.b),
         }
     }
 }
@@ -57,6 +180,28 @@ impl DisplayPixel {
 /// Checkerboard pattern square dimension
 pub const CHECKERBOARD_DIM: u32 = 20;
 
+/// Length in screen pixels of the dashes of the selection outline.
+const MARCHING_ANTS_DASH: u32 = 4;
+
+/// Width of a transform handle in screen pixels.
+const HANDLE_SIZE: f64 = 7.0;
+
+/// Get the dashed black and white selection outline color at screen
+/// coordinates, with the dashes moved along by `phase` screen pixels.
+fn marching_ants(screen_x: u32, screen_y: u32, phase: u32) -> DisplayPixel {
+    let offset = screen_x as u64 + screen_y as u64 + phase as u64;
+    let value = if (offset / MARCHING_ANTS_DASH as u64) % 2 == 1 {
+        255
+    } else {
+        0
+    };
+    DisplayPixel {
+        r: value,
+        g: value,
+        b: value,
+    }
+}
+
 /// Produce a procedural checkerboard, used for empty parts of the screen.
 fn checkerboard(screen_x: u32, screen_y: u32) -> DisplayPixel {
     let x = screen_x / CHECKERBOARD_DIM;
@@ -77,9 +222,66 @@ fn checkerboard(screen_x: u32, screen_y: u32) -> DisplayPixel {
     }
 }
 
+/// Tone-mapping operator, compressing linear values above 1.0 into the
+/// displayable range.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMap {
+    /// Clip values to 1.0
+    Clamp,
+
+    /// Reinhard operator, x / (1 + x)
+    Reinhard,
+
+    /// Filmic curve fitted to ACES
+    Filmic,
+}
+
+impl ToneMap {
+    /// All operators, in cycling order.
+    pub const ALL: [ToneMap; 3] = [ToneMap::Clamp, ToneMap::Reinhard, ToneMap::Filmic];
+
+    /// Next operator in cycling order.
+    pub fn next(self) -> ToneMap {
+        let index = ToneMap::ALL.iter().position(|op| *op == self).unwrap_or(0);
+        ToneMap::ALL[(index + 1) % ToneMap::ALL.len()]
+    }
+
+    /// Name of the operator.
+    pub fn name(self) -> &'static str {
+        match self {
+            ToneMap::Clamp => "clamp",
+            ToneMap::Reinhard => "reinhard",
+            ToneMap::Filmic => "filmic",
+        }
+    }
+
+    /// Look up an operator by name.
+    pub fn from_name(name: &str) -> Option<ToneMap> {
+        ToneMap::ALL.into_iter().find(|op| op.name() == name)
+    }
+
+    /// Map a linear channel value into 0.0 - 1.0.
+    #[inline]
+    fn apply(self, value: f32) -> f32 {
+        let value = value.max(0.0);
+        match self {
+            ToneMap::Clamp => value.min(1.0),
+            ToneMap::Reinhard => value / (1.0 + value),
+            // Krzysztof Narkowicz's fit of the ACES reference rendering.
+            ToneMap::Filmic => ((value * (2.51 * value + 0.03))
+                / (value * (2.43 * value + 0.59) + 0.14))
+                .clamp(0.0, 1.0),
+        }
+    }
+}
+
 /// ImageView handles coordinate-conversion between a front-end screen
 /// buffer and backend image data.
-#[derive(Clone, Debug)]
+///
+/// It also holds the view-only exposure, gamma and tone mapping used to
+/// inspect float images, which never change the image data.
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct ImageView {
     /// X-position of upper left corner of image in view (in screen coords).
     disp_corner_x: f64,
@@ -89,6 +291,23 @@ pub struct ImageView {
 
     /// Conversion factor from display coordinates to image coordinates.
     conversion_factor: f64,
+
+    /// Exposure adjustment in stops.
+    exposure: f32,
+
+    /// Display gamma, applied on top of the sRGB transfer function.
+    gamma: f32,
+
+    /// Tone-mapping operator.
+    tone_map: ToneMap,
+
+    /// Positions of the transform handles to draw, in image coordinates.
+    #[serde(skip)]
+    handles: Vec<(f64, f64)>,
+
+    /// Offset of the selection outline dashes in screen pixels.
+    #[serde(skip)]
+    ants_phase: u32,
 }
 
 impl ImageView {
@@ -97,7 +316,70 @@ impl ImageView {
             disp_corner_x: 0.0,
             disp_corner_y: 0.0,
             conversion_factor: 1.0,
+            exposure: 0.0,
+            gamma: 1.0,
+            tone_map: ToneMap::Clamp,
+            handles: vec![],
+            ants_phase: 0,
+        }
+    }
+
+    /// Set the positions of the transform handles to draw, in image
+    /// coordinates. An empty list hides the handles.
+    pub fn set_handles(&mut self, handles: &[(f64, f64)]) {
+        self.handles = handles.to_vec();
+    }
+
+    /// Exposure adjustment in stops.
+    pub fn exposure(&self) -> f32 {
+        self.exposure
+    }
+
+    /// Set the exposure adjustment in stops.
+    pub fn set_exposure(&mut self, exposure: f32) {
+        self.exposure = exposure;
+    }
+
+    /// Display gamma.
+    pub fn gamma(&self) -> f32 {
+        self.gamma
+    }
+
+    /// Set the display gamma, which must be above 0.
+    pub fn set_gamma(&mut self, gamma: f32) {
+        self.gamma = gamma;
+    }
+
+    /// Move the selection outline dashes along by a number of screen
+    /// pixels. Increasing it between redraws makes the outline march.
+    pub fn set_ants_phase(&mut self, phase: u32) {
+        self.ants_phase = phase % (2 * MARCHING_ANTS_DASH);
+    }
+
+    /// Tone-mapping operator.
+    pub fn tone_map(&self) -> ToneMap {
+        self.tone_map
+    }
+
+    /// Set the tone-mapping operator.
+    pub fn set_tone_map(&mut self, tone_map: ToneMap) {
+        self.tone_map = tone_map;
+    }
+
+    /// Apply the exposure, tone mapping and gamma to a linear pixel for
+    /// display. Alpha is kept.
+    fn display_transform(&self, pixel: &ImagePixel) -> ImagePixel {
+        let scale = self.exposure.exp2();
+        let mut out = *pixel;
+        for channel in out.0.iter_mut().take(3) {
+            let value = self.tone_map.apply(*channel * scale);
+            *channel = if self.gamma == 1.0 {
+                value
+            } else {
+                value.powf(1.0 / self.gamma)
+            };
         }
+        out
     }
 
     /// Zoom into the image by an internal factor.
@@ -114,6 +396,20 @@ impl ImageView {
         self.disp_corner_y /= 1.1;
     }
 
+    /// Zoom level, as the size of an image pixel in screen pixels.
+    pub fn zoom(&self) -> f64 {
+        1.0 / self.conversion_factor
+    }
+
+    /// Set the zoom level, scaling the view around the screen origin like
+    /// `zoom_in()` and `zoom_out()`.
+    pub fn set_zoom(&mut self, zoom: f64) {
+        let scale = zoom * self.conversion_factor;
+        self.conversion_factor = 1.0 / zoom;
+        self.disp_corner_x *= scale;
+        self.disp_corner_y *= scale;
+    }
+
     /// Translate the view by vector (dx, dy) in screen coordinates.
     pub fn translate(&mut self, dx: f64, dy: f64) {
         self.disp_corner_x += dx;
@@ -156,21 +452,100 @@ impl ImageView {
         )
     }
 
-    /// Get a display pixel for the screen coordinates.
-    pub fn get_display_pixel(&self, image: &Image, screen_x: u32, screen_y: u32) -> DisplayPixel {
+    /// Get the screen coordinates for floating-point image coordinates.
+    pub fn get_screen_coords_f(&self, img_x: f64, img_y: f64) -> (f64, f64) {
+        (
+            img_x / self.conversion_factor + self.disp_corner_x,
+            img_y / self.conversion_factor + self.disp_corner_y,
+        )
+    }
+
+    /// Get a display pixel for the screen coordinates on a screen of the
+    /// given dimensions, compositing the visible layers.
+    pub fn get_display_pixel(
+        &self,
+        layers: &LayerStack,
+        screen_x: u32,
+        screen_y: u32,
+        screen_dims: (u32, u32),
+    ) -> DisplayPixel {
+        if let Some(pixel) = self.handle_pixel(screen_x, screen_y) {
+            return pixel;
+        }
+        let image = layers.active_image();
+        if let Some(selection) = layers.selection() {
+            if self.is_selection_outline(image, selection, screen_x, screen_y, screen_dims) {
+                return marching_ants(screen_x, screen_y, self.ants_phase);
+            }
+        }
         if let Some((img_x, img_y)) = self.get_image_coords_u_checked(image, screen_x, screen_y) {
-            DisplayPixel::from_image_pixel(image.get_pixel(img_x, img_y))
+            // Show the checkerboard through transparent pixels.
+            DisplayPixel::from_image_pixel(
+                &self.display_transform(&layers.composite_pixel(img_x, img_y)),
+                &checkerboard(screen_x, screen_y),
+                screen_x,
+                screen_y,
+            )
         } else {
             checkerboard(screen_x, screen_y)
         }
     }
 
-    pub fn update_screen(&self, image: &Image, mut screen: impl ScreenBuffer) {
+    /// Get the color of a transform handle at the screen coordinates, if
+    /// there is one. Handles are white squares with a black border, and keep
+    /// their size at any zoom.
+    fn handle_pixel(&self, screen_x: u32, screen_y: u32) -> Option<DisplayPixel> {
+        let (x, y) = (screen_x as f64 + 0.5, screen_y as f64 + 0.5);
+        let half = HANDLE_SIZE / 2.0;
+        let distance = self
+            .handles
+            .iter()
+            .map(|(img_x, img_y)| {
+                let (handle_x, handle_y) = self.get_screen_coords_f(*img_x, *img_y);
+                (x - handle_x).abs().max((y - handle_y).abs())
+            })
+            .filter(|distance| *distance <= half)
+            .min_by(f64::total_cmp)?;
+        let value = if distance > half - 1.0 { 0 } else { 255 };
+        Some(DisplayPixel {
+            r: value,
+            g: value,
+            b: value,
+        })
+    }
+
+    /// Is the screen pixel on the outline of the selection? The outline is
+    /// one screen pixel wide at any zoom, on the selected side of the edge,
+    /// and follows the screen edges where the selection goes past them.
+    fn is_selection_outline(
+        &self,
+        image: &Image,
+        selection: &Selection,
+        screen_x: u32,
+        screen_y: u32,
+        (screen_width, screen_height): (u32, u32),
+    ) -> bool {
+        let selected = |screen_x: u32, screen_y: u32| {
+            self.get_image_coords_u_checked(image, screen_x, screen_y)
+                .is_some_and(|(x, y)| selection.value(x, y) >= 0.5)
+        };
+        selected(screen_x, screen_y)
+            && (screen_x == 0
+                || screen_y == 0
+                || screen_x + 1 >= screen_width
+                || screen_y + 1 >= screen_height
+                || !selected(screen_x - 1, screen_y)
+                || !selected(screen_x + 1, screen_y)
+                || !selected(screen_x, screen_y - 1)
+                || !selected(screen_x, screen_y + 1))
+    }
+
+    pub fn update_screen(&self, layers: &LayerStack, mut screen: impl ScreenBuffer) {
         let width = screen.width();
         let height = screen.height();
         for x in 0..width {
             for y in 0..height {
-                let pixel = self.get_display_pixel(image, x, y);
+                let pixel = self.get_display_pixel(layers, x, y, (width, height));
                 screen.set(x, y, pixel);
             }
         }
@@ -190,32 +565,215 @@ pub trait ScreenBuffer {
 #[derive(Clone)]
 pub struct Brush {
     name: String,
-    data: Image,
+    tip: Tip,
+}
+
+/// Source of the brush strength.
+#[derive(Clone)]
+enum Tip {
+    /// Brush strength for each pixel of a brush image, from 0.0 - 1.0.
+    Image(image::ImageBuffer<image::Luma<f32>, Vec<f32>>),
+
+    /// Brush strength computed from tip parameters.
+    Procedural(TipParams),
 }
 
 impl Brush {
+    /// Load a brush from an image file, where dark pixels are strong.
     pub fn new<P: AsRef<Path>>(name: &str, path: P) -> image::ImageResult<Brush> {
         let data = image::open(path)?.into_rgba32f();
+        let mask = image::ImageBuffer::from_fn(data.width(), data.height(), |x, y| {
+            image::Luma([Brush::pixel_value(data.get_pixel(x, y)).clamp(0.0, 1.0)])
+        });
         Ok(Brush {
             name: name.to_string(),
-            data,
+            tip: Tip::Image(mask),
         })
     }
 
+    /// Create a brush with a procedural tip.
+    pub fn procedural(name: &str, params: TipParams) -> Brush {
+        Brush {
+            name: name.to_string(),
+            tip: Tip::Procedural(params),
+        }
+    }
+
+    /// Name of the brush.
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    /// Width and height of the brush in pixels at a scale of 1.0.
+    pub fn dimensions(&self) -> (u32, u32) {
+        match &self.tip {
+            Tip::Image(mask) => mask.dimensions(),
+            Tip::Procedural(params) => {
+                let size = params.diameter.ceil() as u32;
+                (size, size)
+            }
+        }
+    }
+
     /// Returns iterator with (dx, dy, value), where dx and dy are distances
     /// from the center of the brush and value is a float from 0.0 - 1.0
     /// indicating the strength of the brush for that pixel.
     #[inline]
     pub fn iter_values(&self) -> impl Iterator<Item = (i32, i32, f32)> + '_ {
-        let half_width = (self.data.width() / 2) as i32;
-        let half_height = (self.data.height() / 2) as i32;
-        self.data.enumerate_pixels().map(move |(x, y, pixel)| {
-            let x = x as i32;
-            let y = y as i32;
-            let dx = x - half_width;
-            let dy = y - half_height;
-            let value = 1.0 - (pixel.0[0] + pixel.0[1] + pixel.0[2]) / 3.0;
-            (dx, dy, value)
+        let (width, height) = self.dimensions();
+        let half_width = (width / 2) as i32;
+        let half_height = (height / 2) as i32;
+        (0..height as i32).flat_map(move |y| {
+            (0..width as i32).map(move |x| {
+                let u = x as f64 + 0.5 - width as f64 / 2.0;
+                let v = y as f64 + 0.5 - height as f64 / 2.0;
+                (x - half_width, y - half_height, self.value(u, v, 1.0))
+            })
         })
     }
+
+    /// Iterate over the pixels covered by a stamp of the brush centered at
+    /// image coordinates (x, y), scaled by a factor and rotated by an angle
+    /// in radians.
+    ///
+    /// Returns (x, y, value) for every covered pixel. The brush is sampled
+    /// at each pixel center (bilinearly for image brushes), so stamps at
+    /// fractional positions, scales and angles come out anti-aliased.
+    pub fn stamp(
+        &self,
+        x: f64,
+        y: f64,
+        scale: f32,
+        angle: f32,
+    ) -> impl Iterator<Item = (i32, i32, f32)> + '_ {
+        let scale = scale.max(f32::EPSILON) as f64;
+        let (sin, cos) = (angle as f64).sin_cos();
+        let (width, height) = self.dimensions();
+        let (width, height) = (width as f64, height as f64);
+        let radius = 0.5 * (width * width + height * height).sqrt() * scale + 1.0;
+        let (min_x, max_x) = ((x - radius).floor() as i32, (x + radius).ceil() as i32);
+        let (min_y, max_y) = ((y - radius).floor() as i32, (y + radius).ceil() as i32);
+        (min_y..=max_y).flat_map(move |img_y| {
+            (min_x..=max_x).filter_map(move |img_x| {
+                // Map the pixel center back into brush coordinates.
+                let dx = img_x as f64 + 0.5 - x;
+                let dy = img_y as f64 + 0.5 - y;
+                let u = (dx * cos + dy * sin) / scale;
+                let v = (dy * cos - dx * sin) / scale;
+                let value = self.value(u, v, scale);
+                (value > 0.0).then_some((img_x, img_y, value))
+            })
+        })
+    }
+
+    /// Brush strength at (u, v) brush pixels from the center of the brush,
+    /// drawn at a scale of `scale` image pixels per brush pixel.
+    fn value(&self, u: f64, v: f64, scale: f64) -> f32 {
+        match &self.tip {
+            Tip::Image(mask) => {
+                let u = u + mask.width() as f64 / 2.0 - 0.5;
+                let v = v + mask.height() as f64 / 2.0 - 0.5;
+                Brush::sample(mask, u, v)
+            }
+            Tip::Procedural(params) => params.value(u, v, scale),
+        }
+    }
+
+    /// Sample a brush mask bilinearly at brush pixel coordinates. The mask
+    /// is zero outside the brush image.
+    fn sample(mask: &image::ImageBuffer<image::Luma<f32>, Vec<f32>>, u: f64, v: f64) -> f32 {
+        let x0 = u.floor();
+        let y0 = v.floor();
+        let fx = (u - x0) as f32;
+        let fy = (v - y0) as f32;
+        let get = |x: f64, y: f64| {
+            if x < 0.0 || y < 0.0 {
+                return 0.0;
+            }
+            mask.get_pixel_checked(x as u32, y as u32)
+                .map_or(0.0, |pixel| pixel.0[0])
+        };
+        let top = get(x0, y0) * (1.0 - fx) + get(x0 + 1.0, y0) * fx;
+        let bottom = get(x0, y0 + 1.0) * (1.0 - fx) + get(x0 + 1.0, y0 + 1.0) * fx;
+        top * (1.0 - fy) + bottom * fy
+    }
+
+    /// Brush strength for a pixel of the brush image (dark is strong).
+    #[inline]
+    fn pixel_value(pixel: &ImagePixel) -> f32 {
+        1.0 - (pixel.0[0] + pixel.0[1] + pixel.0[2]) / 3.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Is the display pixel black or white, as the outline is?
+    fn outline_value(pixel: DisplayPixel) -> Option<u8> {
+        let DisplayPixel { r, g, b } = pixel;
+        (r == g && g == b && (r == 0 || r == 255)).then_some(r)
+    }
+
+    /// A mid gray 8x8 canvas with the rectangle from (2, 2) to (6, 6)
+    /// selected.
+    fn selected_layers() -> LayerStack {
+        let gray = ImagePixel::from([0.5, 0.5, 0.5, 1.0]);
+        let mut layers = LayerStack::new(Image::from_pixel(8, 8, gray));
+        let selection = Selection::rectangle(8, 8, (2.0, 2.0), (6.0, 6.0));
+        layers.set_selection(Some(selection));
+        layers
+    }
+
+    #[test]
+    fn selection_outline_is_drawn_inside_the_edge() {
+        let layers = selected_layers();
+        let view = ImageView::new();
+        let outline = |x, y| outline_value(view.get_display_pixel(&layers, x, y, (8, 8)));
+        for (x, y) in [(2, 2), (5, 2), (2, 4), (5, 5)] {
+            assert!(outline(x, y).is_some(), "({x}, {y})");
+        }
+        for (x, y) in [(1, 2), (3, 3), (4, 4), (6, 5)] {
+            assert!(outline(x, y).is_none(), "({x}, {y})");
+        }
+    }
+
+    #[test]
+    fn selection_outline_follows_the_screen_edges() {
+        let layers = selected_layers();
+        let mut view = ImageView::new();
+        // Show the selected area past every screen edge.
+        view.translate(-3.0, -3.0);
+        let outline = |x, y| outline_value(view.get_display_pixel(&layers, x, y, (2, 2)));
+        for (x, y) in [(0, 0), (1, 0), (0, 1), (1, 1)] {
+            assert!(outline(x, y).is_some(), "({x}, {y})");
+        }
+
+        // The middle of a larger screen is inside the outline.
+        let outline = |x, y| outline_value(view.get_display_pixel(&layers, x, y, (3, 3)));
+        assert!(outline(2, 1).is_some());
+        assert!(outline(1, 2).is_some());
+        assert!(outline(1, 1).is_none());
+    }
+
+    #[test]
+    fn selection_outline_dashes_march() {
+        let layers = selected_layers();
+        let mut view = ImageView::new();
+        let dashes = |view: &ImageView| -> Vec<u8> {
+            (2..6)
+                .map(|x| outline_value(view.get_display_pixel(&layers, x, 2, (8, 8))).unwrap())
+                .collect()
+        };
+        assert_eq!(dashes(&view), [255, 255, 255, 255]);
+        view.set_ants_phase(1);
+        assert_eq!(dashes(&view), [255, 255, 255, 0]);
+        view.set_ants_phase(2);
+        assert_eq!(dashes(&view), [255, 255, 0, 0]);
+        view.set_ants_phase(4);
+        assert_eq!(dashes(&view), [0, 0, 0, 0]);
+        // The phase wraps around after a dash of each color.
+        view.set_ants_phase(2 * MARCHING_ANTS_DASH + 1);
+        assert_eq!(dashes(&view), [255, 255, 255, 0]);
+    }
 }
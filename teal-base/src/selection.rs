@@ -0,0 +1,441 @@
+//! Selections masking where pixels are painted.
+//!
+//! A selection is a float mask over the canvas, from 0.0 (not selected) to
+//! 1.0 (fully selected). Shapes are rasterized with anti-aliased edges and can
+//! be feathered, so painting fades out smoothly at the selection border.
+
+/// How a new selection is combined with the current one.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum SelectionOp {
+    /// Replace the current selection
+    #[default]
+    Replace,
+
+    /// Add to the current selection
+    Add,
+
+    /// Remove from the current selection
+    Subtract,
+
+    /// Keep only the overlap with the current selection
+    Intersect,
+}
+
+impl SelectionOp {
+    /// All operations, in cycling order.
+    pub const ALL: [SelectionOp; 4] = [
+        SelectionOp::Replace,
+        SelectionOp::Add,
+        SelectionOp::Subtract,
+        SelectionOp::Intersect,
+    ];
+
+    /// Next operation in cycling order.
+    pub fn next(self) -> SelectionOp {
+        let index = SelectionOp::ALL
+            .iter()
+            .position(|op| *op == self)
+            .unwrap_or(0);
+        SelectionOp::ALL[(index + 1) % SelectionOp::ALL.len()]
+    }
+
+    /// Name of the operation.
+    pub fn name(self) -> &'static str {
+        match self {
+            SelectionOp::Replace => "replace",
+            SelectionOp::Add => "add",
+            SelectionOp::Subtract => "subtract",
+            SelectionOp::Intersect => "intersect",
+        }
+    }
+
+    /// Look up an operation by name.
+    pub fn from_name(name: &str) -> Option<SelectionOp> {
+        SelectionOp::ALL.into_iter().find(|op| op.name() == name)
+    }
+}
+
+/// Number of sample rows per pixel row when rasterizing a shape.
+const SUBSAMPLES: usize = 4;
+
+/// Selection mask over the canvas.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Selection {
+    width: u32,
+    height: u32,
+
+    /// Selection strength of each pixel in row order, from 0.0 - 1.0.
+    mask: Vec<f32>,
+}
+
+impl Selection {
+    /// Create a selection with no pixels selected.
+    pub fn empty(width: u32, height: u32) -> Selection {
+        Selection {
+            width,
+            height,
+            mask: vec![0.0; width as usize * height as usize],
+        }
+    }
+
+    /// Create a selection with every pixel selected.
+    pub fn all(width: u32, height: u32) -> Selection {
+        Selection {
+            width,
+            height,
+            mask: vec![1.0; width as usize * height as usize],
+        }
+    }
+
+    /// Create a selection from a mask in row order, with values from 0.0 -
+    /// 1.0. Returns None if the mask doesn't match the dimensions.
+    pub fn from_mask(width: u32, height: u32, mask: Vec<f32>) -> Option<Selection> {
+        if mask.len() != width as usize * height as usize {
+            return None;
+        }
+        Some(Selection {
+            width,
+            height,
+            mask,
+        })
+    }
+
+    /// Select the rectangle between two corners in image coordinates.
+    pub fn rectangle(width: u32, height: u32, a: (f64, f64), b: (f64, f64)) -> Selection {
+        Selection::polygon(width, height, &[a, (b.0, a.1), b, (a.0, b.1)])
+    }
+
+    /// Select the ellipse inside the rectangle between two corners in image
+    /// coordinates.
+    pub fn ellipse(width: u32, height: u32, a: (f64, f64), b: (f64, f64)) -> Selection {
+        let center = ((a.0 + b.0) / 2.0, (a.1 + b.1) / 2.0);
+        let radius = ((b.0 - a.0).abs() / 2.0, (b.1 - a.1).abs() / 2.0);
+        // Segments of about a pixel keep the outline round at any size.
+        let perimeter = std::f64::consts::TAU * radius.0.max(radius.1);
+        let segments = perimeter.ceil().clamp(16.0, 4096.0) as usize;
+        let points: Vec<(f64, f64)> = (0..segments)
+            .map(|i| {
+                let angle = std::f64::consts::TAU * i as f64 / segments as f64;
+                (
+                    center.0 + radius.0 * angle.cos(),
+                    center.1 + radius.1 * angle.sin(),
+                )
+            })
+            .collect();
+        Selection::polygon(width, height, &points)
+    }
+
+    /// Select the inside of a closed polygon in image coordinates, using the
+    /// even-odd rule.
+    pub fn polygon(width: u32, height: u32, points: &[(f64, f64)]) -> Selection {
+        let mut selection = Selection::empty(width, height);
+        if points.len() < 3 {
+            return selection;
+        }
+        let min_y = points.iter().map(|p| p.1).fold(f64::INFINITY, f64::min);
+        let max_y = points.iter().map(|p| p.1).fold(f64::NEG_INFINITY, f64::max);
+        let first_row = min_y.floor().max(0.0) as usize;
+        let end_row = (max_y.ceil().max(0.0) as usize).min(height as usize);
+        let row_weight = 1.0 / SUBSAMPLES as f64;
+
+        let mut crossings = vec![];
+        for row in first_row..end_row {
+            let mask_row = &mut selection.mask[row * width as usize..(row + 1) * width as usize];
+            for sub in 0..SUBSAMPLES {
+                let y = row as f64 + (sub as f64 + 0.5) * row_weight;
+                crossings.clear();
+                for (i, p) in points.iter().enumerate() {
+                    let q = points[(i + 1) % points.len()];
+                    if (p.1 <= y) != (q.1 <= y) {
+                        crossings.push(p.0 + (y - p.1) * (q.0 - p.0) / (q.1 - p.1));
+                    }
+                }
+                crossings.sort_by(f64::total_cmp);
+
+                // Add the exact horizontal coverage of each inside span.
+                for span in crossings.chunks_exact(2) {
+                    let start = span[0].clamp(0.0, width as f64);
+                    let end = span[1].clamp(0.0, width as f64);
+                    if end <= start {
+                        continue;
+                    }
+                    let end_col = (end.ceil() as usize).min(width as usize);
+                    for (col, value) in mask_row
+                        .iter_mut()
+                        .enumerate()
+                        .take(end_col)
+                        .skip(start.floor() as usize)
+                    {
+                        let overlap = end.min(col as f64 + 1.0) - start.max(col as f64);
+                        *value += (overlap.max(0.0) * row_weight) as f32;
+                    }
+                }
+            }
+        }
+        for value in selection.mask.iter_mut() {
+            *value = value.min(1.0);
+        }
+        selection
+    }
+
+    /// Width of the selection.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Height of the selection.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Selection strength at (x, y), from 0.0 - 1.0. Pixels outside the
+    /// canvas are not selected.
+    pub fn value(&self, x: u32, y: u32) -> f32 {
+        if x >= self.width || y >= self.height {
+            return 0.0;
+        }
+        self.mask[y as usize * self.width as usize + x as usize]
+    }
+
+    /// Selection strength of each pixel in row order.
+    pub fn mask(&self) -> &[f32] {
+        &self.mask
+    }
+
+    /// Is no pixel selected?
+    pub fn is_empty(&self) -> bool {
+        self.mask.iter().all(|value| *value <= 0.0)
+    }
+
+    /// Bounding box of the selected pixels as (x, y, width, height), or None
+    /// if no pixel is selected.
+    pub fn bounds(&self) -> Option<(u32, u32, u32, u32)> {
+        let width = self.width as usize;
+        let mut bounds: Option<(usize, usize, usize, usize)> = None;
+        for (index, value) in self.mask.iter().enumerate() {
+            if *value <= 0.0 {
+                continue;
+            }
+            let (x, y) = (index % width, index / width);
+            let (min_x, min_y, max_x, max_y) = bounds.get_or_insert((x, y, x, y));
+            *min_x = (*min_x).min(x);
+            *min_y = (*min_y).min(y);
+            *max_x = (*max_x).max(x);
+            *max_y = (*max_y).max(y);
+        }
+        bounds.map(|(min_x, min_y, max_x, max_y)| {
+            (
+                min_x as u32,
+                min_y as u32,
+                (max_x - min_x + 1) as u32,
+                (max_y - min_y + 1) as u32,
+            )
+        })
+    }
+
+    /// Combine another selection of the same dimensions into this one.
+    pub fn combine(&mut self, other: &Selection, op: SelectionOp) {
+        for (value, other) in self.mask.iter_mut().zip(other.mask.iter()) {
+            *value = match op {
+                SelectionOp::Replace => *other,
+                SelectionOp::Add => value.max(*other),
+                SelectionOp::Subtract => *value * (1.0 - other),
+                SelectionOp::Intersect => value.min(*other),
+            };
+        }
+    }
+
+    /// Soften the edges of the selection over about the given radius in
+    /// pixels.
+    ///
+    /// Three box blurs approximate a gaussian blur, in time independent of
+    /// the radius.
+    pub fn feather(&mut self, radius: f32) {
+        let box_radius = (radius / 3.0).ceil() as usize;
+        if box_radius == 0 {
+            return;
+        }
+        let (width, height) = (self.width as usize, self.height as usize);
+        let mut line = vec![];
+        for _ in 0..3 {
+            for y in 0..height {
+                line.clear();
+                line.extend((0..width).map(|x| self.mask[y * width + x]));
+                box_blur(&line, box_radius, |x, value| {
+                    self.mask[y * width + x] = value
+                });
+            }
+            for x in 0..width {
+                line.clear();
+                line.extend((0..height).map(|y| self.mask[y * width + x]));
+                box_blur(&line, box_radius, |y, value| {
+                    self.mask[y * width + x] = value
+                });
+            }
+        }
+    }
+
+    /// Resize the selection, keeping the top left corner in place. Added
+    /// areas are not selected.
+    pub fn resize(&mut self, width: u32, height: u32) {
+        let mut resized = Selection::empty(width, height);
+        for y in 0..height.min(self.height) {
+            for x in 0..width.min(self.width) {
+                resized.mask[(y * width + x) as usize] = self.value(x, y);
+            }
+        }
+        *self = resized;
+    }
+}
+
+/// Box blur a line of values with a radius, passing each result to `out`.
+/// Values past the ends repeat the end values.
+fn box_blur(line: &[f32], radius: usize, mut out: impl FnMut(usize, f32)) {
+    let len = line.len();
+    if len == 0 {
+        return;
+    }
+    let at = |i: isize| line[i.clamp(0, len as isize - 1) as usize];
+    let r = radius as isize;
+    let mut sum: f32 = (-r..=r).map(at).sum();
+    let count = (2 * radius + 1) as f32;
+    for i in 0..len as isize {
+        out(i as usize, (sum / count).clamp(0.0, 1.0));
+        sum += at(i + r + 1) - at(i - r);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Selection drawn as rows, with '*' for fully selected pixels, '+' for
+    /// partly selected ones and '-' for the rest.
+    fn rows(selection: &Selection) -> Vec<String> {
+        selection
+            .mask()
+            .chunks(selection.width() as usize)
+            .map(|row| {
+                row.iter()
+                    .map(|value| match *value {
+                        1.0 => '*',
+                        0.0 => '-',
+                        _ => '+',
+                    })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn rectangles_cover_whole_pixels() {
+        let selection = Selection::rectangle(5, 4, (1.0, 1.0), (4.0, 3.0));
+        assert_eq!(rows(&selection), ["-----", "-***-", "-***-", "-----"]);
+        assert_eq!(selection.bounds(), Some((1, 1, 3, 2)));
+
+        // Corners can be given in any order, and are clipped to the canvas.
+        let flipped = Selection::rectangle(5, 4, (4.0, 3.0), (1.0, 1.0));
+        assert_eq!(flipped, selection);
+        let clipped = Selection::rectangle(3, 2, (-2.0, -2.0), (2.0, 9.0));
+        assert_eq!(rows(&clipped), ["**-", "**-"]);
+    }
+
+    #[test]
+    fn rectangle_edges_are_antialiased() {
+        let selection = Selection::rectangle(3, 1, (0.5, 0.0), (2.0, 1.0));
+        assert_eq!(selection.mask(), [0.5, 1.0, 0.0]);
+    }
+
+    #[test]
+    fn ellipses_fit_their_rectangle() {
+        let selection = Selection::ellipse(9, 9, (0.0, 0.0), (9.0, 9.0));
+        assert_eq!(selection.value(4, 4), 1.0);
+        // The edge pixels in the middle of each side are nearly covered.
+        assert!(selection.value(4, 0) > 0.9);
+        assert!(selection.value(0, 4) > 0.9);
+        // The corners are outside the ellipse.
+        assert_eq!(selection.value(0, 0), 0.0);
+        assert_eq!(selection.value(8, 8), 0.0);
+        assert!(selection.value(1, 1) > 0.0 && selection.value(1, 1) < 1.0);
+        assert_eq!(selection.bounds(), Some((0, 0, 9, 9)));
+    }
+
+    #[test]
+    fn polygons_use_the_even_odd_rule() {
+        let triangle = Selection::polygon(4, 4, &[(0.0, 0.0), (4.0, 0.0), (0.0, 4.0)]);
+        assert_eq!(triangle.value(0, 0), 1.0);
+        assert_eq!(triangle.value(3, 3), 0.0);
+        assert_eq!(triangle.value(3, 0), 0.5);
+
+        // The inner square of a frame traced as one outline is a hole.
+        let frame = [
+            (0.0, 0.0),
+            (5.0, 0.0),
+            (5.0, 5.0),
+            (0.0, 5.0),
+            (0.0, 0.0),
+            (2.0, 2.0),
+            (2.0, 3.0),
+            (3.0, 3.0),
+            (3.0, 2.0),
+            (2.0, 2.0),
+        ];
+        let frame = Selection::polygon(5, 5, &frame);
+        assert_eq!(frame.value(2, 2), 0.0);
+        assert_eq!(frame.value(1, 1), 1.0);
+
+        // Fewer than three points select nothing.
+        assert!(Selection::polygon(4, 4, &[(0.0, 0.0), (4.0, 4.0)]).is_empty());
+    }
+
+    #[test]
+    fn selections_combine() {
+        let left = Selection::rectangle(4, 1, (0.0, 0.0), (2.0, 1.0));
+        let middle = Selection::rectangle(4, 1, (1.0, 0.0), (3.0, 1.0));
+        let cases = [
+            (SelectionOp::Replace, "-**-"),
+            (SelectionOp::Add, "***-"),
+            (SelectionOp::Subtract, "*---"),
+            (SelectionOp::Intersect, "-*--"),
+        ];
+        for (op, expected) in cases {
+            let mut selection = left.clone();
+            selection.combine(&middle, op);
+            assert_eq!(rows(&selection), [expected], "{}", op.name());
+        }
+    }
+
+    #[test]
+    fn partial_selections_combine_by_strength() {
+        let half = Selection::from_mask(2, 1, vec![0.5, 1.0]).unwrap();
+        let quarter = Selection::from_mask(2, 1, vec![0.25, 0.5]).unwrap();
+        let combined = |op| {
+            let mut selection = half.clone();
+            selection.combine(&quarter, op);
+            selection.mask().to_vec()
+        };
+        assert_eq!(combined(SelectionOp::Add), [0.5, 1.0]);
+        assert_eq!(combined(SelectionOp::Subtract), [0.375, 0.5]);
+        assert_eq!(combined(SelectionOp::Intersect), [0.25, 0.5]);
+        assert!(Selection::from_mask(2, 2, vec![1.0]).is_none());
+    }
+
+    #[test]
+    fn feathering_softens_edges() {
+        let mut selection = Selection::rectangle(12, 1, (0.0, 0.0), (6.0, 1.0));
+        selection.feather(3.0);
+        let mask = selection.mask();
+        assert!(mask[4] < 1.0 && mask[7] > 0.0);
+        assert!(mask.windows(2).all(|pair| pair[0] >= pair[1]));
+        assert_eq!(selection.value(0, 0), 1.0);
+        assert!(selection.value(11, 0) < 1e-6);
+    }
+
+    #[test]
+    fn operations_cycle_and_have_names() {
+        for op in SelectionOp::ALL {
+            assert_eq!(SelectionOp::from_name(op.name()), Some(op));
+        }
+        assert_eq!(SelectionOp::Intersect.next(), SelectionOp::Replace);
+    }
+}
@@ -0,0 +1,155 @@
+//! Procedural brush tips.
+use serde::{Deserialize, Serialize};
+
+/// Shape of a procedural brush tip.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TipShape {
+    /// Circle
+    Round,
+
+    /// Square
+    Square,
+
+    /// Ellipse, squashed along its short axis by the roundness
+    Ellipse,
+}
+
+/// Parameters of a procedural brush tip.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct TipParams {
+    /// Shape of the tip.
+    pub shape: TipShape,
+
+    /// Diameter in pixels (the long axis of an ellipse).
+    pub diameter: f32,
+
+    /// Fraction of the radius painted at full strength, from 0.0 - 1.0. The
+    /// rest fades out towards the edge.
+    pub hardness: f32,
+
+    /// Ratio of the short to the long axis of an ellipse, from 0.0 - 1.0.
+    pub roundness: f32,
+
+    /// Rotation of the tip in degrees.
+    pub angle: f32,
+
+    /// Strength of the noise texture, from 0.0 (none) - 1.0.
+    pub noise: f32,
+
+    /// Size of the noise texture features in pixels.
+    pub noise_size: f32,
+
+    /// Seed of the noise texture.
+    pub seed: u32,
+}
+
+impl Default for TipParams {
+    fn default() -> TipParams {
+        TipParams {
+            shape: TipShape::Round,
+            diameter: 10.0,
+            hardness: 1.0,
+            roundness: 1.0,
+            angle: 0.0,
+            noise: 0.0,
+            noise_size: 2.0,
+            seed: 0,
+        }
+    }
+}
+
+impl TipParams {
+    /// Check that the parameters are in range.
+    pub fn validate(&self) -> Result<(), String> {
+        if !(self.diameter.is_finite() && self.diameter > 0.0) {
+            return Err(format!("invalid diameter {}", self.diameter));
+        }
+        if !(0.0..=1.0).contains(&self.hardness) {
+            return Err(format!("hardness {} is not between 0 and 1", self.hardness));
+        }
+        if !(self.roundness > 0.0 && self.roundness <= 1.0) {
+            return Err(format!(
+                "roundness {} is not above 0 and at most 1",
+                self.roundness
+            ));
+        }
+        if !self.angle.is_finite() {
+            return Err(format!("invalid angle {}", self.angle));
+        }
+        if !(0.0..=1.0).contains(&self.noise) {
+            return Err(format!("noise {} is not between 0 and 1", self.noise));
+        }
+        if !(self.noise_size.is_finite() && self.noise_size > 0.0) {
+            return Err(format!("invalid noise size {}", self.noise_size));
+        }
+        Ok(())
+    }
+
+    /// Brush strength at (u, v) pixels from the center of the tip, from
+    /// 0.0 - 1.0. `scale` is the number of image pixels per tip pixel, and
+    /// is used to anti-alias the edge over about one image pixel.
+    pub(crate) fn value(&self, u: f64, v: f64, scale: f64) -> f32 {
+        let radius = self.diameter as f64 / 2.0;
+        let (sin, cos) = (self.angle as f64).to_radians().sin_cos();
+        let x = u * cos + v * sin;
+        let y = v * cos - u * sin;
+        let dist = match self.shape {
+            TipShape::Round => (x * x + y * y).sqrt(),
+            TipShape::Square => x.abs().max(y.abs()),
+            TipShape::Ellipse => {
+                let y = y / self.roundness as f64;
+                (x * x + y * y).sqrt()
+            }
+        } / radius;
+        let edge = ((1.0 - dist) * radius * scale + 0.5).clamp(0.0, 1.0) as f32;
+        if edge <= 0.0 {
+            return 0.0;
+        }
+        let hardness = self.hardness as f64;
+        let falloff = if hardness >= 1.0 {
+            1.0
+        } else {
+            smoothstep(((1.0 - dist) / (1.0 - hardness)).clamp(0.0, 1.0)) as f32
+        };
+        let mut value = edge * falloff;
+        if self.noise > 0.0 {
+            // The texture is in tip coordinates, so it turns with the tip.
+            let size = self.noise_size as f64;
+            let noise = value_noise(x / size, y / size, self.seed);
+            value *= 1.0 - self.noise * (1.0 - noise);
+        }
+        value
+    }
+}
+
+/// Smooth interpolation factor for t in 0.0 - 1.0.
+#[inline]
+fn smoothstep(t: f64) -> f64 {
+    t * t * (3.0 - 2.0 * t)
+}
+
+/// Deterministic value noise from 0.0 - 1.0, with features about one unit in
+/// size.
+fn value_noise(x: f64, y: f64, seed: u32) -> f32 {
+    let x0 = x.floor();
+    let y0 = y.floor();
+    let fx = smoothstep(x - x0) as f32;
+    let fy = smoothstep(y - y0) as f32;
+    let (ix, iy) = (x0 as i64, y0 as i64);
+    let top = lattice(ix, iy, seed) * (1.0 - fx) + lattice(ix + 1, iy, seed) * fx;
+    let bottom = lattice(ix, iy + 1, seed) * (1.0 - fx) + lattice(ix + 1, iy + 1, seed) * fx;
+    top * (1.0 - fy) + bottom * fy
+}
+
+/// Random value from 0.0 - 1.0 for a noise lattice point.
+fn lattice(x: i64, y: i64, seed: u32) -> f32 {
+    let mut hash = (x as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15)
+        ^ (y as u64).wrapping_mul(0xc2b2_ae3d_27d4_eb4f)
+        ^ (seed as u64).wrapping_mul(0x1656_67b1_9e37_79f9);
+    hash ^= hash >> 33;
+    hash = hash.wrapping_mul(0xff51_afd7_ed55_8ccd);
+    hash ^= hash >> 33;
+    (hash >> 40) as f32 / (1u64 << 24) as f32
+}
@@ -2,12 +2,14 @@ use glib::signal;
 use gtk4::cairo;
 use gtk4::prelude::*;
 use gtk4::{
-    Application, ApplicationWindow, DrawingArea, EventControllerKey, GestureClick, GestureDrag,
+    Application, ApplicationWindow, DrawingArea, Entry, EventControllerKey, EventControllerMotion,
+    GestureDrag, Label,
 };
 use std::cell::RefCell;
 use std::process::ExitCode;
-use std::rc::Rc;
-use teal_base::{DragEvent, Event, Key, KeyEvent};
+use std::rc::{Rc, Weak};
+use std::time::Instant;
+use teal_base::{DragEvent, Event, InputSample, Key, KeyEvent, MessageKind, Status};
 
 /// Set up the drawing area.
 ///
@@ -30,7 +32,11 @@ where
         move |_, width, height| {
             let surface = cairo::ImageSurface::create(cairo::Format::Rgb24, width, height).unwrap();
             let _ = ctx.borrow_mut().surface.insert(surface);
-            f(&mut *ctx.borrow_mut(), Event::Resize);
+            let event = Event::Resize {
+                width: width.try_into().unwrap(),
+                height: height.try_into().unwrap(),
+            };
+            f(&mut *ctx.borrow_mut(), event);
         }
     });
 
@@ -47,9 +53,8 @@ where
     // Handle gestures.
     let gesture_drag = create_gesture_drag_handler(Rc::clone(&f), Rc::clone(&ctx));
     drawing_area.add_controller(gesture_drag);
-    let gesture_click =
-        create_gesture_click_handler(Rc::clone(&f), Rc::clone(&ctx), Rc::clone(&drawing_area));
-    drawing_area.add_controller(gesture_click);
+    let motion = create_motion_handler(Rc::clone(&f), Rc::clone(&ctx));
+    drawing_area.add_controller(motion);
 
     // IMPORTANT: hexpand and vexpand are needed to show up in the grid layout
     // later.
@@ -59,6 +64,27 @@ where
     drawing_area
 }
 
+/// Get the input sample for the event currently handled by a gesture.
+///
+/// Tablets report pressure and tilt as axes of the event, the same values
+/// that `GestureStylus` exposes. Other devices get the default sample.
+fn current_sample(gesture: &GestureDrag) -> InputSample {
+    let mut sample = InputSample::default();
+    if let Some(event) = gesture.current_event() {
+        sample.time_ms = event.time();
+        if let Some(pressure) = event.axis(gdk4::AxisUse::Pressure) {
+            sample.pressure = pressure;
+        }
+        if let Some(tilt_x) = event.axis(gdk4::AxisUse::Xtilt) {
+            sample.tilt_x = tilt_x;
+        }
+        if let Some(tilt_y) = event.axis(gdk4::AxisUse::Ytilt) {
+            sample.tilt_y = tilt_y;
+        }
+    }
+    sample
+}
+
 /// Setup controller for gesture dragging.
 fn create_gesture_drag_handler<F>(f: Rc<F>, ctx: Rc<RefCell<Context>>) -> GestureDrag
 where
@@ -69,42 +95,50 @@ where
     gesture_drag.connect_drag_begin({
         let f = Rc::clone(&f);
         let ctx = Rc::clone(&ctx);
-        move |_gesture_drag, x, y| {
-            f(&mut *ctx.borrow_mut(), Event::Drag(DragEvent::Begin(x, y)));
+        move |gesture_drag, x, y| {
+            let sample = current_sample(gesture_drag);
+            f(
+                &mut *ctx.borrow_mut(),
+                Event::Drag(DragEvent::Begin(x, y, sample)),
+            );
         }
     });
     gesture_drag.connect_drag_update({
         let f = Rc::clone(&f);
         let ctx = Rc::clone(&ctx);
-        move |_gesture_drag, x, y| {
-            f(&mut *ctx.borrow_mut(), Event::Drag(DragEvent::Update(x, y)));
+        move |gesture_drag, x, y| {
+            let sample = current_sample(gesture_drag);
+            f(
+                &mut *ctx.borrow_mut(),
+                Event::Drag(DragEvent::Update(x, y, sample)),
+            );
         }
     });
     gesture_drag.connect_drag_end({
         let f = Rc::clone(&f);
         let ctx = Rc::clone(&ctx);
-        move |_gesture_drag, x, y| {
-            f(&mut *ctx.borrow_mut(), Event::Drag(DragEvent::End(x, y)));
+        move |gesture_drag, x, y| {
+            let sample = current_sample(gesture_drag);
+            f(
+                &mut *ctx.borrow_mut(),
+                Event::Drag(DragEvent::End(x, y, sample)),
+            );
         }
     });
 
     gesture_drag
 }
 
-/// Create a gesture click handler
-fn create_gesture_click_handler<F>(
-    _f: Rc<F>,
-    _ctx: Rc<RefCell<Context>>,
-    _drawing_area: Rc<DrawingArea>,
-) -> GestureClick {
-    let gesture_click = GestureClick::new();
-    gesture_click.connect_pressed(|_, i, a, b| {
-        println!("click pressed: {}, {}, {}", i, a, b);
-    });
-    gesture_click.connect_released(|_, i, a, b| {
-        println!("click released: {}, {}, {}", i, a, b);
+/// Create the pointer motion handler, used for the cursor position.
+fn create_motion_handler<F>(f: Rc<F>, ctx: Rc<RefCell<Context>>) -> EventControllerMotion
+where
+    F: Fn(&mut Context, Event) + 'static,
+{
+    let motion = EventControllerMotion::new();
+    motion.connect_motion(move |_, x, y| {
+        f(&mut *ctx.borrow_mut(), Event::Motion(x, y));
     });
-    gesture_click
+    motion
 }
 
 fn parse_key(key: gdk4::Key, modifier: gdk4::ModifierType) -> Option<Key> {
@@ -128,7 +162,10 @@ fn parse_key(key: gdk4::Key, modifier: gdk4::ModifierType) -> Option<Key> {
 }
 
 /// Create the key handler.
-fn create_key_handler<F>(f: Rc<F>, ctx: Rc<RefCell<Context>>) -> EventControllerKey
+///
+/// Typing ':' opens the command line entry. Keys are not sent to the backend
+/// while the entry is open.
+fn create_key_handler<F>(f: Rc<F>, ctx: Rc<RefCell<Context>>, entry: Entry) -> EventControllerKey
 where
     F: Fn(&mut Context, Event) + 'static,
 {
@@ -138,9 +175,23 @@ where
     key_handler.connect_key_pressed({
         let f = Rc::clone(&f);
         let ctx = Rc::clone(&ctx);
+        let entry = entry.clone();
         move |_, key, _, modifier| {
-            if let Some(key) = parse_key(key, modifier) {
-                f(&mut *ctx.borrow_mut(), Event::Key(KeyEvent::Press(key)));
+            if entry.is_visible() {
+                return signal::Propagation::Proceed;
+            }
+            match parse_key(key, modifier) {
+                Some(Key::Sequence {
+                    value: ':',
+                    control: false,
+                    alt: false,
+                }) => {
+                    entry.set_visible(true);
+                    entry.grab_focus();
+                    return signal::Propagation::Stop;
+                }
+                Some(key) => f(&mut *ctx.borrow_mut(), Event::Key(KeyEvent::Press(key))),
+                None => (),
             }
             signal::Propagation::Proceed
         }
@@ -150,6 +201,9 @@ where
         let f = Rc::clone(&f);
         let ctx = Rc::clone(&ctx);
         move |_, key, _, modifier| {
+            if entry.is_visible() {
+                return;
+            }
             if let Some(key) = parse_key(key, modifier) {
                 f(&mut *ctx.borrow_mut(), Event::Key(KeyEvent::Release(key)));
             }
@@ -159,6 +213,119 @@ where
     key_handler
 }
 
+/// Create the command line entry, hidden until ':' is typed.
+///
+/// Enter sends the command to the backend and Escape cancels it.
+fn create_command_entry<F>(f: Rc<F>, ctx: Rc<RefCell<Context>>) -> Entry
+where
+    F: Fn(&mut Context, Event) + 'static,
+{
+    let entry = Entry::new();
+    entry.set_placeholder_text(Some(":"));
+    entry.set_visible(false);
+
+    entry.connect_activate(move |entry| {
+        let line = entry.text().to_string();
+        entry.set_text("");
+        entry.set_visible(false);
+        f(&mut *ctx.borrow_mut(), Event::Command(line));
+    });
+
+    let key_handler = EventControllerKey::new();
+    key_handler.connect_key_pressed({
+        let entry = entry.clone();
+        move |_, key, _, _| {
+            if key == gdk4::Key::Escape {
+                entry.set_text("");
+                entry.set_visible(false);
+                return signal::Propagation::Stop;
+            }
+            signal::Propagation::Proceed
+        }
+    });
+    entry.add_controller(key_handler);
+
+    entry
+}
+
+/// Status bar with the last message and the status fields.
+#[derive(Clone)]
+struct StatusBar {
+    container: gtk4::Box,
+    message: Label,
+    cursor: Label,
+    zoom: Label,
+    brush: Label,
+    color: Label,
+    history: Label,
+}
+
+impl StatusBar {
+    fn new() -> StatusBar {
+        let container = gtk4::Box::new(gtk4::Orientation::Horizontal, 16);
+        let message = Label::new(None);
+        message.set_halign(gtk4::Align::Start);
+        message.set_hexpand(true);
+        container.append(&message);
+        let field = || {
+            let label = Label::new(None);
+            container.append(&label);
+            label
+        };
+        StatusBar {
+            cursor: field(),
+            zoom: field(),
+            brush: field(),
+            color: field(),
+            history: field(),
+            message,
+            container,
+        }
+    }
+
+    /// Show a message, replacing the previous one.
+    fn show_message(&self, kind: MessageKind, text: &str) {
+        self.message.set_text(text);
+        if kind == MessageKind::Error {
+            self.message.add_css_class("error");
+        } else {
+            self.message.remove_css_class("error");
+        }
+    }
+
+    /// Update the status fields.
+    fn set_status(&self, status: &Status) {
+        match status.cursor {
+            Some((x, y)) => self.cursor.set_text(&format!("{x}, {y}")),
+            None => self.cursor.set_text("-"),
+        }
+        self.zoom.set_text(&format!("{:.0}%", status.zoom_percent));
+        if let Some(selection) = status.selection.as_deref() {
+            self.brush.set_text(selection);
+        } else {
+            match status.brush.as_deref() {
+                _ if status.fill => self.brush.set_text("fill"),
+                Some(brush) => {
+                    let tool = if status.eraser { "eraser: " } else { "" };
+                    self.brush
+                        .set_text(&format!("{tool}{brush} {:.0}%", status.brush_size_percent))
+                }
+                None => self.brush.set_text("no brush"),
+            }
+        }
+        let [r, g, b, a] = status
+            .color
+            .map(|channel| (channel.clamp(0.0, 1.0) * 255.0).round() as u8);
+        self.color.set_markup(&format!(
+            "<span foreground=\"#{r:02x}{g:02x}{b:02x}\">\u{25a0}</span> #{r:02x}{g:02x}{b:02x}{a:02x}"
+        ));
+        self.history.set_text(&format!(
+            "undo {} / redo {}",
+            status.undo_count, status.redo_count
+        ));
+    }
+}
+
 /// Create the main color picker.
 fn create_color_picker<F>(f: Rc<F>, ctx: Rc<RefCell<Context>>) -> gtk4::ColorButton
 where
@@ -186,6 +353,30 @@ where
     color_button
 }
 
+/// Convert a texture to 8-bit sRGB RGBA pixels with straight alpha.
+fn texture_pixels(texture: &gdk4::Texture) -> Vec<u8> {
+    let stride = texture.width() as usize * 4;
+    let mut data = vec![0; stride * texture.height() as usize];
+    // Textures are downloaded as premultiplied ARGB in native-endian 32-bit
+    // words, like cairo::Format::ARgb32.
+    texture.download(&mut data, stride);
+    data.chunks_exact(4)
+        .flat_map(|word| {
+            let argb = u32::from_ne_bytes([word[0], word[1], word[2], word[3]]);
+            let alpha = argb >> 24;
+            let channel = |shift: u32| {
+                let value = (argb >> shift) & 0xff;
+                if alpha == 0 {
+                    0
+                } else {
+                    ((value * 255 + alpha / 2) / alpha).min(255) as u8
+                }
+            };
+            [channel(16), channel(8), channel(0), alpha as u8]
+        })
+        .collect()
+}
+
 pub struct GtkGUI;
 
 impl GtkGUI {
@@ -207,29 +398,50 @@ impl teal_base::GUI for GtkGUI {
         let ctx = Rc::new(RefCell::new(Context {
             drawing_area: None,
             surface: None,
+            status_bar: None,
+            emit: None,
+            start: Instant::now(),
         }));
         let f = Rc::new(f);
 
+        // Asynchronous replies, such as clipboard reads, are sent to the
+        // backend like any other event once they finish.
+        let emit: Rc<dyn Fn(Event)> = Rc::new({
+            let f = Rc::clone(&f);
+            let ctx = Rc::downgrade(&ctx);
+            move |event| {
+                if let Some(ctx) = Weak::upgrade(&ctx) {
+                    f(&mut *ctx.borrow_mut(), event);
+                }
+            }
+        });
+        let _ = ctx.borrow_mut().emit.insert(emit);
+
         app.connect_activate(move |app| {
             let grid = gtk4::Grid::new();
             let drawing_area = create_drawing_area(Rc::clone(&f), Rc::clone(&ctx));
             grid.attach(&*drawing_area, 0, 0, 10, 10);
             let color_picker = create_color_picker(Rc::clone(&f), Rc::clone(&ctx));
             color_picker.set_valign(gtk4::Align::Start);
-            let label = gtk4::Label::new(Some("testo"));
-            let label2 = gtk4::Label::new(Some("testo2"));
             let box_layout = gtk4::Box::new(gtk4::Orientation::Vertical, 10);
             box_layout.append(&color_picker);
-            box_layout.append(&label);
-            box_layout.append(&label2);
             grid.attach(&box_layout, 10, 0, 1, 1);
 
+            // Command line and status bar below the image.
+            let command_entry = create_command_entry(Rc::clone(&f), Rc::clone(&ctx));
+            let status_bar = StatusBar::new();
+            let command_layout = gtk4::Box::new(gtk4::Orientation::Vertical, 0);
+            command_layout.append(&command_entry);
+            command_layout.append(&status_bar.container);
+            grid.attach(&command_layout, 0, 10, 11, 1);
+            let _ = ctx.borrow_mut().status_bar.insert(status_bar);
+
             let window = ApplicationWindow::builder()
                 .application(app)
                 .title("Teal")
                 .build();
             let window = Rc::new(window);
-            let key_handler = create_key_handler(Rc::clone(&f), Rc::clone(&ctx));
+            let key_handler = create_key_handler(Rc::clone(&f), Rc::clone(&ctx), command_entry);
             window.add_controller(key_handler);
             window.set_child(Some(&grid));
             window.present();
@@ -245,6 +457,13 @@ impl teal_base::GUI for GtkGUI {
 pub struct Context {
     drawing_area: Option<Rc<DrawingArea>>,
     surface: Option<cairo::ImageSurface>,
+    status_bar: Option<StatusBar>,
+
+    /// Send an event to the backend outside of an event handler.
+    emit: Option<Rc<dyn Fn(Event)>>,
+
+    /// Time the GUI started, for event timestamps.
+    start: Instant,
 }
 
 impl teal_base::GUIContext for &mut Context {
@@ -265,6 +484,43 @@ impl teal_base::GUIContext for &mut Context {
             surface_data: surface.data().unwrap(),
         }
     }
+
+    /// Show the message in the status bar, replacing the previous one.
+    fn show_message(&mut self, kind: MessageKind, text: &str) {
+        self.status_bar.as_ref().unwrap().show_message(kind, text);
+    }
+
+    fn set_status(&mut self, status: &Status) {
+        self.status_bar.as_ref().unwrap().set_status(status);
+    }
+
+    /// Read a texture from the clipboard of the drawing area's display.
+    fn request_clipboard_image(&mut self) {
+        let clipboard = self.drawing_area.as_ref().unwrap().clipboard();
+        let status_bar = self.status_bar.clone().unwrap();
+        let emit = Rc::clone(self.emit.as_ref().unwrap());
+        clipboard.read_texture_async(
+            None::<&gtk4::gio::Cancellable>,
+            move |result| match result {
+                Ok(Some(texture)) => emit(Event::ClipboardImage {
+                    width: texture.width().try_into().unwrap(),
+                    height: texture.height().try_into().unwrap(),
+                    pixels: texture_pixels(&texture),
+                }),
+                Ok(None) => {
+                    status_bar.show_message(MessageKind::Error, "no image on the clipboard")
+                }
+                Err(err) => status_bar.show_message(
+                    MessageKind::Error,
+                    &format!("failed to read the clipboard: {err}"),
+                ),
+            },
+        );
+    }
+
+    fn time_ms(&self) -> u64 {
+        self.start.elapsed().as_millis() as u64
+    }
 }
 
 /// Screen type that can be updated by the backend.
@@ -0,0 +1,327 @@
+//! Headless GUI backend for teal.
+//!
+//! This implements the `teal_base::GUI` traits without a display server. The
+//! backend is driven by a scripted queue of events and renders into an
+//! in-memory screen buffer, which can be inspected after the run for snapshot
+//! assertions.
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::path::Path;
+use std::process::ExitCode;
+use std::rc::Rc;
+use teal_base::image::{ImageResult, RgbImage, RgbaImage};
+use teal_base::{
+    DisplayPixel, DragEvent, Event, GUIContext, GUIOptions, InputSample, Key, KeyEvent,
+    MessageKind, Status, GUI,
+};
+
+/// In-memory screen buffer.
+#[derive(Clone, Debug)]
+pub struct ScreenData {
+    width: u32,
+    height: u32,
+    pixels: Vec<DisplayPixel>,
+}
+
+impl ScreenData {
+    fn new(width: u32, height: u32) -> ScreenData {
+        let black = DisplayPixel { r: 0, g: 0, b: 0 };
+        ScreenData {
+            width,
+            height,
+            pixels: vec![black; (width * height) as usize],
+        }
+    }
+
+    /// Screen width.
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    /// Screen height.
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    /// Get the pixel at screen coordinates (x, y).
+    pub fn get(&self, x: u32, y: u32) -> &DisplayPixel {
+        &self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// Convert the screen contents to an RGB image.
+    pub fn to_image(&self) -> RgbImage {
+        RgbImage::from_fn(self.width, self.height, |x, y| {
+            let pixel = self.get(x, y);
+            teal_base::image::Rgb([pixel.r, pixel.g, pixel.b])
+        })
+    }
+
+    /// Save the screen contents to an image file.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> ImageResult<()> {
+        self.to_image().save(path)
+    }
+}
+
+impl teal_base::ScreenBuffer for &mut ScreenData {
+    #[inline]
+    fn width(&self) -> u32 {
+        self.width
+    }
+
+    #[inline]
+    fn height(&self) -> u32 {
+        self.height
+    }
+
+    #[inline]
+    fn set(&mut self, x: u32, y: u32, pixel: DisplayPixel) {
+        let idx = (y * self.width + x) as usize;
+        self.pixels[idx] = pixel;
+    }
+}
+
+/// Headless GUI driven by a queue of scripted events.
+pub struct HeadlessGUI {
+    /// Events to send in order, with their times in milliseconds.
+    events: VecDeque<(u64, Event)>,
+
+    /// Simulated time of the next queued event, in milliseconds.
+    time_ms: u64,
+
+    /// Screen buffer, shared so it can be inspected after the run.
+    screen: Rc<RefCell<ScreenData>>,
+
+    /// Messages shown by the backend, shared like the screen.
+    messages: Rc<RefCell<Vec<(MessageKind, String)>>>,
+
+    /// Latest status set by the backend, shared like the screen.
+    status: Rc<RefCell<Status>>,
+
+    /// Image on the simulated system clipboard, in sRGB.
+    clipboard: Option<RgbaImage>,
+}
+
+impl HeadlessGUI {
+    /// Create a headless GUI with an initial screen size.
+    pub fn new(width: u32, height: u32) -> HeadlessGUI {
+        HeadlessGUI {
+            events: VecDeque::new(),
+            time_ms: 0,
+            screen: Rc::new(RefCell::new(ScreenData::new(width, height))),
+            messages: Rc::new(RefCell::new(vec![])),
+            status: Rc::new(RefCell::new(Status::default())),
+            clipboard: None,
+        }
+    }
+
+    /// Get a handle to the screen buffer.
+    ///
+    /// The handle stays valid after the GUI has been moved into
+    /// `teal_main::run()`, so it can be used to inspect the final screen.
+    pub fn screen(&self) -> Rc<RefCell<ScreenData>> {
+        Rc::clone(&self.screen)
+    }
+
+    /// Get a handle to the messages shown by the backend, oldest first.
+    pub fn messages(&self) -> Rc<RefCell<Vec<(MessageKind, String)>>> {
+        Rc::clone(&self.messages)
+    }
+
+    /// Get a handle to the latest status set by the backend.
+    pub fn status(&self) -> Rc<RefCell<Status>> {
+        Rc::clone(&self.status)
+    }
+
+    /// Queue a raw event at the current simulated time.
+    pub fn event(&mut self, event: Event) -> &mut HeadlessGUI {
+        self.events.push_back((self.time_ms, event));
+        self
+    }
+
+    /// Queue a raw event at a time in milliseconds, such as a recorded one.
+    /// Later events are queued from this time on.
+    pub fn event_at(&mut self, time_ms: u64, event: Event) -> &mut HeadlessGUI {
+        self.time_ms = time_ms;
+        self.event(event)
+    }
+
+    /// Advance the simulated time for later events.
+    pub fn wait(&mut self, ms: u64) -> &mut HeadlessGUI {
+        self.time_ms += ms;
+        self
+    }
+
+    /// Queue a press and release of a key.
+    pub fn key(&mut self, key: Key) -> &mut HeadlessGUI {
+        self.event(Event::Key(KeyEvent::Press(key.clone())))
+            .event(Event::Key(KeyEvent::Release(key)))
+    }
+
+    /// Queue a press and release of a plain character key.
+    pub fn press(&mut self, value: char) -> &mut HeadlessGUI {
+        self.key(Key::Sequence {
+            value,
+            control: false,
+            alt: false,
+        })
+    }
+
+    /// Queue a drag through a list of screen points.
+    pub fn drag(&mut self, points: &[(f64, f64)]) -> &mut HeadlessGUI {
+        let points: Vec<(f64, f64, f64)> = points.iter().map(|(x, y)| (*x, *y, 1.0)).collect();
+        self.stylus_drag(&points)
+    }
+
+    /// Queue a drag through a list of screen points with pen pressures.
+    ///
+    /// Samples are timestamped 10 ms apart.
+    pub fn stylus_drag(&mut self, points: &[(f64, f64, f64)]) -> &mut HeadlessGUI {
+        let sample = |i: usize, pressure: f64| InputSample {
+            pressure,
+            time_ms: i as u32 * 10,
+            ..InputSample::default()
+        };
+        let Some((start, rest)) = points.split_first() else {
+            return self;
+        };
+        let start_sample = sample(0, start.2);
+        self.event(Event::Drag(DragEvent::Begin(
+            start.0,
+            start.1,
+            start_sample,
+        )));
+        // Drag updates are offsets from the start point.
+        let mut last = (0.0, 0.0, start_sample);
+        for (i, point) in rest.iter().enumerate() {
+            last = (point.0 - start.0, point.1 - start.1, sample(i + 1, point.2));
+            self.event(Event::Drag(DragEvent::Update(last.0, last.1, last.2)));
+        }
+        self.event(Event::Drag(DragEvent::End(last.0, last.1, last.2)))
+    }
+
+    /// Queue a pointer motion to a screen point.
+    pub fn motion(&mut self, x: f64, y: f64) -> &mut HeadlessGUI {
+        self.event(Event::Motion(x, y))
+    }
+
+    /// Queue a color update, in sRGB.
+    pub fn color(&mut self, r: f32, g: f32, b: f32, a: f32) -> &mut HeadlessGUI {
+        self.event(Event::ColorUpdate { r, g, b, a })
+    }
+
+    /// Queue a command line (without the leading ':').
+    pub fn command(&mut self, line: &str) -> &mut HeadlessGUI {
+        self.event(Event::Command(line.to_string()))
+    }
+
+    /// Set the image on the system clipboard, in sRGB. It is sent to the
+    /// backend whenever the backend asks for it.
+    pub fn clipboard_image(&mut self, image: RgbaImage) -> &mut HeadlessGUI {
+        let _ = self.clipboard.insert(image);
+        self
+    }
+
+    /// Queue a screen resize.
+    pub fn resize(&mut self, width: u32, height: u32) -> &mut HeadlessGUI {
+        self.event(Event::Resize { width, height })
+    }
+}
+
+impl GUI for HeadlessGUI {
+    type Context<'a> = HeadlessContext<'a>;
+
+    /// Send all queued events, starting with an initial resize like a real
+    /// window would.
+    fn run<F>(&mut self, _options: GUIOptions, f: F) -> ExitCode
+    where
+        F: Fn(Self::Context<'_>, Event) + 'static,
+    {
+        let (width, height) = {
+            let screen = self.screen.borrow();
+            (screen.width, screen.height)
+        };
+        let start_ms = self
+            .events
+            .front()
+            .map_or(self.time_ms, |(time_ms, _)| *time_ms);
+        self.events
+            .push_front((start_ms, Event::Resize { width, height }));
+
+        while let Some((time_ms, event)) = self.events.pop_front() {
+            let mut screen = self.screen.borrow_mut();
+            if let Event::Resize { width, height } = event {
+                *screen = ScreenData::new(width, height);
+            }
+            let mut messages = self.messages.borrow_mut();
+            let mut status = self.status.borrow_mut();
+            f(
+                HeadlessContext {
+                    screen: &mut screen,
+                    messages: &mut messages,
+                    status: &mut status,
+                    events: &mut self.events,
+                    time_ms,
+                    clipboard: self.clipboard.as_ref(),
+                },
+                event,
+            );
+        }
+
+        ExitCode::SUCCESS
+    }
+}
+
+/// Context passed to the backend for each headless event.
+pub struct HeadlessContext<'a> {
+    screen: &'a mut ScreenData,
+    messages: &'a mut Vec<(MessageKind, String)>,
+    status: &'a mut Status,
+
+    /// Queued events, for sending replies to the backend.
+    events: &'a mut VecDeque<(u64, Event)>,
+
+    /// Time of the current event.
+    time_ms: u64,
+
+    /// Image on the simulated system clipboard.
+    clipboard: Option<&'a RgbaImage>,
+}
+
+impl GUIContext for HeadlessContext<'_> {
+    fn screen(&mut self) -> impl teal_base::ScreenBuffer {
+        &mut *self.screen
+    }
+
+    /// Record the message, and print it since there is no window to show it.
+    fn show_message(&mut self, kind: MessageKind, text: &str) {
+        match kind {
+            MessageKind::Info => println!("{text}"),
+            MessageKind::Error => eprintln!("{text}"),
+        }
+        self.messages.push((kind, text.to_string()));
+    }
+
+    fn set_status(&mut self, status: &Status) {
+        self.status.clone_from(status);
+    }
+
+    /// Send the clipboard image right after the current event, like a
+    /// clipboard read that finishes immediately.
+    fn request_clipboard_image(&mut self) {
+        match self.clipboard {
+            Some(image) => self.events.push_front((
+                self.time_ms,
+                Event::ClipboardImage {
+                    width: image.width(),
+                    height: image.height(),
+                    pixels: image.as_raw().clone(),
+                },
+            )),
+            None => self.show_message(MessageKind::Error, "no image on the clipboard"),
+        }
+    }
+
+    fn time_ms(&self) -> u64 {
+        self.time_ms
+    }
+}
@@ -0,0 +1,229 @@
+//! Headless batch mode.
+//!
+//! A batch script is a plain text file with one command per line. Commands
+//! are applied through the same drag and brush code used by the GUI, but
+//! against an in-memory image with no display. Blank lines and lines starting
+//! with `#` are ignored.
+//!
+//! Supported commands:
+//!
+//! * `open PATH [WIDTHxHEIGHT]` - open an image or project, creating it with
+//!   the given dimensions if it does not exist
+//! * `brush QUICKID|NAME` - select a brush
+//! * `color R G B [A]` or `color #RRGGBB[AA]` - set the current color, in
+//!   sRGB
+//! * `layer [NAME]` - add a new layer above the active one
+//! * `stroke X,Y[,PRESSURE] ...` - paint a stroke through image coordinates,
+//!   with an optional pen pressure from 0 to 1 at each point
+//! * `undo` / `redo` - undo or redo the last operation
+//! * `save [PATH]` - save the image, optionally to a new path
+use crate::cmdline::{parse_color, parse_dims};
+use crate::{Application, Args, Config};
+use std::path::{Path, PathBuf};
+use std::process::ExitCode;
+use teal_base::{DisplayPixel, DragEvent, InputSample, MessageKind, ScreenBuffer};
+use teal_ops::LayerOp;
+
+/// Screen buffer with no pixels, used when there is no display.
+struct NullScreen;
+
+impl ScreenBuffer for NullScreen {
+    fn width(&self) -> u32 {
+        0
+    }
+
+    fn height(&self) -> u32 {
+        0
+    }
+
+    fn set(&mut self, _x: u32, _y: u32, _pixel: DisplayPixel) {}
+}
+
+/// Batch script state.
+struct Batch {
+    config: Config,
+    app: Option<Application>,
+}
+
+impl Batch {
+    /// Get the open application, or fail if no image was opened.
+    fn app(&mut self) -> Result<&mut Application, String> {
+        self.app
+            .as_mut()
+            .ok_or_else(|| "no image open; use 'open PATH' first".to_string())
+    }
+
+    /// Run a single script command.
+    fn run_command(&mut self, cmd: &str, args: &[&str]) -> Result<(), String> {
+        match (cmd, args) {
+            ("open", [path]) | ("open", [path, _]) => {
+                let dims = match args.get(1) {
+                    Some(dims) => Some(parse_dims(dims)?),
+                    None => None,
+                };
+                let args = Args {
+                    fname: path.to_string(),
+                    dims,
+                    record: None,
+                };
+                let _ = self
+                    .app
+                    .insert(Application::try_new(args, self.config.clone())?);
+            }
+            ("brush", [brush]) => {
+                let quickid = self
+                    .config
+                    .brushes
+                    .iter()
+                    .find(|opt| opt.name == *brush || opt.quickid.to_string() == *brush)
+                    .map(|opt| opt.quickid)
+                    .ok_or_else(|| format!("no brush named '{brush}'"))?;
+                let _ = self.app()?.selected_brush.insert(quickid);
+            }
+            ("color", _) => {
+                let color = parse_color(args)?;
+                let _ = self.app()?.color.insert(color);
+            }
+            ("layer", _) => {
+                let app = self.app()?;
+                let name = if args.is_empty() {
+                    format!("Layer {}", app.layers.layers().len())
+                } else {
+                    args.join(" ")
+                };
+                let op = LayerOp::add(&mut app.layers, &name);
+                app.history.push(Box::new(op));
+            }
+            ("stroke", _) => {
+                let points = args
+                    .iter()
+                    .map(|point| parse_point(point))
+                    .collect::<Result<Vec<_>, _>>()?;
+                self.stroke(&points)?;
+            }
+            ("undo", []) => {
+                let app = self.app()?;
+                if !app.history.undo(&mut app.layers) {
+                    return Err("no more operations to undo".to_string());
+                }
+            }
+            ("redo", []) => {
+                let app = self.app()?;
+                if !app.history.redo(&mut app.layers) {
+                    return Err("no more operations to redo".to_string());
+                }
+            }
+            ("save", []) => self.app()?.save()?,
+            ("save", [path]) => {
+                let app = self.app()?;
+                app.image_path = PathBuf::from(path);
+                app.save()?;
+            }
+            _ => {
+                return Err(format!(
+                    "invalid command '{cmd}' with {} argument(s)",
+                    args.len()
+                ))
+            }
+        }
+        Ok(())
+    }
+
+    /// Print messages queued by the application.
+    fn print_messages(&mut self) {
+        let Some(app) = self.app.as_mut() else {
+            return;
+        };
+        for (kind, text) in app.messages.drain(..) {
+            match kind {
+                MessageKind::Info => println!("{text}"),
+                MessageKind::Error => eprintln!("{text}"),
+            }
+        }
+    }
+
+    /// Paint a stroke through a list of image coordinates and samples.
+    fn stroke(&mut self, points: &[(f64, f64, InputSample)]) -> Result<(), String> {
+        let app = self.app()?;
+        let Some((first, rest)) = points.split_first() else {
+            return Err("stroke needs at least one point".to_string());
+        };
+        if app.selected_brush.is_none() {
+            return Err("no brush selected; use 'brush QUICKID' first".to_string());
+        }
+
+        // Drag events are in screen coordinates, with updates given as
+        // offsets from the first point.
+        let (start_x, start_y) = app.image_view.get_screen_coords_f(first.0, first.1);
+        let offset = |point: &(f64, f64, InputSample)| {
+            let (x, y) = app.image_view.get_screen_coords_f(point.0, point.1);
+            (x - start_x, y - start_y, point.2)
+        };
+        let offsets: Vec<(f64, f64, InputSample)> = rest.iter().map(offset).collect();
+        let (end_x, end_y, end_sample) = offsets.last().copied().unwrap_or((0.0, 0.0, first.2));
+
+        app.handle_drag_event(DragEvent::Begin(start_x, start_y, first.2), NullScreen);
+        for (x, y, sample) in offsets {
+            app.handle_drag_event(DragEvent::Update(x, y, sample), NullScreen);
+        }
+        app.handle_drag_event(DragEvent::End(end_x, end_y, end_sample), NullScreen);
+        Ok(())
+    }
+}
+
+/// Parse a point in the format X,Y[,PRESSURE].
+fn parse_point(s: &str) -> Result<(f64, f64, InputSample), String> {
+    let (x, rest) = s
+        .split_once(',')
+        .ok_or_else(|| format!("missing ',' in point '{s}'"))?;
+    let (y, pressure) = match rest.split_once(',') {
+        Some((y, pressure)) => (y, Some(pressure)),
+        None => (rest, None),
+    };
+    let x = x
+        .parse()
+        .map_err(|_| format!("invalid x coordinate in '{s}'"))?;
+    let y = y
+        .parse()
+        .map_err(|_| format!("invalid y coordinate in '{s}'"))?;
+    let mut sample = InputSample::default();
+    if let Some(pressure) = pressure {
+        sample.pressure = pressure
+            .parse()
+            .ok()
+            .filter(|pressure| (0.0..=1.0).contains(pressure))
+            .ok_or_else(|| format!("invalid pressure in '{s}'"))?;
+    }
+    Ok((x, y, sample))
+}
+
+/// Run a batch script.
+pub fn run_batch<P: AsRef<Path>>(script_path: P, config: Config) -> ExitCode {
+    let script_path = script_path.as_ref();
+    let script = match std::fs::read_to_string(script_path) {
+        Ok(script) => script,
+        Err(err) => {
+            eprintln!(
+                "failed to read batch script {}: {err}",
+                script_path.display()
+            );
+            return ExitCode::FAILURE;
+        }
+    };
+
+    let mut batch = Batch { config, app: None };
+    for (i, line) in script.lines().enumerate() {
+        let mut words = line.split_whitespace();
+        let Some(cmd) = words.next().filter(|cmd| !cmd.starts_with('#')) else {
+            continue;
+        };
+        let args: Vec<&str> = words.collect();
+        let result = batch.run_command(cmd, &args);
+        batch.print_messages();
+        if let Err(err) = result {
+            eprintln!("{}:{}: {err}", script_path.display(), i + 1);
+            return ExitCode::FAILURE;
+        }
+    }
+    ExitCode::SUCCESS
+}
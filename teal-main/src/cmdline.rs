@@ -0,0 +1,230 @@
+//! Ex-style command line.
+//!
+//! Commands are typed after ':' in the GUI and sent to the backend as
+//! `Event::Command`. Every key binding command name, such as `undo` or
+//! `new_layer`, can also be run directly.
+//!
+//! Supported commands:
+//!
+//! * `w [PATH]` - save the image, optionally to a new path
+//! * `resize WIDTHxHEIGHT` - resize the canvas, keeping the top left corner
+//! * `brush QUICKID|NAME` - select a brush
+//! * `color R G B [A]` or `color #RRGGBB[AA]` - set the current color, in
+//!   sRGB
+//! * `zoom PERCENT[%]` - set the zoom level
+//! * `size PERCENT[%]` - set the brush size, relative to the brush image
+//! * `exposure STOPS` - set the view exposure
+//! * `gamma GAMMA` - set the view gamma
+//! * `tonemap clamp|reinhard|filmic` - set the view tone-mapping operator
+//! * `mode NAME` - set the paint mode of the brush, such as `normal`,
+//!   `multiply` or `behind`
+//! * `select rectangle|ellipse|lasso|polygon|wand|off` - choose the selection
+//!   tool
+//! * `selection replace|add|subtract|intersect` - set how new selections
+//!   combine with the current one
+//! * `feather RADIUS` - soften the edges of the selection, in pixels
+//! * `rotate DEGREES` - rotate the transformed pixels clockwise, starting a
+//!   transform if there is none
+//! * `scale PERCENT[%] [PERCENT[%]]` - scale the transformed pixels, by
+//!   separate horizontal and vertical amounts if two are given
+//! * `resample nearest|bilinear|bicubic` - set how transformed pixels are
+//!   resampled
+use crate::command::{self, Command};
+use crate::select::SelectionTool;
+use std::path::PathBuf;
+use teal_base::{color, ImagePixel, PaintMode, SelectionOp, ToneMap};
+use teal_ops::Resampling;
+
+/// Largest size of a resized layer in bytes, 16384x16384 float RGBA pixels.
+const MAX_LAYER_BYTES: u64 = 1 << 32;
+
+/// Command entered on the command line.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ExCommand {
+    Write(Option<PathBuf>),
+    Resize {
+        width: u32,
+        height: u32,
+    },
+    Brush(String),
+    Color(ImagePixel),
+    Zoom(f64),
+    BrushSize(f64),
+    Exposure(f32),
+    Gamma(f32),
+    ToneMap(ToneMap),
+    PaintMode(PaintMode),
+    SelectionTool(Option<SelectionTool>),
+    SelectionOp(SelectionOp),
+    Feather(f32),
+    Rotate(f64),
+    Scale(f64, f64),
+    Resample(Resampling),
+
+    /// A key binding command, run by name.
+    Key(Command),
+}
+
+/// Parse a command line.
+pub fn parse(line: &str) -> Result<ExCommand, String> {
+    let mut words = line.split_whitespace();
+    let Some(cmd) = words.next() else {
+        return Err("empty command".to_string());
+    };
+    let args: Vec<&str> = words.collect();
+    match (cmd, &args[..]) {
+        ("w", []) => Ok(ExCommand::Write(None)),
+        ("w", [path]) => Ok(ExCommand::Write(Some(PathBuf::from(path)))),
+        ("resize", [dims]) => {
+            let (width, height) = parse_dims(dims)?;
+            if width == 0 || height == 0 {
+                return Err(format!("invalid dimensions '{dims}'"));
+            }
+            let bytes = (width as u64)
+                .checked_mul(height as u64)
+                .and_then(|pixels| pixels.checked_mul(16));
+            if bytes.filter(|bytes| *bytes <= MAX_LAYER_BYTES).is_none() {
+                return Err(format!(
+                    "dimensions '{dims}' are too large, layers are limited to {} MiB",
+                    MAX_LAYER_BYTES >> 20
+                ));
+            }
+            Ok(ExCommand::Resize { width, height })
+        }
+        ("brush", [brush]) => Ok(ExCommand::Brush(brush.to_string())),
+        ("color", _) => Ok(ExCommand::Color(parse_color(&args)?)),
+        ("zoom", [zoom]) => Ok(ExCommand::Zoom(parse_percent(zoom)?)),
+        ("size", [size]) => Ok(ExCommand::BrushSize(parse_percent(size)?)),
+        ("exposure", [stops]) => stops
+            .parse()
+            .ok()
+            .filter(|stops: &f32| stops.is_finite())
+            .map(ExCommand::Exposure)
+            .ok_or_else(|| format!("invalid exposure '{stops}'")),
+        ("gamma", [gamma]) => gamma
+            .parse()
+            .ok()
+            .filter(|gamma: &f32| gamma.is_finite() && *gamma > 0.0)
+            .map(ExCommand::Gamma)
+            .ok_or_else(|| format!("invalid gamma '{gamma}'")),
+        ("tonemap", [name]) => ToneMap::from_name(name)
+            .map(ExCommand::ToneMap)
+            .ok_or_else(|| format!("unknown tone-mapping operator '{name}'")),
+        ("mode", [name]) => PaintMode::from_name(name)
+            .map(ExCommand::PaintMode)
+            .ok_or_else(|| format!("unknown paint mode '{name}'")),
+        ("select", ["off"]) => Ok(ExCommand::SelectionTool(None)),
+        ("select", [name]) => SelectionTool::from_name(name)
+            .map(|tool| ExCommand::SelectionTool(Some(tool)))
+            .ok_or_else(|| format!("unknown selection tool '{name}'")),
+        ("selection", [name]) => SelectionOp::from_name(name)
+            .map(ExCommand::SelectionOp)
+            .ok_or_else(|| format!("unknown selection operation '{name}'")),
+        ("feather", [radius]) => radius
+            .parse()
+            .ok()
+            .filter(|radius: &f32| radius.is_finite() && *radius >= 0.0)
+            .map(ExCommand::Feather)
+            .ok_or_else(|| format!("invalid feather radius '{radius}'")),
+        ("rotate", [degrees]) => degrees
+            .parse()
+            .ok()
+            .filter(|degrees: &f64| degrees.is_finite())
+            .map(ExCommand::Rotate)
+            .ok_or_else(|| format!("invalid angle '{degrees}'")),
+        ("scale", [scale]) => {
+            let scale = parse_percent(scale)?;
+            Ok(ExCommand::Scale(scale, scale))
+        }
+        ("scale", [x, y]) => Ok(ExCommand::Scale(parse_percent(x)?, parse_percent(y)?)),
+        ("resample", [name]) => Resampling::from_name(name)
+            .map(ExCommand::Resample)
+            .ok_or_else(|| format!("unknown resampling method '{name}'")),
+        (name, []) => command::command_by_name(name)
+            .map(ExCommand::Key)
+            .ok_or_else(|| format!("unknown command '{name}'")),
+        _ => Err(format!(
+            "invalid command '{cmd}' with {} argument(s)",
+            args.len()
+        )),
+    }
+}
+
+/// Parse dimensions in the format WIDTHxHEIGHT.
+pub(crate) fn parse_dims(s: &str) -> Result<(u32, u32), String> {
+    let (width, height) = s
+        .split_once('x')
+        .ok_or_else(|| format!("missing 'x' in dimensions '{s}'"))?;
+    let width = width
+        .parse()
+        .map_err(|_| format!("invalid width in '{s}'"))?;
+    let height = height
+        .parse()
+        .map_err(|_| format!("invalid height in '{s}'"))?;
+    Ok((width, height))
+}
+
+/// Parse an sRGB color, either as 3 or 4 float channels or as a hex string,
+/// into the linear working space.
+pub(crate) fn parse_color(args: &[&str]) -> Result<ImagePixel, String> {
+    match args {
+        [hex] => {
+            let digits = hex
+                .strip_prefix('#')
+                .ok_or_else(|| format!("invalid color '{hex}'"))?;
+            if !(digits.len() == 6 || digits.len() == 8) || !digits.is_ascii() {
+                return Err(format!("invalid color '{hex}'"));
+            }
+            let mut channels = [1.0; 4];
+            for (i, channel) in channels.iter_mut().enumerate().take(digits.len() / 2) {
+                let value = u8::from_str_radix(&digits[i * 2..i * 2 + 2], 16)
+                    .map_err(|_| format!("invalid color '{hex}'"))?;
+                *channel = value as f32 / u8::MAX as f32;
+            }
+            Ok(color::pixel_to_linear(&ImagePixel::from(channels)))
+        }
+        [_, _, _] | [_, _, _, _] => {
+            let mut channels = [1.0; 4];
+            for (channel, arg) in channels.iter_mut().zip(args) {
+                *channel = arg
+                    .parse()
+                    .map_err(|_| format!("invalid color channel '{arg}'"))?;
+            }
+            Ok(color::pixel_to_linear(&ImagePixel::from(channels)))
+        }
+        _ => Err("expected 'color R G B [A]' or 'color #RRGGBB[AA]'".to_string()),
+    }
+}
+
+/// Parse a percentage, such as 200%, into a factor.
+fn parse_percent(s: &str) -> Result<f64, String> {
+    let percent: f64 = s
+        .strip_suffix('%')
+        .unwrap_or(s)
+        .parse()
+        .map_err(|_| format!("invalid percentage '{s}'"))?;
+    if !(percent.is_finite() && percent > 0.0) {
+        return Err(format!("invalid percentage '{s}'"));
+    }
+    Ok(percent / 100.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resize_sizes_are_limited() {
+        assert_eq!(
+            parse("resize 16384x16384"),
+            Ok(ExCommand::Resize {
+                width: 16384,
+                height: 16384
+            })
+        );
+        for dims in ["0x10", "10x0", "16385x16384", "4294967295x4294967295"] {
+            assert!(parse(&format!("resize {dims}")).is_err(), "{dims}");
+        }
+        assert!(parse("resize 10").is_err());
+    }
+}
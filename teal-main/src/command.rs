@@ -1,20 +1,864 @@
 //! Module for handling key input state and commands.
+//!
+//! Key presses are matched against a table of bindings. A binding is a
+//! sequence of chords, such as `u` or `b` followed by a brush quickid, and
+//! may be preceded by a numeric count (`5u` undoes five operations), which is
+//! capped at `MAX_COUNT`. A
+//! partially entered sequence is dropped if the next key arrives after the
+//! timeout. Bindings can be changed in the `[keybindings]` config table.
+use std::collections::{BTreeMap, HashSet};
+use std::fmt;
+use std::time::Duration;
 use teal_base::Key;
 
-pub struct CommandState;
+/// Time after which a pending key sequence is discarded.
+const SEQUENCE_TIMEOUT: Duration = Duration::from_millis(1500);
+
+/// Largest count prefix. Larger counts are clamped to it.
+const MAX_COUNT: usize = 999;
+
+/// A single key chord: a character with modifiers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Chord {
+    pub value: char,
+    pub control: bool,
+    pub alt: bool,
+}
+
+impl Chord {
+    /// Convert a key to a chord, if it is a character key.
+    fn from_key(key: &Key) -> Option<Chord> {
+        if let Key::Sequence {
+            value,
+            control,
+            alt,
+        } = *key
+        {
+            Some(Chord {
+                value,
+                control,
+                alt,
+            })
+        } else {
+            None
+        }
+    }
+
+    /// Check if this is a plain digit (with no modifiers).
+    fn digit(&self) -> Option<usize> {
+        if self.control || self.alt {
+            return None;
+        }
+        self.value.to_digit(10).map(|digit| digit as usize)
+    }
+}
+
+/// Pattern matching one chord of a binding.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ChordPattern {
+    /// Match exactly this chord.
+    Exact(Chord),
+
+    /// Match any character with these modifiers; the character is passed as
+    /// the argument of the command.
+    Any { control: bool, alt: bool },
+}
+
+impl ChordPattern {
+    fn matches(&self, chord: &Chord) -> bool {
+        match self {
+            ChordPattern::Exact(exact) => exact == chord,
+            ChordPattern::Any { control, alt } => *control == chord.control && *alt == chord.alt,
+        }
+    }
+
+    /// Check if there is a chord matched by both patterns.
+    fn overlaps(&self, other: &ChordPattern) -> bool {
+        match (self, other) {
+            (ChordPattern::Exact(chord), pattern) | (pattern, ChordPattern::Exact(chord)) => {
+                pattern.matches(chord)
+            }
+            (
+                ChordPattern::Any { control, alt },
+                ChordPattern::Any {
+                    control: other_control,
+                    alt: other_alt,
+                },
+            ) => control == other_control && alt == other_alt,
+        }
+    }
+
+    /// Parse a chord, such as `u`, `C-z`, `A-S-x` or `A-*`.
+    ///
+    /// `C-`, `A-` and `S-` are the control, alt and shift modifiers. Shift is
+    /// applied to the key itself, so `S-d` is the same chord as `D`. `*`
+    /// matches any key, and `space`, `enter` and `escape` name those keys.
+    fn parse(s: &str) -> Result<ChordPattern, String> {
+        let mut control = false;
+        let mut alt = false;
+        let mut shift = false;
+        let mut rest = s;
+        while rest.chars().count() > 2 {
+            let modifier = match rest.get(..2) {
+                Some("C-") => &mut control,
+                Some("A-") => &mut alt,
+                Some("S-") => &mut shift,
+                _ => break,
+            };
+            *modifier = true;
+            rest = &rest[2..];
+        }
+
+        let value = match rest {
+            "*" if shift => return Err("shift can't be used with '*'".to_string()),
+            "*" => return Ok(ChordPattern::Any { control, alt }),
+            "space" => ' ',
+            "enter" => '\r',
+            "escape" => '\u{1b}',
+            _ => {
+                let mut chars = rest.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(value), None) => value,
+                    _ => return Err(format!("invalid key '{rest}'")),
+                }
+            }
+        };
+        let value = if shift {
+            let mut upper = value.to_uppercase();
+            match (upper.next(), upper.next()) {
+                (Some(upper), None) if upper != value => upper,
+                _ => return Err(format!("shift can't be used with '{value}'")),
+            }
+        } else {
+            value
+        };
+        Ok(ChordPattern::Exact(Chord {
+            value,
+            control,
+            alt,
+        }))
+    }
+}
+
+impl fmt::Display for ChordPattern {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let (control, alt) = match self {
+            ChordPattern::Exact(chord) => (chord.control, chord.alt),
+            ChordPattern::Any { control, alt } => (*control, *alt),
+        };
+        if control {
+            write!(f, "C-")?;
+        }
+        if alt {
+            write!(f, "A-")?;
+        }
+        match self {
+            ChordPattern::Exact(chord) if chord.value == ' ' => write!(f, "space"),
+            ChordPattern::Exact(chord) if chord.value == '\r' => write!(f, "enter"),
+            ChordPattern::Exact(chord) if chord.value == '\u{1b}' => write!(f, "escape"),
+            ChordPattern::Exact(chord) => write!(f, "{}", chord.value),
+            ChordPattern::Any { .. } => write!(f, "*"),
+        }
+    }
+}
+
+/// Name of a bindable action.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Action {
+    Undo,
+    Redo,
+    Save,
+    ZoomIn,
+    ZoomOut,
+    ChooseBrush,
+    NewLayer,
+    DeleteLayer,
+    SelectLayerBelow,
+    SelectLayerAbove,
+    ToggleLayerVisibility,
+    ToggleLayerLock,
+    CycleLayerBlendMode,
+    DecreaseLayerOpacity,
+    IncreaseLayerOpacity,
+    DecreaseBrushSize,
+    IncreaseBrushSize,
+    ToggleSmoothing,
+    ToggleEraser,
+    IncreaseExposure,
+    DecreaseExposure,
+    ResetExposure,
+    CycleToneMap,
+    CyclePaintMode,
+    ToggleFill,
+    CycleSelectionTool,
+    CycleSelectionOp,
+    SelectAll,
+    Deselect,
+    Copy,
+    CopyMerged,
+    Cut,
+    Paste,
+    PasteClipboard,
+    Commit,
+    Cancel,
+    Transform,
+    FlipHorizontal,
+    FlipVertical,
+    CycleResampling,
+}
+
+/// Command names used in the config, for each action.
+const ACTION_NAMES: &[(&str, Action)] = &[
+    ("undo", Action::Undo),
+    ("redo", Action::Redo),
+    ("save", Action::Save),
+    ("zoom_in", Action::ZoomIn),
+    ("zoom_out", Action::ZoomOut),
+    ("choose_brush", Action::ChooseBrush),
+    ("new_layer", Action::NewLayer),
+    ("delete_layer", Action::DeleteLayer),
+    ("select_layer_below", Action::SelectLayerBelow),
+    ("select_layer_above", Action::SelectLayerAbove),
+    ("toggle_layer_visibility", Action::ToggleLayerVisibility),
+    ("toggle_layer_lock", Action::ToggleLayerLock),
+    ("cycle_layer_blend_mode", Action::CycleLayerBlendMode),
+    ("decrease_layer_opacity", Action::DecreaseLayerOpacity),
+    ("increase_layer_opacity", Action::IncreaseLayerOpacity),
+    ("decrease_brush_size", Action::DecreaseBrushSize),
+    ("increase_brush_size", Action::IncreaseBrushSize),
+    ("toggle_smoothing", Action::ToggleSmoothing),
+    ("toggle_eraser", Action::ToggleEraser),
+    ("increase_exposure", Action::IncreaseExposure),
+    ("decrease_exposure", Action::DecreaseExposure),
+    ("reset_exposure", Action::ResetExposure),
+    ("cycle_tone_map", Action::CycleToneMap),
+    ("cycle_paint_mode", Action::CyclePaintMode),
+    ("toggle_fill", Action::ToggleFill),
+    ("cycle_selection_tool", Action::CycleSelectionTool),
+    ("cycle_selection_op", Action::CycleSelectionOp),
+    ("select_all", Action::SelectAll),
+    ("deselect", Action::Deselect),
+    ("copy", Action::Copy),
+    ("copy_merged", Action::CopyMerged),
+    ("cut", Action::Cut),
+    ("paste", Action::Paste),
+    ("paste_clipboard", Action::PasteClipboard),
+    ("commit", Action::Commit),
+    ("cancel", Action::Cancel),
+    ("transform", Action::Transform),
+    ("flip_horizontal", Action::FlipHorizontal),
+    ("flip_vertical", Action::FlipVertical),
+    ("cycle_resampling", Action::CycleResampling),
+];
+
+/// Command name used in the config to remove a default binding.
+const UNBOUND: &str = "none";
+
+impl Action {
+    /// Look up an action by its command name.
+    fn from_name(name: &str) -> Option<Action> {
+        ACTION_NAMES
+            .iter()
+            .find(|(action_name, _)| *action_name == name)
+            .map(|(_, action)| *action)
+    }
+
+    /// Command name of this action.
+    fn name(&self) -> &'static str {
+        ACTION_NAMES
+            .iter()
+            .find(|(_, action)| action == self)
+            .map(|(name, _)| *name)
+            .expect("missing action name")
+    }
+
+    /// Does this action take the key matched by a `*` as its argument?
+    fn takes_argument(&self) -> bool {
+        *self == Action::ChooseBrush
+    }
+
+    /// Build the command for this action.
+    fn command(&self, arg: Option<char>) -> Command {
+        match self {
+            Action::Undo => Command::Undo,
+            Action::Redo => Command::Redo,
+            Action::Save => Command::Save,
+            Action::ZoomIn => Command::ZoomIn,
+            Action::ZoomOut => Command::ZoomOut,
+            Action::ChooseBrush => Command::ChooseBrush {
+                quickid: arg.expect("missing brush quickid"),
+            },
+            Action::NewLayer => Command::NewLayer,
+            Action::DeleteLayer => Command::DeleteLayer,
+            Action::SelectLayerBelow => Command::SelectLayerBelow,
+            Action::SelectLayerAbove => Command::SelectLayerAbove,
+            Action::ToggleLayerVisibility => Command::ToggleLayerVisibility,
+            Action::ToggleLayerLock => Command::ToggleLayerLock,
+            Action::CycleLayerBlendMode => Command::CycleLayerBlendMode,
+            Action::DecreaseLayerOpacity => Command::DecreaseLayerOpacity,
+            Action::IncreaseLayerOpacity => Command::IncreaseLayerOpacity,
+            Action::DecreaseBrushSize => Command::DecreaseBrushSize,
+            Action::IncreaseBrushSize => Command::IncreaseBrushSize,
+            Action::ToggleSmoothing => Command::ToggleSmoothing,
+            Action::ToggleEraser => Command::ToggleEraser,
+            Action::IncreaseExposure => Command::IncreaseExposure,
+            Action::DecreaseExposure => Command::DecreaseExposure,
+            Action::ResetExposure => Command::ResetExposure,
+            Action::CycleToneMap => Command::CycleToneMap,
+            Action::CyclePaintMode => Command::CyclePaintMode,
+            Action::ToggleFill => Command::ToggleFill,
+            Action::CycleSelectionTool => Command::CycleSelectionTool,
+            Action::CycleSelectionOp => Command::CycleSelectionOp,
+            Action::SelectAll => Command::SelectAll,
+            Action::Deselect => Command::Deselect,
+            Action::Copy => Command::Copy,
+            Action::CopyMerged => Command::CopyMerged,
+            Action::Cut => Command::Cut,
+            Action::Paste => Command::Paste,
+            Action::PasteClipboard => Command::PasteClipboard,
+            Action::Commit => Command::Commit,
+            Action::Cancel => Command::Cancel,
+            Action::Transform => Command::Transform,
+            Action::FlipHorizontal => Command::FlipHorizontal,
+            Action::FlipVertical => Command::FlipVertical,
+            Action::CycleResampling => Command::CycleResampling,
+        }
+    }
+}
+
+/// Look up a command by its key binding name, if it takes no argument.
+pub fn command_by_name(name: &str) -> Option<Command> {
+    Action::from_name(name)
+        .filter(|action| !action.takes_argument())
+        .map(|action| action.command(None))
+}
+
+/// Binding from a key sequence to an action.
+#[derive(Clone, Debug)]
+pub struct Binding {
+    pub keys: Vec<ChordPattern>,
+    pub action: Action,
+}
+
+impl Binding {
+    /// Check that the key sequence fits the action.
+    fn validate(&self) -> Result<(), String> {
+        let wildcards = self
+            .keys
+            .iter()
+            .filter(|pattern| matches!(pattern, ChordPattern::Any { .. }))
+            .count();
+        if self.action.takes_argument() && wildcards != 1 {
+            return Err(format!(
+                "command '{}' needs exactly one '*' in its keys",
+                self.action.name()
+            ));
+        }
+        if !self.action.takes_argument() && wildcards != 0 {
+            return Err(format!(
+                "command '{}' doesn't take a '*' argument",
+                self.action.name()
+            ));
+        }
+
+        // Digits at the start of a sequence are read as a count prefix.
+        if let Some(ChordPattern::Exact(chord)) = self.keys.first() {
+            if chord.digit().is_some_and(|digit| digit != 0) {
+                return Err("keys can't start with a count digit".to_string());
+            }
+        }
+        Ok(())
+    }
+
+    /// Check if the two bindings can match the same key presses, either
+    /// because they are the same or one is a prefix of the other.
+    fn conflicts(&self, other: &Binding) -> bool {
+        self.keys
+            .iter()
+            .zip(other.keys.iter())
+            .all(|(pattern, other_pattern)| pattern.overlaps(other_pattern))
+    }
+}
+
+impl fmt::Display for Binding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, pattern) in self.keys.iter().enumerate() {
+            if i > 0 {
+                write!(f, " ")?;
+            }
+            write!(f, "{pattern}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Parse a key sequence of space separated chords.
+fn parse_keys(s: &str) -> Result<Vec<ChordPattern>, String> {
+    let keys = s
+        .split_whitespace()
+        .map(ChordPattern::parse)
+        .collect::<Result<Vec<_>, _>>()?;
+    if keys.is_empty() {
+        return Err("empty key sequence".to_string());
+    }
+    Ok(keys)
+}
+
+/// Default key bindings, in the format of the `[keybindings]` config table.
+const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("u", "undo"),
+    ("r", "redo"),
+    ("s", "save"),
+    ("z", "zoom_in"),
+    ("x", "zoom_out"),
+    ("b *", "choose_brush"),
+    ("A-*", "choose_brush"),
+    ("n", "new_layer"),
+    ("D", "delete_layer"),
+    ("j", "select_layer_below"),
+    ("k", "select_layer_above"),
+    ("v", "toggle_layer_visibility"),
+    ("l", "toggle_layer_lock"),
+    ("m", "cycle_layer_blend_mode"),
+    ("<", "decrease_layer_opacity"),
+    (">", "increase_layer_opacity"),
+    ("[", "decrease_brush_size"),
+    ("]", "increase_brush_size"),
+    ("S", "toggle_smoothing"),
+    ("e", "toggle_eraser"),
+    ("+", "increase_exposure"),
+    ("-", "decrease_exposure"),
+    ("=", "reset_exposure"),
+    ("T", "cycle_tone_map"),
+    ("M", "cycle_paint_mode"),
+    ("f", "toggle_fill"),
+    ("o", "cycle_selection_tool"),
+    ("O", "cycle_selection_op"),
+    ("C-a", "select_all"),
+    ("C-d", "deselect"),
+    ("y", "copy"),
+    ("Y", "copy_merged"),
+    ("d", "cut"),
+    ("p", "paste"),
+    ("P", "paste_clipboard"),
+    ("enter", "commit"),
+    ("escape", "cancel"),
+    ("t", "transform"),
+    ("h", "flip_horizontal"),
+    ("H", "flip_vertical"),
+    ("R", "cycle_resampling"),
+];
+
+/// Build the key bindings from the `[keybindings]` config table.
+///
+/// Each entry maps a key sequence to a command name. Configured commands
+/// lose their default keys, and configured key sequences replace any default
+/// binding for the same keys; binding keys to `none` just removes the
+/// default. All problems found are returned together.
+pub fn load_bindings(config: &BTreeMap<String, String>) -> Result<Vec<Binding>, Vec<String>> {
+    let mut errors = vec![];
+    let mut bindings = vec![];
+    let mut configured_keys = vec![];
+    let mut configured_actions = HashSet::new();
+    for (keys, name) in config {
+        let parsed_keys = match parse_keys(keys) {
+            Ok(parsed_keys) => parsed_keys,
+            Err(err) => {
+                errors.push(format!("keybinding '{keys}': {err}"));
+                continue;
+            }
+        };
+        configured_keys.push(parsed_keys.clone());
+        if name == UNBOUND {
+            continue;
+        }
+        let Some(action) = Action::from_name(name) else {
+            errors.push(format!("keybinding '{keys}': unknown command '{name}'"));
+            continue;
+        };
+        configured_actions.insert(action);
+        bindings.push(Binding {
+            keys: parsed_keys,
+            action,
+        });
+    }
+
+    for (keys, name) in DEFAULT_BINDINGS {
+        let keys = parse_keys(keys).expect("invalid default key sequence");
+        let action = Action::from_name(name).expect("invalid default command");
+        if !configured_actions.contains(&action) && !configured_keys.contains(&keys) {
+            bindings.push(Binding { keys, action });
+        }
+    }
+
+    for (i, binding) in bindings.iter().enumerate() {
+        if let Err(err) = binding.validate() {
+            errors.push(format!("keybinding '{binding}': {err}"));
+        }
+        for other in &bindings[i + 1..] {
+            if binding.conflicts(other) {
+                errors.push(format!(
+                    "keybindings '{binding}' ({}) and '{other}' ({}) conflict",
+                    binding.action.name(),
+                    other.action.name()
+                ));
+            }
+        }
+    }
+
+    if errors.is_empty() {
+        Ok(bindings)
+    } else {
+        Err(errors)
+    }
+}
+
+pub struct CommandState {
+    /// Key bindings.
+    bindings: Vec<Binding>,
+
+    /// Chords of the sequence entered so far.
+    pending: Vec<Chord>,
+
+    /// Numeric count prefix entered so far.
+    count: Option<usize>,
+
+    /// Time of the last key press, in milliseconds.
+    last_press: Option<u64>,
+}
 
 impl CommandState {
-    pub fn new() -> CommandState {
-        CommandState
+    pub fn new(bindings: Vec<Binding>) -> CommandState {
+        CommandState {
+            bindings,
+            pending: vec![],
+            count: None,
+            last_press: None,
+        }
     }
 
-    /// Update the state with a new key press.
-    pub fn handle(&mut self, key: Key) {
-        // TODO
+    /// Discard any partially entered sequence.
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.count = None;
+    }
+
+    /// Update the state with a new key press at `time_ms`.
+    ///
+    /// Returns the command to run and the number of times to run it, once a
+    /// complete sequence has been entered. A binding that matches the whole
+    /// sequence runs immediately, even if a longer binding shares its prefix.
+    pub fn handle(&mut self, key: Key, time_ms: u64) -> Option<(Command, usize)> {
+        // Plain modifier presses don't affect the sequence.
+        let chord = Chord::from_key(&key)?;
+
+        if let Some(last_press) = self.last_press {
+            if Duration::from_millis(time_ms.saturating_sub(last_press)) > SEQUENCE_TIMEOUT {
+                self.reset();
+            }
+        }
+        let _ = self.last_press.insert(time_ms);
+
+        // Count prefixes are only allowed before the sequence starts. A
+        // leading zero is not treated as a count.
+        if self.pending.is_empty() {
+            if let Some(digit) = chord.digit() {
+                if digit != 0 || self.count.is_some() {
+                    let count = self.count.unwrap_or(0).saturating_mul(10);
+                    let _ = self
+                        .count
+                        .insert(count.saturating_add(digit).min(MAX_COUNT));
+                    return None;
+                }
+            }
+        }
+
+        self.pending.push(chord);
+        let mut partial_match = false;
+        for binding in &self.bindings {
+            if binding.keys.len() < self.pending.len()
+                || !binding
+                    .keys
+                    .iter()
+                    .zip(self.pending.iter())
+                    .all(|(pattern, chord)| pattern.matches(chord))
+            {
+                continue;
+            }
+
+            if binding.keys.len() == self.pending.len() {
+                let arg = binding
+                    .keys
+                    .iter()
+                    .zip(self.pending.iter())
+                    .find(|(pattern, _)| matches!(pattern, ChordPattern::Any { .. }))
+                    .map(|(_, chord)| chord.value);
+                let command = binding.action.command(arg);
+                let count = self.count.unwrap_or(1);
+                self.reset();
+                return Some((command, count));
+            }
+            partial_match = true;
+        }
+
+        if !partial_match {
+            self.reset();
+        }
+        None
     }
 }
 
 /// Commands to be executed by the application.
+#[derive(Clone, Debug, PartialEq)]
 pub enum Command {
+    Undo,
+    Redo,
+    Save,
+    ZoomIn,
+    ZoomOut,
     ChooseBrush { quickid: char },
+    NewLayer,
+    DeleteLayer,
+    SelectLayerBelow,
+    SelectLayerAbove,
+    ToggleLayerVisibility,
+    ToggleLayerLock,
+    CycleLayerBlendMode,
+    DecreaseLayerOpacity,
+    IncreaseLayerOpacity,
+    DecreaseBrushSize,
+    IncreaseBrushSize,
+    ToggleSmoothing,
+    ToggleEraser,
+    IncreaseExposure,
+    DecreaseExposure,
+    ResetExposure,
+    CycleToneMap,
+    CyclePaintMode,
+    ToggleFill,
+    CycleSelectionTool,
+    CycleSelectionOp,
+    SelectAll,
+    Deselect,
+    Copy,
+    CopyMerged,
+    Cut,
+    Paste,
+    PasteClipboard,
+    Commit,
+    Cancel,
+    Transform,
+    FlipHorizontal,
+    FlipVertical,
+    CycleResampling,
+}
+
+impl Command {
+    /// Can this command be repeated with a count prefix?
+    pub fn repeatable(&self) -> bool {
+        !matches!(
+            self,
+            Command::Save
+                | Command::ChooseBrush { .. }
+                | Command::Paste
+                | Command::PasteClipboard
+                | Command::Transform
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(value: char, control: bool, alt: bool) -> ChordPattern {
+        ChordPattern::Exact(Chord {
+            value,
+            control,
+            alt,
+        })
+    }
+
+    fn key(value: char) -> Key {
+        Key::Sequence {
+            value,
+            control: false,
+            alt: false,
+        }
+    }
+
+    fn bindings(config: &[(&str, &str)]) -> Result<Vec<Binding>, Vec<String>> {
+        let config = config
+            .iter()
+            .map(|(keys, name)| (keys.to_string(), name.to_string()))
+            .collect();
+        load_bindings(&config)
+    }
+
+    /// Press keys one millisecond apart, returning the last result.
+    fn press(state: &mut CommandState, keys: &str) -> Option<(Command, usize)> {
+        let mut result = None;
+        for (time_ms, value) in keys.chars().enumerate() {
+            result = state.handle(key(value), time_ms as u64);
+        }
+        result
+    }
+
+    #[test]
+    fn chords_are_parsed() {
+        assert_eq!(ChordPattern::parse("u"), Ok(chord('u', false, false)));
+        assert_eq!(ChordPattern::parse("C-z"), Ok(chord('z', true, false)));
+        assert_eq!(ChordPattern::parse("A-S-x"), Ok(chord('X', false, true)));
+        assert_eq!(ChordPattern::parse("S-d"), ChordPattern::parse("D"));
+        assert_eq!(ChordPattern::parse("enter"), Ok(chord('\r', false, false)));
+        assert_eq!(
+            ChordPattern::parse("C-*"),
+            Ok(ChordPattern::Any {
+                control: true,
+                alt: false
+            })
+        );
+        // A lone "-" is a key, not a modifier.
+        assert_eq!(ChordPattern::parse("-"), Ok(chord('-', false, false)));
+        assert_eq!(ChordPattern::parse("C--"), Ok(chord('-', true, false)));
+
+        for invalid in ["", "uu", "S-*", "S-1", "X-u"] {
+            assert!(ChordPattern::parse(invalid).is_err(), "{invalid}");
+        }
+        assert!(parse_keys(" ").is_err());
+    }
+
+    #[test]
+    fn chords_are_displayed_as_parsed() {
+        for keys in ["u", "C-A-z", "A-*", "space", "escape"] {
+            assert_eq!(ChordPattern::parse(keys).unwrap().to_string(), keys);
+        }
+    }
+
+    #[test]
+    fn sequences_run_when_complete() {
+        let mut state = CommandState::new(bindings(&[]).unwrap());
+        assert_eq!(press(&mut state, "u"), Some((Command::Undo, 1)));
+        // A partial sequence waits for the next key.
+        assert_eq!(press(&mut state, "b"), None);
+        assert_eq!(
+            press(&mut state, "h"),
+            Some((Command::ChooseBrush { quickid: 'h' }, 1))
+        );
+        let alt_h = Key::Sequence {
+            value: 'h',
+            control: false,
+            alt: true,
+        };
+        assert_eq!(
+            state.handle(alt_h, 0),
+            Some((Command::ChooseBrush { quickid: 'h' }, 1))
+        );
+        // Keys that match nothing are dropped.
+        assert_eq!(press(&mut state, "Q"), None);
+        assert_eq!(press(&mut state, "u"), Some((Command::Undo, 1)));
+    }
+
+    #[test]
+    fn full_matches_run_before_longer_bindings() {
+        let config = [("g", "undo"), ("g g", "redo")];
+        assert!(bindings(&config).is_err());
+
+        // Without the conflict check, the shorter binding wins.
+        let keys = |s| parse_keys(s).unwrap();
+        let mut state = CommandState::new(vec![
+            Binding {
+                keys: keys("g"),
+                action: Action::Undo,
+            },
+            Binding {
+                keys: keys("g g"),
+                action: Action::Redo,
+            },
+        ]);
+        assert_eq!(press(&mut state, "g"), Some((Command::Undo, 1)));
+    }
+
+    #[test]
+    fn count_prefixes_repeat_commands() {
+        let mut state = CommandState::new(bindings(&[]).unwrap());
+        assert_eq!(press(&mut state, "12u"), Some((Command::Undo, 12)));
+        assert_eq!(press(&mut state, "u"), Some((Command::Undo, 1)));
+        // Zeros after the first digit are part of the count.
+        assert_eq!(press(&mut state, "10r"), Some((Command::Redo, 10)));
+        // Counts can't appear inside a sequence.
+        assert_eq!(
+            press(&mut state, "3b5"),
+            Some((Command::ChooseBrush { quickid: '5' }, 3))
+        );
+    }
+
+    #[test]
+    fn leading_zeros_are_keys() {
+        let mut state = CommandState::new(bindings(&[("0", "undo")]).unwrap());
+        assert_eq!(press(&mut state, "0"), Some((Command::Undo, 1)));
+        // After a count digit, a zero is part of the count.
+        assert_eq!(press(&mut state, "20r"), Some((Command::Redo, 20)));
+        assert!(bindings(&[("1", "undo")]).is_err());
+    }
+
+    #[test]
+    fn counts_are_capped() {
+        let mut state = CommandState::new(bindings(&[]).unwrap());
+        assert_eq!(press(&mut state, "999u"), Some((Command::Undo, 999)));
+        assert_eq!(press(&mut state, "1000u"), Some((Command::Undo, MAX_COUNT)));
+        let digits = "9".repeat(40);
+        assert_eq!(
+            press(&mut state, &format!("{digits}u")),
+            Some((Command::Undo, MAX_COUNT))
+        );
+    }
+
+    #[test]
+    fn pending_sequences_time_out() {
+        let mut state = CommandState::new(bindings(&[]).unwrap());
+        let timeout = SEQUENCE_TIMEOUT.as_millis() as u64;
+        assert_eq!(state.handle(key('b'), 0), None);
+        assert_eq!(
+            state.handle(key('h'), timeout),
+            Some((Command::ChooseBrush { quickid: 'h' }, 1))
+        );
+
+        assert_eq!(state.handle(key('b'), 0), None);
+        // The late key starts over, and 'h' alone is flip_horizontal.
+        assert_eq!(
+            state.handle(key('h'), timeout + 1),
+            Some((Command::FlipHorizontal, 1))
+        );
+
+        // Counts time out too.
+        assert_eq!(state.handle(key('5'), 0), None);
+        assert_eq!(
+            state.handle(key('u'), timeout + 1),
+            Some((Command::Undo, 1))
+        );
+    }
+
+    #[test]
+    fn conflicting_bindings_are_rejected() {
+        let errors = bindings(&[("g", "undo"), ("g h", "redo")]).unwrap_err();
+        assert_eq!(errors.len(), 1);
+        assert!(errors[0].contains("conflict"), "{}", errors[0]);
+
+        // A default binding for the same keys is replaced, so it doesn't
+        // conflict.
+        let bindings = bindings(&[("u", "redo")]).unwrap();
+        let undo = bindings.iter().find(|b| b.action == Action::Undo);
+        assert!(undo.is_none());
+
+        // Binding keys to none removes the default.
+        let mut state = CommandState::new(self::bindings(&[("r", "none")]).unwrap());
+        assert_eq!(press(&mut state, "r"), None);
+    }
+
+    #[test]
+    fn invalid_bindings_are_reported_together() {
+        let errors = bindings(&[("q", "unknown"), ("b", "choose_brush"), ("", "undo")]);
+        assert_eq!(errors.unwrap_err().len(), 3);
+    }
 }
@@ -1,20 +1,389 @@
 use serde::Deserialize;
+use std::collections::BTreeMap;
+use teal_base::{ImagePixel, TipParams};
+use teal_ops::{
+    BucketFill, ColorMetric, Dynamics, FillSource, MagicWand, PressureResponse, Resampling,
+    ResponseCurve, Smoothing, Stabilizer,
+};
 
 /// Brush configuration.
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct Brush {
     /// Name of the brush.
     pub name: String,
 
-    /// Path to brush image file.
-    pub file: String,
+    /// Path to brush image file, for image brushes.
+    #[serde(default)]
+    pub file: Option<String>,
+
+    /// Procedural tip, for brushes without an image file.
+    #[serde(default)]
+    pub tip: Option<TipParams>,
 
     /// Quick ID (to be used to set the current brush).
     pub quickid: char,
+
+    /// Brush rotation in degrees.
+    #[serde(default)]
+    pub angle: f32,
+
+    /// Pressure response curves.
+    #[serde(default)]
+    pub pressure: PressureCurves,
+
+    /// Stamp spacing and randomization.
+    #[serde(default)]
+    pub dynamics: DynamicsConfig,
+}
+
+/// Brush dynamics, with amounts in percent.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct DynamicsConfig {
+    /// Distance between stamps in percent of the brush diameter.
+    pub spacing: f32,
+
+    /// Largest random reduction of the stamp size.
+    pub size_jitter: f32,
+
+    /// Largest random reduction of the stamp opacity.
+    pub opacity_jitter: f32,
+
+    /// Turn the brush to follow the direction of the stroke.
+    pub follow_stroke: bool,
+
+    /// Largest random offset of a stamp in percent of the brush diameter.
+    pub scatter: f32,
+
+    /// Most that a stroke covers a pixel.
+    pub opacity: f32,
+
+    /// How much a single stamp adds towards the opacity.
+    pub flow: f32,
+
+    /// Seed of the random variations.
+    pub seed: u64,
+}
+
+impl Default for DynamicsConfig {
+    fn default() -> DynamicsConfig {
+        let dynamics = Dynamics::default();
+        DynamicsConfig {
+            spacing: dynamics.spacing * 100.0,
+            size_jitter: dynamics.size_jitter * 100.0,
+            opacity_jitter: dynamics.opacity_jitter * 100.0,
+            follow_stroke: dynamics.follow_stroke,
+            scatter: dynamics.scatter * 100.0,
+            opacity: dynamics.opacity * 100.0,
+            flow: dynamics.flow * 100.0,
+            seed: dynamics.seed,
+        }
+    }
+}
+
+impl DynamicsConfig {
+    /// Build the brush dynamics from the config.
+    pub fn dynamics(&self) -> Result<Dynamics, String> {
+        let fraction = |value: f32, name: &str| {
+            if (0.0..=100.0).contains(&value) {
+                Ok(value / 100.0)
+            } else {
+                Err(format!("{name} {value} is not between 0 and 100"))
+            }
+        };
+        if !(self.spacing.is_finite() && self.spacing > 0.0) {
+            return Err(format!("invalid spacing {}", self.spacing));
+        }
+        if !(self.scatter.is_finite() && self.scatter >= 0.0) {
+            return Err(format!("invalid scatter {}", self.scatter));
+        }
+        Ok(Dynamics {
+            spacing: self.spacing / 100.0,
+            size_jitter: fraction(self.size_jitter, "size jitter")?,
+            opacity_jitter: fraction(self.opacity_jitter, "opacity jitter")?,
+            follow_stroke: self.follow_stroke,
+            scatter: self.scatter / 100.0,
+            opacity: fraction(self.opacity, "opacity")?,
+            flow: fraction(self.flow, "flow")?,
+            seed: self.seed,
+        })
+    }
+}
+
+/// Pressure response curves of a brush, each a list of [pressure, factor]
+/// points. Missing curves don't respond to pressure.
+#[derive(Clone, Default, Deserialize)]
+pub struct PressureCurves {
+    /// Brush size factor.
+    pub size: Option<Vec<[f32; 2]>>,
+
+    /// Stroke opacity.
+    pub opacity: Option<Vec<[f32; 2]>>,
+
+    /// Flow of each brush stamp.
+    pub flow: Option<Vec<[f32; 2]>>,
+}
+
+impl PressureCurves {
+    /// Build the pressure response from the curves.
+    pub fn response(&self) -> Result<PressureResponse, String> {
+        let curve = |points: &Option<Vec<[f32; 2]>>, name: &str| match points {
+            Some(points) => ResponseCurve::new(
+                points
+                    .iter()
+                    .map(|[pressure, value]| (*pressure, *value))
+                    .collect(),
+            )
+            .map_err(|err| format!("{name} curve: {err}")),
+            None => Ok(ResponseCurve::constant(1.0)),
+        };
+        Ok(PressureResponse {
+            size: curve(&self.size, "size")?,
+            opacity: curve(&self.opacity, "opacity")?,
+            flow: curve(&self.flow, "flow")?,
+        })
+    }
+}
+
+/// Stabilizer kind of the smoothing config.
+#[derive(Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum StabilizerKind {
+    /// No stabilizer
+    None,
+
+    /// Moving average of pointer positions
+    Average,
+
+    /// Lazy rope
+    Rope,
+}
+
+/// Drag input smoothing.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct SmoothingConfig {
+    /// Is smoothing enabled at startup?
+    pub enabled: bool,
+
+    /// Stabilizer of the pointer positions.
+    pub stabilizer: StabilizerKind,
+
+    /// Number of pointer positions averaged, or the rope length in screen
+    /// pixels.
+    pub strength: f64,
+
+    /// Draw curves through the stabilized points.
+    pub curves: bool,
+}
+
+impl Default for SmoothingConfig {
+    fn default() -> SmoothingConfig {
+        SmoothingConfig {
+            enabled: false,
+            stabilizer: StabilizerKind::Rope,
+            strength: 10.0,
+            curves: true,
+        }
+    }
+}
+
+impl SmoothingConfig {
+    /// Build the smoothing settings from the config.
+    pub fn smoothing(&self) -> Result<Smoothing, String> {
+        if !(self.strength.is_finite() && self.strength >= 0.0) {
+            return Err(format!("invalid strength {}", self.strength));
+        }
+        let stabilizer = match self.stabilizer {
+            StabilizerKind::None => Stabilizer::None,
+            StabilizerKind::Average => Stabilizer::MovingAverage(self.strength.round() as usize),
+            StabilizerKind::Rope => Stabilizer::LazyRope(self.strength),
+        };
+        Ok(Smoothing {
+            stabilizer,
+            curves: self.curves,
+        })
+    }
+}
+
+/// Image that the fill and wand configs compare colors in.
+#[derive(Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FillSourceKind {
+    /// Active layer
+    Layer,
+
+    /// Composite of the visible layers
+    Composite,
+}
+
+impl FillSourceKind {
+    /// Image that colors are compared in.
+    fn source(self) -> FillSource {
+        match self {
+            FillSourceKind::Layer => FillSource::Layer,
+            FillSourceKind::Composite => FillSource::Composite,
+        }
+    }
+}
+
+/// Bucket fill settings.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct FillConfig {
+    /// Color tolerance as a percentage.
+    pub tolerance: f32,
+
+    /// Only fill pixels connected to the clicked pixel.
+    pub contiguous: bool,
+
+    /// Anti-alias the edges of the filled area.
+    pub antialias: bool,
+
+    /// Image that colors are compared in.
+    pub sample: FillSourceKind,
+}
+
+impl Default for FillConfig {
+    fn default() -> FillConfig {
+        FillConfig {
+            tolerance: 10.0,
+            contiguous: true,
+            antialias: true,
+            sample: FillSourceKind::Layer,
+        }
+    }
+}
+
+impl FillConfig {
+    /// Build a bucket fill with a color from the config.
+    pub fn fill(&self, color: ImagePixel) -> Result<BucketFill, String> {
+        if !(0.0..=100.0).contains(&self.tolerance) {
+            return Err(format!(
+                "tolerance {} is not between 0 and 100",
+                self.tolerance
+            ));
+        }
+        Ok(BucketFill::new(color)
+            .with_tolerance(self.tolerance / 100.0)
+            .with_contiguous(self.contiguous)
+            .with_antialias(self.antialias)
+            .with_source(self.sample.source()))
+    }
+}
+
+/// Color distance metric of the wand config.
+#[derive(Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum MetricKind {
+    /// Red, green and blue channels
+    Rgb,
+
+    /// Red, green, blue and alpha channels
+    Rgba,
+
+    /// Perceptual CIE Lab
+    Lab,
+}
+
+/// Magic wand settings.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct WandConfig {
+    /// Color tolerance as a percentage.
+    pub tolerance: f32,
+
+    /// Only select pixels connected to the clicked pixel.
+    pub contiguous: bool,
+
+    /// Anti-alias the edges of the selection.
+    pub antialias: bool,
+
+    /// How color differences are measured.
+    pub metric: MetricKind,
+
+    /// Image that colors are compared in.
+    pub sample: FillSourceKind,
+}
+
+impl Default for WandConfig {
+    fn default() -> WandConfig {
+        WandConfig {
+            tolerance: 10.0,
+            contiguous: true,
+            antialias: false,
+            metric: MetricKind::Rgba,
+            sample: FillSourceKind::Layer,
+        }
+    }
+}
+
+impl WandConfig {
+    /// Build the magic wand from the config.
+    pub fn wand(&self) -> Result<MagicWand, String> {
+        if !(0.0..=100.0).contains(&self.tolerance) {
+            return Err(format!(
+                "tolerance {} is not between 0 and 100",
+                self.tolerance
+            ));
+        }
+        let metric = match self.metric {
+            MetricKind::Rgb => ColorMetric::Rgb,
+            MetricKind::Rgba => ColorMetric::Rgba,
+            MetricKind::Lab => ColorMetric::Lab,
+        };
+        Ok(MagicWand::default()
+            .with_tolerance(self.tolerance / 100.0)
+            .with_contiguous(self.contiguous)
+            .with_antialias(self.antialias)
+            .with_metric(metric)
+            .with_source(self.sample.source()))
+    }
+}
+
+/// Resampling method of the transform config.
+#[derive(Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ResamplingKind {
+    /// Closest pixel
+    Nearest,
+
+    /// Linear interpolation
+    Bilinear,
+
+    /// Cubic interpolation
+    Bicubic,
+}
+
+/// Free transform settings.
+#[derive(Clone, Deserialize)]
+#[serde(default)]
+pub struct TransformConfig {
+    /// How transformed pixels are resampled at first.
+    pub resampling: ResamplingKind,
+}
+
+impl Default for TransformConfig {
+    fn default() -> TransformConfig {
+        TransformConfig {
+            resampling: ResamplingKind::Bilinear,
+        }
+    }
+}
+
+impl TransformConfig {
+    /// Resampling method from the config.
+    pub fn resampling(&self) -> Resampling {
+        match self.resampling {
+            ResamplingKind::Nearest => Resampling::Nearest,
+            ResamplingKind::Bilinear => Resampling::Bilinear,
+            ResamplingKind::Bicubic => Resampling::Bicubic,
+        }
+    }
 }
 
 /// Color setting.
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct Color {
     /// Red channel.
     r: f32,
@@ -29,21 +398,121 @@ pub struct Color {
     a: f32,
 }
 
+/// Default undo budget of 64 MiB, for configs that predate it.
+fn default_undo_budget() -> usize {
+    64 * 1024 * 1024
+}
+
 /// Main application config.
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub struct Config {
     /// Should teal make a backup of the image?
     pub backup: bool,
 
-    /// Maximum number of operations in the undo buffer.
-    pub max_undo: usize,
-
-    /// Maximum number of operations in the redo buffer.
-    pub max_redo: usize,
+    /// Maximum size of the undo/redo history (and its journal) in bytes.
+    #[serde(default = "default_undo_budget")]
+    pub undo_budget: usize,
 
     /// List of available brushes.
     pub brushes: Vec<Brush>,
 
     /// Default color.
     pub default_color: Color,
+
+    /// Drag input smoothing.
+    #[serde(default)]
+    pub smoothing: SmoothingConfig,
+
+    /// Bucket fill settings.
+    #[serde(default)]
+    pub fill: FillConfig,
+
+    /// Magic wand settings.
+    #[serde(default)]
+    pub wand: WandConfig,
+
+    /// Free transform settings.
+    #[serde(default)]
+    pub transform: TransformConfig,
+
+    /// Key bindings, mapping key sequences to command names. These replace
+    /// the default bindings of the same keys or commands.
+    #[serde(default)]
+    pub keybindings: BTreeMap<String, String>,
+}
+
+impl Config {
+    /// Check the config for problems, returning a message for each one.
+    pub fn validate(&self) -> Result<(), Vec<String>> {
+        let mut errors = crate::command::load_bindings(&self.keybindings)
+            .err()
+            .unwrap_or_default();
+        for brush in &self.brushes {
+            match (&brush.file, &brush.tip) {
+                (Some(_), Some(_)) => {
+                    errors.push(format!("brush '{}': has both a file and a tip", brush.name))
+                }
+                (None, None) => errors.push(format!(
+                    "brush '{}': needs either a file or a tip",
+                    brush.name
+                )),
+                (None, Some(tip)) => {
+                    if let Err(err) = tip.validate() {
+                        errors.push(format!("brush '{}': tip {err}", brush.name));
+                    }
+                }
+                (Some(_), None) => (),
+            }
+            if let Err(err) = brush.pressure.response() {
+                errors.push(format!("brush '{}': pressure {err}", brush.name));
+            }
+            if let Err(err) = brush.dynamics.dynamics() {
+                errors.push(format!("brush '{}': dynamics {err}", brush.name));
+            }
+        }
+        if let Err(err) = self.smoothing.smoothing() {
+            errors.push(format!("smoothing: {err}"));
+        }
+        if let Err(err) = self.fill.fill(ImagePixel::from([0.0; 4])) {
+            errors.push(format!("fill: {err}"));
+        }
+        if let Err(err) = self.wand.wand() {
+            errors.push(format!("wand: {err}"));
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn configs_without_undo_budget_load() {
+        let config: Config = toml::from_str(
+            r#"
+            backup = true
+            max_undo = 128
+            max_redo = 2
+
+            [default_color]
+            r = 1.0
+            g = 1.0
+            b = 1.0
+            a = 1.0
+
+            [[brushes]]
+            name = "8x8"
+            file = "./brushes/8x8.exr"
+            quickid = '1'
+            "#,
+        )
+        .unwrap();
+        assert_eq!(config.undo_budget, default_undo_budget());
+        assert!(config.validate().is_ok());
+    }
 }
@@ -1,23 +1,76 @@
 //! Teal paint
 use std::cell::RefCell;
-use std::collections::{HashMap, VecDeque};
-use std::path::PathBuf;
+use std::collections::HashMap;
+use std::fs::File;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 use std::rc::Rc;
 use teal_base::{
-    Brush, DragEvent, Event, GUIContext, GUIOptions, Image, ImagePixel, ImageView, Key, KeyEvent,
-    ScreenBuffer, GUI,
+    color, Brush, DragEvent, Event, GUIContext, GUIOptions, Image, ImagePixel, ImageView, Key,
+    KeyEvent, LayerId, LayerStack, MessageKind, PaintMode, ScreenBuffer, Selection, SelectionOp,
+    Status, GUI,
+};
+use teal_ops::journal::{self, JournalHeader};
+use teal_ops::{
+    Clip, DragInput, Dynamics, Eraser, FloatingDrag, FloatingPaste, FreeTransform, History,
+    LayerOp, LayerPropsOp, Operation, PaintBrush, PressureResponse, Resampling, ResizeOp,
+    Smoothing, TransformDrag, ViewDragHandler,
 };
-use teal_ops::{DragInput, Operation, PaintBrush, ViewDragHandler};
 
 mod config;
 pub use config::Config;
+use config::{FillConfig, WandConfig};
+mod batch;
+pub use batch::run_batch;
+mod cmdline;
+use cmdline::ExCommand;
 mod command;
+use command::Command;
+mod project;
+use project::Project;
+mod record;
+pub use record::{load_recording, RecordedEvent, Recorder};
+mod select;
+use select::{OutlineShape, SelectionDrag, SelectionTool};
+
+/// Distance in screen pixels from the first corner of a polygon selection
+/// within which a click closes it.
+const POLYGON_CLOSE_DISTANCE: f64 = 8.0;
+
+/// Time in milliseconds for the selection outline to march one screen pixel.
+/// The outline moves whenever the screen is redrawn.
+const MARCHING_ANTS_STEP_MS: u64 = 100;
+
+/// Factor applied to the brush size by each size step.
+const BRUSH_SIZE_STEP: f32 = 1.25;
+
+/// Range of brush sizes, as factors of the brush image size.
+const BRUSH_SIZE_RANGE: (f32, f32) = (0.05, 20.0);
+
+/// Exposure change of the exposure keys, in stops.
+const EXPOSURE_STEP: f32 = 1.0;
+
+/// Color used when none has been chosen.
+const DEFAULT_COLOR: [f32; 4] = [1.0, 1.0, 1.0, 1.0];
 
 /// CLI arguments.
 pub struct Args {
     pub fname: String,
     pub dims: Option<(u32, u32)>,
+
+    /// Optional path to record all incoming events to.
+    pub record: Option<PathBuf>,
+}
+
+/// A loaded brush with its settings from the config.
+struct BrushPreset {
+    brush: Brush,
+    pressure: PressureResponse,
+
+    /// Brush rotation in radians.
+    angle: f32,
+
+    dynamics: Dynamics,
 }
 
 /// Application data
@@ -25,8 +78,11 @@ pub struct Application {
     /// Image path.
     image_path: PathBuf,
 
-    /// Actual image data being operated on.
-    image: Image,
+    /// Layers of the image being operated on.
+    layers: LayerStack,
+
+    /// ICC profile of the source image, kept when saving.
+    icc_profile: Option<Vec<u8>>,
 
     /// Image view, tranforming the image for view on the screen.
     image_view: ImageView,
@@ -40,118 +96,582 @@ pub struct Application {
     /// Command state handling incoming key presses.
     command: command::CommandState,
 
-    /// Current color.
+    /// Current color, in linear sRGB.
     color: Option<ImagePixel>,
 
     /// Currently selected brush (by quickid).
     selected_brush: Option<char>,
 
-    /// Completed operations.
-    undo_buffer: VecDeque<Box<dyn Operation>>,
+    /// Brush size, as a factor of the brush image size.
+    brush_size: f32,
+
+    /// Smoothing of brush strokes.
+    smoothing: Smoothing,
+
+    /// Is stroke smoothing enabled?
+    smoothing_enabled: bool,
+
+    /// Erase with the selected brush instead of painting.
+    eraser: bool,
+
+    /// How the brush blends the color onto the image.
+    paint_mode: PaintMode,
+
+    /// Fill on click instead of painting with the brush.
+    fill_tool: bool,
+
+    /// Bucket fill settings.
+    fill: FillConfig,
+
+    /// Magic wand settings.
+    wand: WandConfig,
+
+    /// Selection tool used by drags instead of the brush, if any.
+    selection_tool: Option<SelectionTool>,
+
+    /// How new selections combine with the current one.
+    selection_op: SelectionOp,
+
+    /// Outline of the selection being drawn.
+    selection_drag: Option<SelectionDrag>,
+
+    /// Screen point where the current selection drag began.
+    selection_start: Option<(f64, f64)>,
+
+    /// Pixels copied or cut, for pasting.
+    clip: Option<Clip>,
+
+    /// Pasted pixels that can still be moved, shared with the drag moving
+    /// them.
+    floating: Option<Rc<RefCell<FloatingPaste>>>,
+
+    /// Ask the GUI for the system clipboard image after the current event.
+    request_clipboard: bool,
+
+    /// Pixels being transformed, shared with the drag transforming them.
+    transform: Option<Rc<RefCell<FreeTransform>>>,
+
+    /// How transformed pixels are resampled.
+    resampling: Resampling,
+
+    /// Undo/redo history.
+    history: History,
+
+    /// Loaded brushes (<quickid, BrushPreset> pairs).
+    brushes: HashMap<char, BrushPreset>,
 
-    /// Undone operations.
-    redo_buffer: VecDeque<Box<dyn Operation>>,
+    /// Image coordinates under the cursor, if it is over the image.
+    cursor: Option<(u32, u32)>,
 
-    /// Loaded brushes (<quickid, Brush> pairs).
-    brushes: HashMap<char, Brush>,
+    /// Messages for the user, shown after the current event is handled.
+    messages: Vec<(MessageKind, String)>,
 
-    /// Config file options.
-    config: Config,
+    /// Status last sent to the GUI.
+    last_status: Option<Status>,
 }
 
 impl Application {
-    /// Create a new application from a config.
-    fn new(args: Args, config: Config) -> Application {
-        // Load or create the image.
+    /// Create a new application from a config, failing if the image,
+    /// project or brushes can't be loaded.
+    fn try_new(args: Args, config: Config) -> Result<Application, String> {
+        // Load or create the image or project.
         let image_path = PathBuf::from(args.fname);
-        let image = if let Some(image) = teal_base::load_image(&image_path) {
-            if config.backup {
-                // Make a backup of the old image.
-                let ext = image_path
-                    .extension()
-                    .expect("image is missing an extension")
-                    .to_str()
-                    .expect("failed to decode extension into unicode string")
-                    .to_string();
-                let mut backup_path = image_path.clone();
-                backup_path.set_extension(format!("teal_backup.{ext}"));
-                image
-                    .save(&backup_path)
-                    .expect("failed to save backup image");
-            }
-            image
+        let session = if project::is_project_path(&image_path) {
+            Application::load_project(&image_path, args.dims, &config)?
         } else {
-            if let Some((width, height)) = args.dims {
-                Image::new(width, height)
-            } else {
-                panic!("missing width and height dimensions for creating new image");
-            }
+            Application::load_image(&image_path, args.dims, &config)?
         };
 
         // Load brushes.
         let mut brushes = HashMap::new();
         for brush_opt in &config.brushes {
-            let brush = Brush::new(&brush_opt.name, &brush_opt.file)
-                .expect(&format!("failed to load brush: {}", brush_opt.name));
-            brushes.insert(brush_opt.quickid, brush);
+            let brush = match (&brush_opt.file, &brush_opt.tip) {
+                (Some(file), _) => Brush::new(&brush_opt.name, file)
+                    .map_err(|err| format!("failed to load brush '{}': {err}", brush_opt.name))?,
+                (None, Some(tip)) => Brush::procedural(&brush_opt.name, tip.clone()),
+                (None, None) => {
+                    return Err(format!("brush '{}' has no file or tip", brush_opt.name))
+                }
+            };
+            let pressure = brush_opt
+                .pressure
+                .response()
+                .map_err(|err| format!("brush '{}': pressure {err}", brush_opt.name))?;
+            let dynamics = brush_opt
+                .dynamics
+                .dynamics()
+                .map_err(|err| format!("brush '{}': dynamics {err}", brush_opt.name))?;
+            let angle = brush_opt.angle.to_radians();
+            brushes.insert(
+                brush_opt.quickid,
+                BrushPreset {
+                    brush,
+                    pressure,
+                    angle,
+                    dynamics,
+                },
+            );
         }
 
-        Application {
+        let selected_brush = session
+            .selected_brush
+            .filter(|quickid| brushes.contains_key(quickid));
+        let mut messages = vec![];
+        let history =
+            Application::load_history(&image_path, &session.layers, &config, &mut messages);
+        let bindings = command::load_bindings(&config.keybindings)
+            .map_err(|errors| format!("invalid key bindings: {}", errors.join("; ")))?;
+        let smoothing = config
+            .smoothing
+            .smoothing()
+            .map_err(|err| format!("smoothing: {err}"))?;
+        Ok(Application {
             image_path,
-            image,
-            image_view: ImageView::new(),
+            layers: session.layers,
+            icc_profile: session.icc_profile,
+            image_view: session.image_view,
             drag: None,
-            command: command::CommandState::new(),
+            command: command::CommandState::new(bindings),
             key: None,
+            color: session.color.map(ImagePixel::from),
+            selected_brush,
+            brush_size: 1.0,
+            smoothing,
+            smoothing_enabled: config.smoothing.enabled,
+            eraser: false,
+            paint_mode: PaintMode::Normal,
+            fill_tool: false,
+            fill: config.fill.clone(),
+            wand: config.wand.clone(),
+            selection_tool: None,
+            selection_op: SelectionOp::Replace,
+            selection_drag: None,
+            selection_start: None,
+            clip: None,
+            floating: None,
+            request_clipboard: false,
+            transform: None,
+            resampling: config.transform.resampling(),
+            history,
+            brushes,
+            cursor: None,
+            messages,
+            last_status: None,
+        })
+    }
+
+    /// Load a flat image or create a new one.
+    fn load_image(
+        image_path: &Path,
+        dims: Option<(u32, u32)>,
+        config: &Config,
+    ) -> Result<Project, String> {
+        let loaded = teal_base::load_image(image_path)
+            .map_err(|err| format!("failed to load {}: {err}", image_path.display()))?;
+        let (image, icc_profile) = match (loaded, dims) {
+            (Some((image, icc_profile)), _) => {
+                if config.backup {
+                    // Make a backup of the old image.
+                    let ext = image_path
+                        .extension()
+                        .and_then(|ext| ext.to_str())
+                        .ok_or("image is missing an extension")?;
+                    let mut backup_path = image_path.to_path_buf();
+                    backup_path.set_extension(format!("teal_backup.{ext}"));
+                    teal_base::save_image(&image, &backup_path, icc_profile.as_deref())
+                        .map_err(|err| format!("failed to save backup image: {err}"))?;
+                }
+                (image, icc_profile)
+            }
+            (None, Some((width, height))) => (Image::new(width, height), None),
+            (None, None) => {
+                return Err(format!(
+                    "{} does not exist; give its width and height to create it",
+                    image_path.display()
+                ))
+            }
+        };
+        Ok(Project {
+            layers: LayerStack::new(image),
+            image_view: ImageView::new(),
             color: None,
             selected_brush: None,
-            undo_buffer: VecDeque::new(),
-            redo_buffer: VecDeque::new(),
-            brushes,
-            config,
+            icc_profile,
+        })
+    }
+
+    /// Load a project file or create a new project.
+    fn load_project(
+        project_path: &Path,
+        dims: Option<(u32, u32)>,
+        config: &Config,
+    ) -> Result<Project, String> {
+        if !project_path.exists() {
+            let (width, height) = dims.ok_or_else(|| {
+                format!(
+                    "{} does not exist; give its width and height to create it",
+                    project_path.display()
+                )
+            })?;
+            return Ok(Project {
+                layers: LayerStack::new(Image::new(width, height)),
+                image_view: ImageView::new(),
+                color: None,
+                selected_brush: None,
+                icc_profile: None,
+            });
+        }
+
+        if config.backup {
+            // Make a backup of the old project.
+            let mut backup_path = project_path.to_path_buf();
+            backup_path.set_extension(format!("teal_backup.{}", project::PROJECT_EXTENSION));
+            std::fs::copy(project_path, &backup_path)
+                .map_err(|err| format!("failed to save backup project: {err}"))?;
         }
+        project::load(project_path)
+            .map_err(|err| format!("failed to load {}: {err}", project_path.display()))
+    }
+
+    /// Get the path of the undo journal for an image.
+    fn journal_path(image_path: &Path) -> PathBuf {
+        let ext = image_path
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .unwrap_or("");
+        let mut journal_path = image_path.to_path_buf();
+        journal_path.set_extension(format!("{ext}.teal_journal"));
+        journal_path
+    }
+
+    /// Load the undo history from the journal next to the image.
+    ///
+    /// The journal is only used if it was written for the exact file that
+    /// was loaded; otherwise the history starts out empty.
+    fn load_history(
+        image_path: &Path,
+        layers: &LayerStack,
+        config: &Config,
+        messages: &mut Vec<(MessageKind, String)>,
+    ) -> History {
+        let journal_path = Application::journal_path(image_path);
+        let Ok(journal_file) = File::open(&journal_path) else {
+            return History::new(config.undo_budget);
+        };
+        let source_hash = match std::fs::read(image_path) {
+            Ok(data) => journal::hash_bytes(&data),
+            Err(_) => return History::new(config.undo_budget),
+        };
+        match History::load(journal_file, config.undo_budget) {
+            Ok((history, header))
+                if header
+                    == (JournalHeader {
+                        width: layers.width(),
+                        height: layers.height(),
+                        source_hash,
+                    }) =>
+            {
+                history
+            }
+            Ok(_) => {
+                messages.push((
+                    MessageKind::Error,
+                    "ignoring undo journal that does not match the image".to_string(),
+                ));
+                History::new(config.undo_budget)
+            }
+            Err(err) => {
+                messages.push((
+                    MessageKind::Error,
+                    format!("failed to load undo journal: {err}"),
+                ));
+                History::new(config.undo_budget)
+            }
+        }
+    }
+
+    /// Can the undo history be replayed on the saved file once it's
+    /// reopened? A flat image reopens as a single layer, so its history only
+    /// applies if the stack is already just that layer, shown as it is.
+    fn history_matches_file(&self) -> bool {
+        if project::is_project_path(&self.image_path) {
+            return true;
+        }
+        let layers = self.layers.layers();
+        layers.len() == 1
+            && layers[0].id == LayerId(0)
+            && layers[0].visible
+            && layers[0].opacity == 1.0
+    }
+
+    /// Save the undo history to the journal next to the image, or remove a
+    /// stale journal if the history doesn't apply to the saved file.
+    fn save_history(&mut self) {
+        let journal_path = Application::journal_path(&self.image_path);
+        if !self.history_matches_file() {
+            match std::fs::remove_file(&journal_path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => {
+                    self.error(format!("failed to remove undo journal: {err}"))
+                }
+                _ => (),
+            }
+            return;
+        }
+        let source_hash = match std::fs::read(&self.image_path) {
+            Ok(data) => journal::hash_bytes(&data),
+            Err(err) => {
+                self.error(format!("failed to read saved image for journal: {err}"));
+                return;
+            }
+        };
+        let header = JournalHeader {
+            width: self.layers.width(),
+            height: self.layers.height(),
+            source_hash,
+        };
+        let result = File::create(&journal_path)
+            .and_then(|journal_file| self.history.save(journal_file, &header));
+        if let Err(err) = result {
+            self.error(format!("failed to save undo journal: {err}"));
+        }
+    }
+
+    /// Save the image, either as a project or as a flattened image.
+    fn save(&mut self) -> Result<(), String> {
+        self.anchor();
+        if project::is_project_path(&self.image_path) {
+            let session = Project {
+                layers: self.layers.clone(),
+                image_view: self.image_view.clone(),
+                color: self.color.map(|color| color.0),
+                selected_brush: self.selected_brush,
+                icc_profile: self.icc_profile.clone(),
+            };
+            project::save(&self.image_path, &session)
+                .map_err(|err| format!("failed to save project: {err}"))?;
+        } else {
+            teal_base::save_image(
+                &self.layers.flatten(),
+                &self.image_path,
+                self.icc_profile.as_deref(),
+            )
+            .map_err(|err| format!("failed to save image: {err}"))?;
+        }
+        self.save_history();
+        self.info(format!("saved {}", self.image_path.display()));
+        Ok(())
+    }
+
+    /// Queue an informational message for the user.
+    fn info(&mut self, text: String) {
+        self.messages.push((MessageKind::Info, text));
+    }
+
+    /// Queue an error message for the user.
+    fn error(&mut self, text: String) {
+        self.messages.push((MessageKind::Error, text));
     }
 
     /// Main event handling function.
     ///
     /// Handles all events coming from the GUI.
     fn handle_event(&mut self, mut ctx: impl GUIContext, event: Event) {
+        self.image_view
+            .set_ants_phase((ctx.time_ms() / MARCHING_ANTS_STEP_MS) as u32);
         match event {
             Event::Key(key_event) => {
-                self.handle_key_event(key_event, ctx.screen());
+                let time_ms = ctx.time_ms();
+                self.handle_key_event(key_event, time_ms, ctx.screen());
             }
             Event::Drag(drag_event) => {
                 self.handle_drag_event(drag_event, ctx.screen());
             }
+            Event::Motion(x, y) => {
+                self.cursor = self.image_view.get_image_coords_u_checked(
+                    self.layers.active_image(),
+                    x as u32,
+                    y as u32,
+                );
+            }
             Event::ColorUpdate { r, g, b, a } => {
-                let _ = self
-                    .color
-                    .insert(ImagePixel::from([r, g, b, a]));
+                let color = ImagePixel::from([r, g, b, a]);
+                let _ = self.color.insert(color::pixel_to_linear(&color));
             }
-            Event::Resize => {
-                self.image_view.update_screen(&self.image, ctx.screen());
+            Event::Resize { .. } => {
+                self.image_view.update_screen(&self.layers, ctx.screen());
             }
+            Event::Command(line) => {
+                let (width, height) = {
+                    let screen = ctx.screen();
+                    (screen.width(), screen.height())
+                };
+                if self.handle_command_line(&line, width, height) {
+                    self.image_view.update_screen(&self.layers, ctx.screen());
+                }
+            }
+            Event::ClipboardImage {
+                width,
+                height,
+                pixels,
+            } => match teal_base::image::RgbaImage::from_raw(width, height, pixels) {
+                Some(image) => {
+                    let image = Image::from_fn(width, height, |x, y| {
+                        let channels = image.get_pixel(x, y).0;
+                        let pixel = ImagePixel::from(channels.map(|c| c as f32 / u8::MAX as f32));
+                        color::pixel_to_linear(&pixel)
+                    });
+                    self.paste(Clip::new(image));
+                    self.image_view.update_screen(&self.layers, ctx.screen());
+                }
+                None => self.error("invalid clipboard image".to_string()),
+            },
+        }
+        if std::mem::take(&mut self.request_clipboard) {
+            ctx.request_clipboard_image();
         }
+        for (kind, text) in self.messages.drain(..) {
+            ctx.show_message(kind, &text);
+        }
+        let status = self.status();
+        if self.last_status.as_ref() != Some(&status) {
+            ctx.set_status(&status);
+            let _ = self.last_status.insert(status);
+        }
+    }
 
-        // After each event, check whether the undo/redo buffers are too big,
-        // and if so drop some operations.
-        if self.undo_buffer.len() > self.config.max_undo {
-            let new_size = self.config.max_undo / 2;
-            let _ = self.undo_buffer.drain(0..new_size);
+    /// Current status fields.
+    fn status(&self) -> Status {
+        Status {
+            cursor: self.cursor,
+            zoom_percent: self.image_view.zoom() * 100.0,
+            brush: self
+                .selected_brush
+                .and_then(|quickid| self.brushes.get(&quickid))
+                .map(|preset| preset.brush.name().to_string()),
+            brush_size_percent: self.brush_size as f64 * 100.0,
+            eraser: self.eraser,
+            fill: self.fill_tool,
+            selection: self
+                .selection_tool
+                .map(|tool| format!("select {} ({})", tool.name(), self.selection_op.name())),
+            color: self
+                .color
+                .map_or(DEFAULT_COLOR, |color| color::pixel_to_srgb(&color).0),
+            undo_count: self.history.undo_count(),
+            redo_count: self.history.redo_count(),
         }
-        if self.redo_buffer.len() > self.config.max_redo {
-            let new_size = self.config.max_redo / 2;
-            let _ = self.redo_buffer.drain(0..new_size);
+    }
+
+    /// Handle a command line on a screen of the given dimensions, returning
+    /// true if the screen needs a redraw.
+    fn handle_command_line(&mut self, line: &str, width: u32, height: u32) -> bool {
+        let command = match cmdline::parse(line) {
+            Ok(command) => command,
+            Err(err) => {
+                self.error(err);
+                return false;
+            }
+        };
+        match command {
+            ExCommand::Write(path) => {
+                // Keep the old path if saving to the new one fails.
+                let old_path = path.map(|path| std::mem::replace(&mut self.image_path, path));
+                if let Err(err) = self.save() {
+                    self.error(err);
+                    if let Some(old_path) = old_path {
+                        self.image_path = old_path;
+                    }
+                }
+            }
+            ExCommand::Resize { width, height } => {
+                self.anchor();
+                let mut op = ResizeOp::new(width, height);
+                op.redo(&mut self.layers);
+                self.history.push(Box::new(op));
+                self.info(format!("resized canvas to {width}x{height}"));
+                return true;
+            }
+            ExCommand::Brush(name) => {
+                let quickid = self
+                    .brushes
+                    .iter()
+                    .find(|(quickid, preset)| {
+                        preset.brush.name() == name || quickid.to_string() == name
+                    })
+                    .map(|(quickid, _)| *quickid);
+                match quickid {
+                    Some(quickid) => {
+                        let _ = self.selected_brush.insert(quickid);
+                        self.info(format!("selected brush '{name}'"));
+                    }
+                    None => self.error(format!("no brush named '{name}'")),
+                }
+            }
+            ExCommand::Color(color) => {
+                let _ = self.color.insert(color);
+            }
+            ExCommand::Zoom(zoom) => {
+                self.image_view.set_zoom(zoom);
+                return true;
+            }
+            ExCommand::BrushSize(size) => self.set_brush_size(size as f32),
+            ExCommand::Exposure(stops) => {
+                self.set_exposure(stops);
+                return true;
+            }
+            ExCommand::Gamma(gamma) => {
+                self.image_view.set_gamma(gamma);
+                self.info(format!("gamma {gamma}"));
+                return true;
+            }
+            ExCommand::ToneMap(tone_map) => {
+                self.image_view.set_tone_map(tone_map);
+                self.info(format!("tone mapping {}", tone_map.name()));
+                return true;
+            }
+            ExCommand::PaintMode(mode) => self.set_paint_mode(mode),
+            ExCommand::SelectionTool(tool) => self.set_selection_tool(tool),
+            ExCommand::SelectionOp(op) => self.selection_op = op,
+            ExCommand::Feather(radius) => {
+                if let Some(selection) = self.layers.selection() {
+                    let mut selection = selection.clone();
+                    selection.feather(radius);
+                    self.layers.set_selection(Some(selection));
+                    return true;
+                }
+                self.error("Nothing is selected.".to_string());
+            }
+            ExCommand::Rotate(degrees) => {
+                let angle = degrees.to_radians();
+                return self.modify_transform(|transform, image| transform.rotate(image, angle));
+            }
+            ExCommand::Scale(x, y) => {
+                return self.modify_transform(|transform, image| transform.scale(image, x, y));
+            }
+            ExCommand::Resample(resampling) => {
+                self.set_resampling(resampling);
+                return self.transform.is_some();
+            }
+            ExCommand::Key(command) => return self.run_command(&command, width, height),
         }
+        false
     }
 
-    /// Handle a key event.
-    fn handle_key_event(&mut self, key_event: KeyEvent, screen: impl ScreenBuffer) {
+    /// Handle a key event that happened at `time_ms`.
+    fn handle_key_event(&mut self, key_event: KeyEvent, time_ms: u64, screen: impl ScreenBuffer) {
         match key_event {
             KeyEvent::Press(key) => {
                 // Determine if the key should cause a command to run.
-                self.command.handle(key.clone());
-                self.take_key_press_action(key.clone(), screen);
+                if let Some((command, count)) = self.command.handle(key.clone(), time_ms) {
+                    let count = if command.repeatable() { count } else { 1 };
+                    let mut redraw = false;
+                    for _ in 0..count {
+                        redraw |= self.run_command(&command, screen.width(), screen.height());
+                    }
+                    if redraw {
+                        self.image_view.update_screen(&self.layers, screen);
+                    }
+                }
                 let _ = self.key.insert(key);
             }
             KeyEvent::Release(_key) => {
@@ -160,120 +680,597 @@ impl Application {
         }
     }
 
-    /// Take action for various key press sequences.
-    fn take_key_press_action(&mut self, key: Key, screen: impl ScreenBuffer) {
-        if let Key::Sequence {
-            value,
-            control: _,
-            alt,
-        } = key
-        {
-            // Check if it's a brush action.
-            if alt {
-                if self.brushes.get(&value).is_some() {
-                    let _ = self.selected_brush.insert(value);
+    /// Run a single command, returning true if the screen needs a redraw.
+    fn run_command(&mut self, command: &Command, width: u32, height: u32) -> bool {
+        match command {
+            Command::Undo => {
+                // Undo a paste or transform that is not committed yet by
+                // dropping it.
+                if self.cancel() {
+                    return true;
+                }
+                if self.history.undo(&mut self.layers) {
+                    return true;
+                }
+                self.info("no more operations to undo".to_string());
+            }
+            Command::Redo => {
+                self.anchor();
+                if self.history.redo(&mut self.layers) {
+                    return true;
+                }
+                self.info("no more operations to redo".to_string());
+            }
+            Command::Save => {
+                if let Err(err) = self.save() {
+                    self.error(err);
+                }
+            }
+            Command::ZoomIn => {
+                self.image_view.zoom_in(width, height);
+                return true;
+            }
+            Command::ZoomOut => {
+                self.image_view.zoom_out(width, height);
+                return true;
+            }
+            Command::ChooseBrush { quickid } => {
+                if self.brushes.contains_key(quickid) {
+                    let _ = self.selected_brush.insert(*quickid);
                 } else {
-                    eprintln!("no brush for quickid '{}' found", value);
+                    self.error(format!("no brush for quickid '{quickid}' found"));
                 }
-                return;
             }
-
-            // Check for other commands.
-            match value {
-                // Undo an operation.
-                'u' => {
-                    if let Some(mut last_op) = self.undo_buffer.pop_back() {
-                        last_op.undo(&mut self.image);
-                        self.redo_buffer.push_back(last_op);
-                        self.image_view.update_screen(&self.image, screen);
-                    } else {
-                        println!("no more operations to undo");
-                    }
+            Command::DecreaseBrushSize => self.set_brush_size(self.brush_size / BRUSH_SIZE_STEP),
+            Command::IncreaseBrushSize => self.set_brush_size(self.brush_size * BRUSH_SIZE_STEP),
+            Command::ToggleSmoothing => {
+                self.smoothing_enabled = !self.smoothing_enabled;
+                let state = if self.smoothing_enabled { "on" } else { "off" };
+                self.info(format!("stroke smoothing {state}"));
+            }
+            Command::ToggleEraser => self.eraser = !self.eraser,
+            Command::IncreaseExposure => {
+                self.set_exposure(self.image_view.exposure() + EXPOSURE_STEP);
+                return true;
+            }
+            Command::DecreaseExposure => {
+                self.set_exposure(self.image_view.exposure() - EXPOSURE_STEP);
+                return true;
+            }
+            Command::ResetExposure => {
+                self.set_exposure(0.0);
+                return true;
+            }
+            Command::CycleToneMap => {
+                let tone_map = self.image_view.tone_map().next();
+                self.image_view.set_tone_map(tone_map);
+                self.info(format!("tone mapping {}", tone_map.name()));
+                return true;
+            }
+            Command::CyclePaintMode => self.set_paint_mode(self.paint_mode.next()),
+            Command::ToggleFill => {
+                self.fill_tool = !self.fill_tool;
+                if self.fill_tool {
+                    self.set_selection_tool(None);
                 }
-                // Redo an operation.
-                'r' => {
-                    if let Some(mut last_op) = self.redo_buffer.pop_back() {
-                        last_op.redo(&mut self.image);
-                        self.undo_buffer.push_back(last_op);
-                        self.image_view.update_screen(&self.image, screen);
-                    } else {
-                        println!("no more operations to redo");
-                    }
+            }
+            Command::CycleSelectionTool => {
+                self.set_selection_tool(SelectionTool::next(self.selection_tool))
+            }
+            Command::CycleSelectionOp => self.selection_op = self.selection_op.next(),
+            Command::SelectAll => {
+                let selection = Selection::all(self.layers.width(), self.layers.height());
+                self.layers.set_selection(Some(selection));
+                return true;
+            }
+            Command::Deselect => {
+                self.layers.set_selection(None);
+                return true;
+            }
+            Command::Copy => self.set_clip(Clip::copy(&self.layers), "copied"),
+            Command::CopyMerged => self.set_clip(Clip::copy_merged(&self.layers), "copied"),
+            Command::Cut => {
+                self.anchor();
+                if self.layers.active().locked {
+                    self.error("The active layer is locked; use 'l' to unlock it.".to_string());
+                    return false;
                 }
-                // Save the image.
-                's' => {
-                    self.image
-                        .save(&self.image_path)
-                        .expect("failed to save image");
+                let (clip, op) = Clip::cut(&mut self.layers);
+                if let Some(op) = op {
+                    self.history.push(Box::new(op));
                 }
-                // Zoom in some.
-                'z' => {
-                    self.image_view.zoom_in(screen.width(), screen.height());
-                    self.image_view.update_screen(&self.image, screen);
+                self.set_clip(clip, "cut");
+                return true;
+            }
+            Command::Paste => match self.clip.clone() {
+                Some(clip) => {
+                    self.paste(clip);
+                    return true;
                 }
-                // Zoom out some.
-                'x' => {
-                    self.image_view.zoom_out(screen.width(), screen.height());
-                    self.image_view.update_screen(&self.image, screen);
+                None => self.error("Nothing to paste; use 'y' or 'd' to copy or cut.".to_string()),
+            },
+            Command::PasteClipboard => self.request_clipboard = true,
+            Command::Commit => return self.anchor(),
+            Command::Cancel => return self.cancel(),
+            Command::Transform => return self.start_transform(),
+            Command::FlipHorizontal => {
+                return self.modify_transform(|transform, image| transform.flip(image, true))
+            }
+            Command::FlipVertical => {
+                return self.modify_transform(|transform, image| transform.flip(image, false))
+            }
+            Command::CycleResampling => {
+                self.set_resampling(self.resampling.next());
+                return self.transform.is_some();
+            }
+            _ => {
+                self.run_layer_command(command);
+                return true;
+            }
+        }
+        false
+    }
+
+    /// Keep copied or cut pixels for pasting.
+    fn set_clip(&mut self, clip: Clip, verb: &str) {
+        let (width, height) = clip.dimensions();
+        self.info(format!("{verb} {width}x{height} pixels"));
+        let _ = self.clip.insert(clip);
+    }
+
+    /// Paste pixels onto the active layer, floating until they are committed
+    /// or cancelled. An earlier paste is committed first.
+    fn paste(&mut self, clip: Clip) {
+        self.anchor();
+        if self.layers.active().locked {
+            self.error("The active layer is locked; use 'l' to unlock it.".to_string());
+            return;
+        }
+        let (width, height) = clip.dimensions();
+        let paste = FloatingPaste::new(&mut self.layers, clip);
+        let _ = self.floating.insert(Rc::new(RefCell::new(paste)));
+        self.info(format!(
+            "pasted {width}x{height} pixels; drag to move them, then commit or cancel the paste"
+        ));
+    }
+
+    /// Take the floating paste, ending any drag moving it.
+    fn take_floating(&mut self) -> Option<FloatingPaste> {
+        let paste = self.floating.take()?;
+        // The drag moving the paste holds the only other reference.
+        self.drag = None;
+        let paste = Rc::into_inner(paste).expect("floating paste is still shared");
+        Some(paste.into_inner())
+    }
+
+    /// Start transforming the selected pixels of the active layer, or the
+    /// whole layer, returning false if that isn't possible. A transform
+    /// already in progress is kept.
+    fn start_transform(&mut self) -> bool {
+        if self.transform.is_some() {
+            return true;
+        }
+        self.anchor();
+        if self.layers.active().locked {
+            self.error("The active layer is locked; use 'l' to unlock it.".to_string());
+            return false;
+        }
+        let transform = FreeTransform::new(&mut self.layers, self.resampling);
+        let _ = self.transform.insert(Rc::new(RefCell::new(transform)));
+        self.update_handles();
+        self.info(format!(
+            "transforming with {} resampling; drag the handles, then commit or cancel the transform",
+            self.resampling.name()
+        ));
+        true
+    }
+
+    /// Change the transform in progress, starting one if there is none.
+    /// Returns true if the pixels were transformed.
+    fn modify_transform(&mut self, modify: impl FnOnce(&mut FreeTransform, &mut Image)) -> bool {
+        if !self.start_transform() {
+            return false;
+        }
+        let transform = self.transform.as_ref().expect("missing transform");
+        let mut transform = transform.borrow_mut();
+        let layer = self
+            .layers
+            .get_mut(transform.layer())
+            .expect("missing transformed layer");
+        modify(&mut transform, &mut layer.image);
+        drop(transform);
+        self.update_handles();
+        true
+    }
+
+    /// Set how transformed pixels are resampled.
+    fn set_resampling(&mut self, resampling: Resampling) {
+        self.resampling = resampling;
+        if let Some(transform) = self.transform.as_ref() {
+            let mut transform = transform.borrow_mut();
+            if let Some(layer) = self.layers.get_mut(transform.layer()) {
+                transform.set_resampling(&mut layer.image, resampling);
+            }
+        }
+        self.info(format!("resampling {}", resampling.name()));
+    }
+
+    /// Take the transform in progress, ending any drag changing it and
+    /// hiding its handles.
+    fn take_transform(&mut self) -> Option<FreeTransform> {
+        let transform = self.transform.take()?;
+        // The drag changing the transform holds the only other reference.
+        self.drag = None;
+        self.update_handles();
+        let transform = Rc::into_inner(transform).expect("transform is still shared");
+        Some(transform.into_inner())
+    }
+
+    /// Show the handles of the transform in progress, if any.
+    fn update_handles(&mut self) {
+        match self.transform.as_ref() {
+            Some(transform) => self.image_view.set_handles(&transform.borrow().handles()),
+            None => self.image_view.set_handles(&[]),
+        }
+    }
+
+    /// Are pasted or transformed pixels floating over a layer?
+    fn is_floating(&self) -> bool {
+        self.floating.is_some() || self.transform.is_some()
+    }
+
+    /// Commit the floating paste or the transform in progress to the
+    /// history, returning false if there was neither.
+    fn anchor(&mut self) -> bool {
+        if let Some(paste) = self.take_floating() {
+            if let Some(op) = paste.commit() {
+                self.history.push(Box::new(op));
+            }
+            return true;
+        }
+        if let Some(transform) = self.take_transform() {
+            if let Some(op) = transform.commit(&mut self.layers) {
+                self.history.push(Box::new(op));
+            }
+            return true;
+        }
+        false
+    }
+
+    /// Drop the floating paste or the transform in progress, returning false
+    /// if there was neither.
+    fn cancel(&mut self) -> bool {
+        if let Some(paste) = self.take_floating() {
+            paste.cancel(&mut self.layers);
+            return true;
+        }
+        if let Some(transform) = self.take_transform() {
+            transform.cancel(&mut self.layers);
+            return true;
+        }
+        false
+    }
+
+    /// Choose the selection tool, or go back to painting with None.
+    fn set_selection_tool(&mut self, tool: Option<SelectionTool>) {
+        self.selection_tool = tool;
+        self.selection_drag = None;
+        if tool.is_some() {
+            self.fill_tool = false;
+        }
+    }
+
+    /// Set the paint mode of the brush.
+    fn set_paint_mode(&mut self, mode: PaintMode) {
+        self.paint_mode = mode;
+        self.info(format!("paint mode {}", mode.name()));
+    }
+
+    /// Set the view exposure in stops.
+    fn set_exposure(&mut self, stops: f32) {
+        self.image_view.set_exposure(stops);
+        self.info(format!("exposure {stops:+.1} EV"));
+    }
+
+    /// Set the brush size, limited to the allowed range.
+    fn set_brush_size(&mut self, size: f32) {
+        let (min, max) = BRUSH_SIZE_RANGE;
+        self.brush_size = size.clamp(min, max);
+        self.info(format!("brush size {:.0}%", self.brush_size * 100.0));
+    }
+
+    /// Run a layer command.
+    fn run_layer_command(&mut self, command: &Command) {
+        // A paste stays on the layer it was pasted onto.
+        self.anchor();
+        // Changes to the opacity, visibility or blend mode are undoable.
+        let props = LayerPropsOp::new(self.layers.active());
+        match command {
+            // Add a new layer above the active one.
+            Command::NewLayer => {
+                let name = format!("Layer {}", self.layers.layers().len());
+                let op = LayerOp::add(&mut self.layers, &name);
+                self.history.push(Box::new(op));
+            }
+            Command::DeleteLayer => match LayerOp::remove_active(&mut self.layers) {
+                Some(op) => self.history.push(Box::new(op)),
+                None => self.error("cannot delete the last layer".to_string()),
+            },
+            Command::SelectLayerBelow => {
+                let index = self.layers.active_index();
+                if index > 0 {
+                    self.layers.select(index - 1);
                 }
-                _ => (),
-                _ => (),
             }
+            Command::SelectLayerAbove => {
+                let index = self.layers.active_index();
+                self.layers.select(index + 1);
+            }
+            Command::ToggleLayerVisibility => {
+                let layer = self.layers.active_mut();
+                layer.visible = !layer.visible;
+            }
+            Command::ToggleLayerLock => {
+                let layer = self.layers.active_mut();
+                layer.locked = !layer.locked;
+            }
+            Command::CycleLayerBlendMode => {
+                let layer = self.layers.active_mut();
+                layer.blend_mode = layer.blend_mode.next();
+            }
+            Command::DecreaseLayerOpacity | Command::IncreaseLayerOpacity => {
+                let step = if *command == Command::DecreaseLayerOpacity {
+                    -0.1
+                } else {
+                    0.1
+                };
+                let layer = self.layers.active_mut();
+                layer.opacity = (layer.opacity + step).clamp(0.0, 1.0);
+            }
+            _ => return,
         }
+        let layer = self.layers.active();
+        if layer.id == props.layer() && !props.matches(layer) {
+            self.history.push(Box::new(props));
+        }
+        let status = format!(
+            "layer {}/{}: '{}' (opacity: {:.1}, visible: {}, locked: {}, blend: {:?})",
+            self.layers.active_index() + 1,
+            self.layers.layers().len(),
+            layer.name,
+            layer.opacity,
+            layer.visible,
+            layer.locked,
+            layer.blend_mode,
+        );
+        self.info(status);
     }
 
     /// Create the drag input handler.
-    fn create_drag_input(&self) -> Option<DragInput> {
+    fn create_drag_input(&mut self) -> Option<DragInput> {
         if let Some(Key::PlainControl) = self.key {
             // This needs a drag handler that will translate the view.
             let view_handler = ViewDragHandler::new();
             Some(DragInput::new(view_handler))
+        } else if let Some(paste) = self.floating.as_ref() {
+            // Drags move the floating paste until it is committed.
+            Some(DragInput::new(FloatingDrag::new(Rc::clone(paste))))
+        } else if let Some(transform) = self.transform.as_ref() {
+            // Drags change the transform until it is committed.
+            Some(DragInput::new(TransformDrag::new(Rc::clone(transform))))
         } else {
             // Create an image operation drag handler.
+            if self.layers.active().locked {
+                self.error("The active layer is locked; use 'l' to unlock it.".to_string());
+                return None;
+            }
             if self.selected_brush.is_none() {
-                eprintln!("No selected brush found; use 'ALT+<quickid>' to select a brush.");
+                self.error(
+                    "No selected brush found; use 'b<quickid>' or ':brush' to select a brush."
+                        .to_string(),
+                );
                 return None;
             }
             let selected_brush = self.selected_brush.unwrap();
-            let brush = self
+            let preset = self
                 .brushes
                 .get(&selected_brush)
                 .expect("failed to find brush");
             let color = if let Some(color) = self.color.as_ref() {
                 color.clone()
             } else {
-                ImagePixel::from([1.0, 1.0, 1.0, 1.0])
+                ImagePixel::from(DEFAULT_COLOR)
+            };
+            let paint_brush = PaintBrush::new(preset.brush.clone(), color)
+                .with_pressure(preset.pressure.clone())
+                .with_size(self.brush_size)
+                .with_angle(preset.angle)
+                .with_dynamics(preset.dynamics.clone())
+                .with_mode(self.paint_mode);
+            let smoothing = if self.smoothing_enabled {
+                self.smoothing
+            } else {
+                Smoothing::default()
             };
-            let paint_brush = PaintBrush::new(brush.clone(), color);
-            Some(DragInput::new(paint_brush))
+            let drag_input = if self.eraser {
+                DragInput::new(Eraser::new(paint_brush))
+            } else {
+                DragInput::new(paint_brush)
+            };
+            Some(drag_input.with_smoothing(smoothing))
+        }
+    }
+
+    /// Fill the active layer from the pixel under screen coordinates (x, y).
+    fn bucket_fill(&mut self, x: f64, y: f64) {
+        if self.layers.active().locked {
+            self.error("The active layer is locked; use 'l' to unlock it.".to_string());
+            return;
+        }
+        let Some((img_x, img_y)) = self.image_view.get_image_coords_u_checked(
+            self.layers.active_image(),
+            x as u32,
+            y as u32,
+        ) else {
+            return;
+        };
+        let color = self.color.unwrap_or(ImagePixel::from(DEFAULT_COLOR));
+        let fill = self
+            .fill
+            .fill(color)
+            .expect("invalid fill config")
+            .with_mode(self.paint_mode);
+        if let Some(op) = fill.apply(&mut self.layers, img_x, img_y) {
+            self.history.push(Box::new(op));
+        }
+    }
+
+    /// Draw a selection outline with a selection tool, returning true if the
+    /// selection changed.
+    ///
+    /// Rectangles, ellipses and lassos are selected when the drag ends. A
+    /// polygon gets a corner for each click and is selected when its first
+    /// corner is clicked again. The magic wand selects on a click.
+    fn handle_selection_drag(&mut self, tool: SelectionTool, drag_event: DragEvent) -> bool {
+        // Drag updates are offsets from the start point.
+        let (screen_x, screen_y) = match drag_event {
+            DragEvent::Begin(x, y, _) => *self.selection_start.insert((x, y)),
+            DragEvent::Update(dx, dy, _) | DragEvent::End(dx, dy, _) => {
+                let (x, y) = self.selection_start.unwrap_or_default();
+                (x + dx, y + dy)
+            }
+        };
+        let shape = match tool {
+            SelectionTool::Outline(shape) => shape,
+            SelectionTool::Wand => {
+                if !matches!(drag_event, DragEvent::Begin(..)) {
+                    return false;
+                }
+                let Some((x, y)) = self.image_view.get_image_coords_u_checked(
+                    self.layers.active_image(),
+                    screen_x as u32,
+                    screen_y as u32,
+                ) else {
+                    return false;
+                };
+                let wand = self.wand.wand().expect("invalid wand config");
+                let Some(selection) = wand.select(&self.layers, x, y) else {
+                    return false;
+                };
+                self.combine_selection(&selection);
+                return true;
+            }
+        };
+        let point =
+            self.image_view
+                .get_image_coords_f(self.layers.active_image(), screen_x, screen_y);
+        match (shape, drag_event) {
+            (OutlineShape::Polygon, DragEvent::Begin(..)) => match self.selection_drag.as_mut() {
+                Some(drag) if drag.points().len() >= 3 => {
+                    let first = drag.points()[0];
+                    let (first_x, first_y) = self.image_view.get_screen_coords_f(first.0, first.1);
+                    let dist = ((screen_x - first_x).powi(2) + (screen_y - first_y).powi(2)).sqrt();
+                    if dist <= POLYGON_CLOSE_DISTANCE {
+                        return self.finish_selection();
+                    }
+                    drag.add_point(point);
+                }
+                Some(drag) => drag.add_point(point),
+                None => {
+                    let _ = self.selection_drag.insert(SelectionDrag::new(shape, point));
+                }
+            },
+            (OutlineShape::Polygon, _) => (),
+            (_, DragEvent::Begin(..)) => {
+                let _ = self.selection_drag.insert(SelectionDrag::new(shape, point));
+            }
+            (_, DragEvent::Update(..)) => {
+                if let Some(drag) = self.selection_drag.as_mut() {
+                    drag.add_point(point);
+                }
+            }
+            (_, DragEvent::End(..)) => {
+                if let Some(drag) = self.selection_drag.as_mut() {
+                    drag.add_point(point);
+                }
+                return self.finish_selection();
+            }
         }
+        false
+    }
+
+    /// Combine the drawn selection outline with the current selection.
+    fn finish_selection(&mut self) -> bool {
+        let Some(drag) = self.selection_drag.take() else {
+            return false;
+        };
+        let shape = drag.selection(self.layers.width(), self.layers.height());
+        self.combine_selection(&shape);
+        true
+    }
+
+    /// Combine a new selection with the current one by the selection
+    /// operation.
+    fn combine_selection(&mut self, new: &Selection) {
+        let (width, height) = (self.layers.width(), self.layers.height());
+        let mut selection = self
+            .layers
+            .selection()
+            .cloned()
+            .unwrap_or_else(|| Selection::empty(width, height));
+        selection.combine(new, self.selection_op);
+        self.layers.set_selection(Some(selection));
     }
 
     /// Handle a drag event.
     fn handle_drag_event(&mut self, drag_event: DragEvent, screen: impl ScreenBuffer) {
+        // Control drags still move the view with the selection tools, and
+        // other drags move a floating paste or change a transform.
+        if let Some(tool) = self.selection_tool {
+            if self.drag.is_none()
+                && !self.is_floating()
+                && !matches!(self.key, Some(Key::PlainControl))
+            {
+                if self.handle_selection_drag(tool, drag_event) {
+                    self.image_view.update_screen(&self.layers, screen);
+                }
+                return;
+            }
+        }
         match drag_event {
-            DragEvent::Begin(start_x, start_y) => {
+            // Control drags still move the view with the fill tool.
+            DragEvent::Begin(start_x, start_y, _)
+                if self.fill_tool
+                    && !self.is_floating()
+                    && !matches!(self.key, Some(Key::PlainControl)) =>
+            {
+                self.bucket_fill(start_x, start_y);
+                self.image_view.update_screen(&self.layers, screen);
+            }
+            DragEvent::Begin(start_x, start_y, sample) => {
                 // First create drag input and handler.
                 if let Some(mut drag) = self.create_drag_input() {
-                    drag.start(&mut self.image, start_x, start_y);
-                    self.image_view.update_screen(&self.image, screen);
+                    drag.start(&mut self.layers, start_x, start_y, sample);
+                    self.image_view.update_screen(&self.layers, screen);
                     let _ = self.drag.insert(drag);
                 }
             }
-            DragEvent::Update(x, y) => {
+            DragEvent::Update(x, y, sample) => {
                 if let Some(drag) = self.drag.as_mut() {
-                    drag.update(&mut self.image, &mut self.image_view, x, y);
-                    self.image_view.update_screen(&self.image, screen);
+                    drag.update(&mut self.layers, &mut self.image_view, x, y, sample);
+                    self.update_handles();
+                    self.image_view.update_screen(&self.layers, screen);
                 }
             }
-            DragEvent::End(x, y) => {
+            DragEvent::End(x, y, sample) => {
                 if let Some(mut drag) = self.drag.take() {
-                    drag.finish(&mut self.image, &mut self.image_view, x, y);
+                    drag.finish(&mut self.layers, &mut self.image_view, x, y, sample);
                     // Drag input complete, save it for undo later, if necessary.
                     if let Some(drag_op) = drag.to_op() {
-                        self.undo_buffer.push_back(Box::new(drag_op));
+                        self.history.push(Box::new(drag_op));
                     }
-                    self.image_view.update_screen(&self.image, screen);
+                    self.update_handles();
+                    self.image_view.update_screen(&self.layers, screen);
                 }
             }
         }
@@ -283,11 +1280,32 @@ impl Application {
 // NOTE: I don't want anything too fancy here; I want something that works and
 // that can slowly be refactored to perfection.
 pub fn run<G: GUI>(args: Args, config: Config, mut gui: G) -> ExitCode {
-    let app = Rc::new(RefCell::new(Application::new(args, config)));
+    let recorder = match args.record.as_ref().map(Recorder::create).transpose() {
+        Ok(recorder) => RefCell::new(recorder),
+        Err(err) => {
+            eprintln!("failed to create event recording: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    let app = match Application::try_new(args, config) {
+        Ok(app) => Rc::new(RefCell::new(app)),
+        Err(err) => {
+            eprintln!("{err}");
+            return ExitCode::FAILURE;
+        }
+    };
 
     let options = GUIOptions {};
     // TODO: Simply update the screen with changes to an image made from here
-    gui.run(options, move |ctx, event| {
+    gui.run(options, move |mut ctx, event| {
+        if let Some(recorder) = recorder.borrow_mut().as_mut() {
+            if let Err(err) = recorder.record(ctx.time_ms(), &event) {
+                ctx.show_message(
+                    MessageKind::Error,
+                    &format!("failed to record event: {err}"),
+                );
+            }
+        }
         app.borrow_mut().handle_event(ctx, event);
     })
 }
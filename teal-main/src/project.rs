@@ -0,0 +1,469 @@
+//! Native teal project format.
+//!
+//! A project file is a tar archive holding a `manifest.toml` and one float
+//! EXR payload per layer, plus the ICC profile of the source image if it had
+//! one. The manifest stores the layer properties along with the editor state
+//! (brush, color and view), so that reopening a project gives back the same
+//! session. Layers and the color are in linear sRGB.
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs::File;
+use std::io::{Cursor, Read};
+use std::path::Path;
+use teal_base::image::{self, DynamicImage, ImageFormat};
+use teal_base::{color, BlendMode, ImageView, Layer, LayerId, LayerStack, ToneMap};
+
+/// Extension used for project files.
+pub const PROJECT_EXTENSION: &str = "teal";
+
+/// Current version of the manifest schema.
+pub const PROJECT_VERSION: u32 = 4;
+
+/// Name of the manifest entry in the archive.
+const MANIFEST_NAME: &str = "manifest.toml";
+
+/// Name of the ICC profile entry in the archive.
+const ICC_PROFILE_NAME: &str = "profile.icc";
+
+/// Upgrade functions for older manifests.
+///
+/// The entry at index `i` upgrades a version `i + 1` manifest to version
+/// `i + 2`. Any schema change must bump `PROJECT_VERSION` and add an entry
+/// here.
+const MIGRATIONS: &[fn(&mut toml::Table)] =
+    &[migrate_v1_srgb, migrate_v2_view_tone, migrate_v3_next_id];
+
+/// Version 1 projects stored sRGB encoded layers and color.
+fn migrate_v1_srgb(table: &mut toml::Table) {
+    table.insert("linear".to_string(), toml::Value::Boolean(false));
+}
+
+/// Version 2 views had no exposure, gamma or tone mapping.
+fn migrate_v2_view_tone(table: &mut toml::Table) {
+    if let Some(view) = table.get_mut("view").and_then(|view| view.as_table_mut()) {
+        view.insert("exposure".to_string(), toml::Value::Float(0.0));
+        view.insert("gamma".to_string(), toml::Value::Float(1.0));
+        view.insert(
+            "tone_map".to_string(),
+            toml::Value::String(ToneMap::Clamp.name().to_string()),
+        );
+    }
+}
+
+/// Version 3 projects did not store the next layer id, so it is the one
+/// after the highest id in use.
+fn migrate_v3_next_id(table: &mut toml::Table) {
+    let next_id = table
+        .get("layers")
+        .and_then(|layers| layers.as_array())
+        .into_iter()
+        .flatten()
+        .filter_map(|layer| layer.get("id")?.as_integer())
+        .map(|id| id + 1)
+        .max()
+        .unwrap_or(0);
+    table.insert("next_id".to_string(), toml::Value::Integer(next_id));
+}
+
+/// Project loading and saving errors.
+#[derive(Debug)]
+pub enum Error {
+    Io(std::io::Error),
+    Image(image::ImageError),
+    Format(String),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Error::Io(err) => write!(f, "project I/O error: {err}"),
+            Error::Image(err) => write!(f, "project layer error: {err}"),
+            Error::Format(msg) => write!(f, "invalid project: {msg}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<std::io::Error> for Error {
+    fn from(err: std::io::Error) -> Error {
+        Error::Io(err)
+    }
+}
+
+impl From<image::ImageError> for Error {
+    fn from(err: image::ImageError) -> Error {
+        Error::Image(err)
+    }
+}
+
+/// Full editor session stored in a project.
+pub struct Project {
+    pub layers: LayerStack,
+    pub image_view: ImageView,
+    pub color: Option<[f32; 4]>,
+    pub selected_brush: Option<char>,
+
+    /// ICC profile of the source image.
+    pub icc_profile: Option<Vec<u8>>,
+}
+
+/// Check if a path refers to a project file.
+pub fn is_project_path(path: &Path) -> bool {
+    path.extension().and_then(|ext| ext.to_str()) == Some(PROJECT_EXTENSION)
+}
+
+/// Layer entry in the manifest.
+#[derive(Serialize, Deserialize)]
+struct LayerManifest {
+    id: LayerId,
+    name: String,
+    file: String,
+    opacity: f32,
+    visible: bool,
+    locked: bool,
+    blend_mode: BlendMode,
+}
+
+/// Project manifest.
+#[derive(Serialize, Deserialize)]
+struct Manifest {
+    version: u32,
+    width: u32,
+    height: u32,
+    active_layer: usize,
+
+    /// Next layer id to hand out, above the ids of removed layers too.
+    next_id: u32,
+    selected_brush: Option<char>,
+    color: Option<[f32; 4]>,
+    view: ImageView,
+    layers: Vec<LayerManifest>,
+
+    /// Are the layers and color linear (or sRGB encoded)?
+    linear: bool,
+}
+
+/// Save a project to a path.
+///
+/// The archive is written next to the destination first and then renamed, so
+/// a failed save never leaves a truncated project behind.
+pub fn save<P: AsRef<Path>>(path: P, project: &Project) -> Result<(), Error> {
+    let path = path.as_ref();
+    let layers = project.layers.layers();
+    let manifest = Manifest {
+        version: PROJECT_VERSION,
+        width: project.layers.width(),
+        height: project.layers.height(),
+        active_layer: project.layers.active_index(),
+        next_id: project.layers.next_id(),
+        selected_brush: project.selected_brush,
+        color: project.color,
+        view: project.image_view.clone(),
+        layers: layers
+            .iter()
+            .map(|layer| LayerManifest {
+                id: layer.id,
+                name: layer.name.clone(),
+                file: format!("layers/{}.exr", layer.id.0),
+                opacity: layer.opacity,
+                visible: layer.visible,
+                locked: layer.locked,
+                blend_mode: layer.blend_mode,
+            })
+            .collect(),
+        linear: true,
+    };
+    let manifest_data = toml::to_string(&manifest)
+        .map_err(|err| Error::Format(format!("failed to encode manifest: {err}")))?;
+
+    let tmp_path = path.with_extension(format!("{PROJECT_EXTENSION}.tmp"));
+    let mut builder = tar::Builder::new(File::create(&tmp_path)?);
+    append_entry(&mut builder, MANIFEST_NAME, manifest_data.as_bytes())?;
+    for (layer, entry) in layers.iter().zip(manifest.layers.iter()) {
+        let mut data = Cursor::new(vec![]);
+        DynamicImage::ImageRgba32F(layer.image.clone())
+            .write_to(&mut data, ImageFormat::OpenExr)?;
+        append_entry(&mut builder, &entry.file, data.get_ref())?;
+    }
+    if let Some(icc_profile) = project.icc_profile.as_ref() {
+        append_entry(&mut builder, ICC_PROFILE_NAME, icc_profile)?;
+    }
+    builder.into_inner()?.sync_all()?;
+    std::fs::rename(&tmp_path, path)?;
+    Ok(())
+}
+
+/// Append a single file entry to the archive.
+fn append_entry(builder: &mut tar::Builder<File>, name: &str, data: &[u8]) -> Result<(), Error> {
+    let mut header = tar::Header::new_gnu();
+    header.set_size(data.len() as u64);
+    header.set_mode(0o644);
+    header.set_cksum();
+    builder.append_data(&mut header, name, data)?;
+    Ok(())
+}
+
+/// Load a project from a path.
+pub fn load<P: AsRef<Path>>(path: P) -> Result<Project, Error> {
+    let mut archive = tar::Archive::new(File::open(path)?);
+    let mut entries = HashMap::new();
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let name = entry.path()?.to_string_lossy().into_owned();
+        let mut data = vec![];
+        entry.read_to_end(&mut data)?;
+        entries.insert(name, data);
+    }
+
+    let manifest_data = entries
+        .get(MANIFEST_NAME)
+        .ok_or_else(|| Error::Format(format!("missing {MANIFEST_NAME}")))?;
+    let manifest_data = std::str::from_utf8(manifest_data)
+        .map_err(|_| Error::Format("manifest is not valid UTF-8".to_string()))?;
+    let manifest = parse_manifest(manifest_data)?;
+
+    let mut layers = vec![];
+    for entry in manifest.layers {
+        let data = entries
+            .get(&entry.file)
+            .ok_or_else(|| Error::Format(format!("missing layer data '{}'", entry.file)))?;
+        let mut image =
+            image::load_from_memory_with_format(data, ImageFormat::OpenExr)?.into_rgba32f();
+        if !manifest.linear {
+            for pixel in image.pixels_mut() {
+                *pixel = color::pixel_to_linear(pixel);
+            }
+        }
+        if image.dimensions() != (manifest.width, manifest.height) {
+            return Err(Error::Format(format!(
+                "layer '{}' does not match the project dimensions",
+                entry.name
+            )));
+        }
+        layers.push(Layer {
+            id: entry.id,
+            name: entry.name,
+            image,
+            opacity: entry.opacity,
+            visible: entry.visible,
+            locked: entry.locked,
+            blend_mode: entry.blend_mode,
+        });
+    }
+    let layers = LayerStack::from_layers(layers, manifest.active_layer, manifest.next_id)
+        .ok_or_else(|| Error::Format("invalid layer stack".to_string()))?;
+
+    Ok(Project {
+        layers,
+        image_view: manifest.view,
+        color: manifest.color.map(|color| {
+            if manifest.linear {
+                color
+            } else {
+                color::pixel_to_linear(&color.into()).0
+            }
+        }),
+        selected_brush: manifest.selected_brush,
+        icc_profile: entries.remove(ICC_PROFILE_NAME),
+    })
+}
+
+/// Parse a manifest, migrating it to the current version if needed.
+fn parse_manifest(data: &str) -> Result<Manifest, Error> {
+    let mut table: toml::Table = data
+        .parse()
+        .map_err(|err| Error::Format(format!("failed to parse manifest: {err}")))?;
+    let version = table
+        .get("version")
+        .and_then(|version| version.as_integer())
+        .ok_or_else(|| Error::Format("manifest is missing a version".to_string()))?;
+    let version = u32::try_from(version)
+        .ok()
+        .filter(|version| *version >= 1)
+        .ok_or_else(|| Error::Format(format!("invalid manifest version {version}")))?;
+    if version > PROJECT_VERSION {
+        return Err(Error::Format(format!(
+            "project version {version} is newer than the supported version {PROJECT_VERSION}"
+        )));
+    }
+
+    for migration in &MIGRATIONS[(version - 1) as usize..] {
+        migration(&mut table);
+    }
+    table.insert(
+        "version".to_string(),
+        toml::Value::Integer(PROJECT_VERSION.into()),
+    );
+
+    table
+        .try_into()
+        .map_err(|err| Error::Format(format!("failed to decode manifest: {err}")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+    use teal_base::{Image, ImagePixel};
+
+    /// Path for a test's project file.
+    fn test_path(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!(
+            "teal-project-{}-{name}.{PROJECT_EXTENSION}",
+            std::process::id()
+        ))
+    }
+
+    /// Write a project archive from a manifest and layer files.
+    fn write_archive(path: &Path, manifest: &str, layers: &[(&str, Image)]) {
+        let mut builder = tar::Builder::new(File::create(path).unwrap());
+        append_entry(&mut builder, MANIFEST_NAME, manifest.as_bytes()).unwrap();
+        for (file, image) in layers {
+            let mut data = Cursor::new(vec![]);
+            DynamicImage::ImageRgba32F(image.clone())
+                .write_to(&mut data, ImageFormat::OpenExr)
+                .unwrap();
+            append_entry(&mut builder, file, data.get_ref()).unwrap();
+        }
+        builder.into_inner().unwrap();
+    }
+
+    #[test]
+    fn projects_round_trip() {
+        let red = ImagePixel::from([1.0, 0.0, 0.0, 1.0]);
+        let mut layers = LayerStack::new(Image::from_pixel(3, 2, red));
+        let top = layers.add_layer("top");
+        {
+            let layer = layers.active_mut();
+            // Values above 1.0 stay linear and unclamped.
+            layer
+                .image
+                .put_pixel(2, 1, ImagePixel::from([2.5, 0.5, 0.0, 0.5]));
+            layer.opacity = 0.5;
+            layer.visible = false;
+            layer.locked = true;
+            layer.blend_mode = BlendMode::Multiply;
+        }
+        // The removed layer's id is not handed out again after loading.
+        layers.add_layer("removed");
+        layers.remove_active();
+        layers.select(0);
+
+        let mut image_view = ImageView::new();
+        image_view.set_zoom(2.0);
+        image_view.set_exposure(1.5);
+        image_view.set_gamma(2.0);
+        image_view.set_tone_map(ToneMap::Filmic);
+        let project = Project {
+            layers,
+            image_view,
+            color: Some([0.25, 0.5, 2.0, 1.0]),
+            selected_brush: Some('h'),
+            icc_profile: Some(b"profile".to_vec()),
+        };
+        let path = test_path("round-trip");
+        save(&path, &project).unwrap();
+        let mut loaded = load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        let names: Vec<_> = loaded.layers.layers().iter().map(|l| &l.name).collect();
+        assert_eq!(names, ["Background", "top"]);
+        assert_eq!(loaded.layers.active_index(), 0);
+        let layer = loaded.layers.get(top).unwrap();
+        assert_eq!(
+            (layer.opacity, layer.visible, layer.locked, layer.blend_mode),
+            (0.5, false, true, BlendMode::Multiply)
+        );
+        assert_eq!(layer.image.get_pixel(2, 1).0, [2.5, 0.5, 0.0, 0.5]);
+        assert_eq!(loaded.layers.layers()[0].image.get_pixel(0, 0), &red);
+        assert_eq!(loaded.color, project.color);
+        assert_eq!(loaded.selected_brush, Some('h'));
+        assert_eq!(loaded.icc_profile, project.icc_profile);
+        let view = &loaded.image_view;
+        assert_eq!(view.zoom(), 2.0);
+        assert_eq!((view.exposure(), view.gamma()), (1.5, 2.0));
+        assert_eq!(view.tone_map(), ToneMap::Filmic);
+        assert_eq!(loaded.layers.next_id(), project.layers.next_id());
+        assert_eq!(loaded.layers.add_layer("new"), LayerId(3));
+    }
+
+    #[test]
+    fn version_1_projects_are_migrated() {
+        let manifest = r#"
+            version = 1
+            width = 2
+            height = 1
+            active_layer = 1
+            color = [0.5, 0.5, 0.5, 1.0]
+
+            [view]
+            disp_corner_x = 0.0
+            disp_corner_y = 0.0
+            conversion_factor = 1.0
+
+            [[layers]]
+            id = 0
+            name = "Background"
+            file = "layers/0.exr"
+            opacity = 1.0
+            visible = true
+            locked = false
+            blend_mode = "normal"
+
+            [[layers]]
+            id = 4
+            name = "Layer 1"
+            file = "layers/4.exr"
+            opacity = 1.0
+            visible = true
+            locked = false
+            blend_mode = "screen"
+        "#;
+        let gray = Image::from_pixel(2, 1, ImagePixel::from([0.5, 0.5, 0.5, 1.0]));
+        let path = test_path("version-1");
+        write_archive(
+            &path,
+            manifest,
+            &[("layers/0.exr", gray.clone()), ("layers/4.exr", gray)],
+        );
+        let mut project = load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        // Layers and the color were sRGB encoded.
+        let linear = color::srgb_to_linear(0.5);
+        let pixel = project.layers.active_image().get_pixel(1, 0).0;
+        assert!((pixel[0] - linear).abs() < 1e-3);
+        assert_eq!(pixel[3], 1.0);
+        assert!((project.color.unwrap()[0] - linear).abs() < 1e-3);
+        assert_eq!(project.layers.active().blend_mode, BlendMode::Screen);
+
+        let view = &project.image_view;
+        assert_eq!((view.exposure(), view.gamma()), (0.0, 1.0));
+        assert_eq!(view.tone_map(), ToneMap::Clamp);
+        assert_eq!(project.layers.add_layer("new"), LayerId(5));
+    }
+
+    #[test]
+    fn migrations_reach_the_current_version() {
+        assert_eq!(MIGRATIONS.len() as u32, PROJECT_VERSION - 1);
+        let manifest = r#"
+            version = 2
+            width = 1
+            height = 1
+            active_layer = 0
+            linear = true
+            view = { disp_corner_x = 0.0, disp_corner_y = 0.0, conversion_factor = 1.0 }
+            layers = []
+        "#;
+        let manifest = parse_manifest(manifest).unwrap();
+        assert_eq!(manifest.version, PROJECT_VERSION);
+        assert!(manifest.linear);
+        assert_eq!(manifest.next_id, 0);
+
+        let newer = format!("version = {}", PROJECT_VERSION + 1);
+        assert!(parse_manifest(&newer).is_err());
+        assert!(parse_manifest("version = 0").is_err());
+    }
+}
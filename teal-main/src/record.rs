@@ -0,0 +1,66 @@
+//! Recording and loading of input event sessions.
+//!
+//! Sessions are stored as JSON lines, one timestamped event per line, so
+//! that a recording can be replayed against the same starting image.
+use serde::{Deserialize, Serialize};
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, LineWriter, Write};
+use std::path::Path;
+use teal_base::Event;
+
+/// A single recorded event.
+#[derive(Serialize, Deserialize)]
+pub struct RecordedEvent {
+    /// Time of the event in milliseconds, as given by the GUI.
+    pub time_ms: u64,
+
+    /// The event itself.
+    pub event: Event,
+}
+
+/// Writes events to a recording file as they arrive.
+pub struct Recorder {
+    out: LineWriter<File>,
+}
+
+impl Recorder {
+    /// Create a new recording file, overwriting any existing one.
+    pub fn create<P: AsRef<Path>>(path: P) -> io::Result<Recorder> {
+        Ok(Recorder {
+            out: LineWriter::new(File::create(path)?),
+        })
+    }
+
+    /// Record an event with its time.
+    ///
+    /// Every line is flushed immediately, so the recording is usable even if
+    /// the application crashes.
+    pub fn record(&mut self, time_ms: u64, event: &Event) -> io::Result<()> {
+        let line = serde_json::to_string(&RecordedEventRef { time_ms, event })?;
+        writeln!(self.out, "{line}")
+    }
+}
+
+/// Borrowed version of `RecordedEvent`, for serializing without a copy.
+#[derive(Serialize)]
+struct RecordedEventRef<'a> {
+    time_ms: u64,
+    event: &'a Event,
+}
+
+/// Load a recording.
+pub fn load_recording<P: AsRef<Path>>(path: P) -> io::Result<Vec<RecordedEvent>> {
+    let reader = BufReader::new(File::open(path)?);
+    let mut events = vec![];
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let event = serde_json::from_str(&line).map_err(|err| {
+            io::Error::new(io::ErrorKind::InvalidData, format!("line {}: {err}", i + 1))
+        })?;
+        events.push(event);
+    }
+    Ok(events)
+}
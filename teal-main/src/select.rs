@@ -0,0 +1,124 @@
+//! Selection tools.
+use teal_base::Selection;
+
+/// Shape of a selection outline drawn by dragging.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum OutlineShape {
+    /// Rectangle dragged from corner to corner
+    Rectangle,
+
+    /// Ellipse inside the rectangle dragged from corner to corner
+    Ellipse,
+
+    /// Freehand outline following the drag
+    Lasso,
+
+    /// Polygon with a corner at each click, closed by clicking the first
+    /// corner again
+    Polygon,
+}
+
+impl OutlineShape {
+    /// Name of the shape.
+    pub fn name(self) -> &'static str {
+        match self {
+            OutlineShape::Rectangle => "rectangle",
+            OutlineShape::Ellipse => "ellipse",
+            OutlineShape::Lasso => "lasso",
+            OutlineShape::Polygon => "polygon",
+        }
+    }
+}
+
+/// Selection tool.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum SelectionTool {
+    /// Outline of a shape, selected once it is drawn
+    Outline(OutlineShape),
+
+    /// Magic wand, selecting the pixels of similar color to the clicked one
+    Wand,
+}
+
+impl SelectionTool {
+    /// All tools, in cycling order.
+    pub const ALL: [SelectionTool; 5] = [
+        SelectionTool::Outline(OutlineShape::Rectangle),
+        SelectionTool::Outline(OutlineShape::Ellipse),
+        SelectionTool::Outline(OutlineShape::Lasso),
+        SelectionTool::Outline(OutlineShape::Polygon),
+        SelectionTool::Wand,
+    ];
+
+    /// Next tool in cycling order, or None after the last one.
+    pub fn next(tool: Option<SelectionTool>) -> Option<SelectionTool> {
+        match tool {
+            None => Some(SelectionTool::ALL[0]),
+            Some(tool) => {
+                let index = SelectionTool::ALL.iter().position(|t| *t == tool)?;
+                SelectionTool::ALL.get(index + 1).copied()
+            }
+        }
+    }
+
+    /// Name of the tool.
+    pub fn name(self) -> &'static str {
+        match self {
+            SelectionTool::Outline(shape) => shape.name(),
+            SelectionTool::Wand => "wand",
+        }
+    }
+
+    /// Look up a tool by name.
+    pub fn from_name(name: &str) -> Option<SelectionTool> {
+        SelectionTool::ALL
+            .into_iter()
+            .find(|tool| tool.name() == name)
+    }
+}
+
+/// Outline of a selection being drawn, in image coordinates.
+pub struct SelectionDrag {
+    shape: OutlineShape,
+    points: Vec<(f64, f64)>,
+}
+
+impl SelectionDrag {
+    /// Start an outline at a point.
+    pub fn new(shape: OutlineShape, point: (f64, f64)) -> SelectionDrag {
+        SelectionDrag {
+            shape,
+            points: vec![point],
+        }
+    }
+
+    /// Points of the outline so far.
+    pub fn points(&self) -> &[(f64, f64)] {
+        &self.points
+    }
+
+    /// Add a point. Rectangles and ellipses only keep the latest point as
+    /// the opposite corner.
+    pub fn add_point(&mut self, point: (f64, f64)) {
+        match self.shape {
+            OutlineShape::Rectangle | OutlineShape::Ellipse => {
+                self.points.truncate(1);
+                self.points.push(point);
+            }
+            OutlineShape::Lasso | OutlineShape::Polygon => self.points.push(point),
+        }
+    }
+
+    /// Rasterize the outline into a selection of the canvas dimensions.
+    pub fn selection(&self, width: u32, height: u32) -> Selection {
+        let first = self.points[0];
+        let last = *self.points.last().unwrap();
+        match self.shape {
+            OutlineShape::Rectangle => Selection::rectangle(width, height, first, last),
+            OutlineShape::Ellipse => Selection::ellipse(width, height, first, last),
+            OutlineShape::Lasso | OutlineShape::Polygon => {
+                Selection::polygon(width, height, &self.points)
+            }
+        }
+    }
+}
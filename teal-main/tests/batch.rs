@@ -0,0 +1,109 @@
+//! Brush regression tests driven by batch scripts.
+mod common;
+
+use std::path::Path;
+use std::process::ExitCode;
+use teal_base::image::RgbaImage;
+
+/// Run a batch script in a test directory, returning the exit code.
+fn run_script(dir: &Path, script: &str) -> ExitCode {
+    let script_path = dir.join("script.txt");
+    std::fs::write(&script_path, script).expect("failed to write script");
+    teal_main::run_batch(&script_path, common::config())
+}
+
+/// Run a batch script that saves `out.png`, returning the saved image.
+fn paint(name: &str, script: &str) -> RgbaImage {
+    let dir = common::test_dir(name);
+    let out = dir.join("out.png");
+    let script = format!("open {} 32x32\n{script}\nsave\n", out.display());
+    assert_eq!(run_script(&dir, &script), ExitCode::SUCCESS);
+    teal_base::image::open(out)
+        .expect("failed to open output")
+        .to_rgba8()
+}
+
+/// Number of pixels painted with any coverage in a row.
+fn painted_in_row(image: &RgbaImage, y: u32) -> usize {
+    (0..image.width())
+        .filter(|x| image.get_pixel(*x, y).0[3] > 0)
+        .count()
+}
+
+#[test]
+fn dab_is_round_and_centered() {
+    let image = paint("dab", "brush h\ncolor 1 0 0\nstroke 16,16");
+    assert_eq!(image.get_pixel(16, 16).0, [255, 0, 0, 255]);
+    assert_eq!(image.get_pixel(16, 24).0[3], 0);
+    assert_eq!(image.get_pixel(24, 16).0[3], 0);
+    for offset in 1..6 {
+        assert_eq!(
+            image.get_pixel(16 - offset, 16),
+            image.get_pixel(16 + offset - 1, 16)
+        );
+        assert_eq!(
+            image.get_pixel(16, 16 - offset),
+            image.get_pixel(16, 16 + offset - 1)
+        );
+    }
+    // The corners of the bounding square are outside the round tip.
+    assert_eq!(image.get_pixel(12, 12).0[3], 0);
+}
+
+#[test]
+fn stroke_covers_the_line() {
+    let image = paint("line", "brush h\ncolor 0 0 1\nstroke 4,16 28,16");
+    for x in 4..28 {
+        assert_eq!(image.get_pixel(x, 16).0, [0, 0, 255, 255], "at x {x}");
+    }
+    assert_eq!(painted_in_row(&image, 8), 0);
+    assert_eq!(painted_in_row(&image, 24), 0);
+}
+
+#[test]
+fn pressure_scales_the_brush() {
+    let full = paint("pressure-full", "brush p\nstroke 4,16,1 28,16,1");
+    let half = paint("pressure-half", "brush p\nstroke 4,16,0.5 28,16,0.5");
+    let height = |image: &RgbaImage| (0..32).filter(|y| image.get_pixel(16, *y).0[3] > 0).count();
+    assert!(height(&half) > 0);
+    assert!(height(&half) < height(&full));
+}
+
+#[test]
+fn strokes_are_deterministic() {
+    let script = "brush h\ncolor 0.2 0.6 0.4 0.5\nstroke 3.5,5.25,0.3 20,28,1 29,2,0.7";
+    assert_eq!(paint("repeat-a", script), paint("repeat-b", script));
+}
+
+#[test]
+fn undo_and_redo_stroke() {
+    let painted = paint("painted", "brush h\nstroke 4,4 28,28");
+    let undone = paint("undone", "brush h\nstroke 4,4 28,28\nundo");
+    let redone = paint("redone", "brush h\nstroke 4,4 28,28\nundo\nredo");
+    assert!(undone.pixels().all(|pixel| pixel.0[3] == 0));
+    assert_eq!(painted, redone);
+}
+
+#[test]
+fn load_errors_fail_the_script() {
+    let dir = common::test_dir("errors");
+    let missing = dir.join("missing.png");
+    assert_eq!(
+        run_script(&dir, &format!("open {}\n", missing.display())),
+        ExitCode::FAILURE
+    );
+
+    let corrupt = dir.join("corrupt.png");
+    std::fs::write(&corrupt, b"not a png").unwrap();
+    assert_eq!(
+        run_script(&dir, &format!("open {}\n", corrupt.display())),
+        ExitCode::FAILURE
+    );
+
+    let project = dir.join("corrupt.teal");
+    std::fs::write(&project, b"not a project").unwrap();
+    assert_eq!(
+        run_script(&dir, &format!("open {}\n", project.display())),
+        ExitCode::FAILURE
+    );
+}
@@ -0,0 +1,94 @@
+//! Shared setup for the end-to-end tests.
+#![allow(dead_code)]
+use std::path::PathBuf;
+use teal_base::image::RgbImage;
+use teal_headless::HeadlessGUI;
+use teal_main::Config;
+
+/// Config with procedural brushes only, so tests need no brush files.
+///
+/// Brush 'h' is a hard round tip and brush 'p' is the same tip with its size
+/// following pen pressure.
+pub fn config() -> Config {
+    toml::from_str(
+        r#"
+        backup = false
+
+        [default_color]
+        r = 1.0
+        g = 1.0
+        b = 1.0
+        a = 1.0
+
+        [[brushes]]
+        name = "hard"
+        quickid = 'h'
+        tip = { shape = "round", diameter = 8.0, hardness = 1.0 }
+
+        [[brushes]]
+        name = "pressure"
+        quickid = 'p'
+        tip = { shape = "round", diameter = 8.0, hardness = 1.0 }
+        pressure = { size = [[0.0, 0.0], [1.0, 1.0]] }
+        "#,
+    )
+    .expect("invalid test config")
+}
+
+/// Create an empty directory for a test's files.
+pub fn test_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("teal-test-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).expect("failed to create test directory");
+    dir
+}
+
+/// Run the application on a new 32x32 image with the events queued in `gui`.
+pub fn run_gui(name: &str, gui: HeadlessGUI) {
+    let dir = test_dir(name);
+    let args = teal_main::Args {
+        fname: dir.join("image.png").display().to_string(),
+        dims: Some((32, 32)),
+        record: None,
+    };
+    teal_main::run(args, config(), gui);
+}
+
+/// Run the application with the events queued by `script`, returning the
+/// final screen contents.
+pub fn run(name: &str, script: impl FnOnce(&mut HeadlessGUI)) -> RgbImage {
+    let mut gui = HeadlessGUI::new(64, 48);
+    script(&mut gui);
+    let screen = gui.screen();
+    run_gui(name, gui);
+    let image = screen.borrow().to_image();
+    image
+}
+
+/// Check that undoing the events queued by `change` after those queued by
+/// `setup` gives back the screen from before the change, and that redoing
+/// gives back the screen after it. Returns the screen after the change.
+pub fn assert_undo_restores(
+    name: &str,
+    setup: impl Fn(&mut HeadlessGUI),
+    change: impl Fn(&mut HeadlessGUI),
+) -> RgbImage {
+    let before = run(&format!("{name}-before"), &setup);
+    let changed = run(&format!("{name}-changed"), |gui| {
+        setup(gui);
+        change(gui);
+    });
+    let undone = run(&format!("{name}-undone"), |gui| {
+        setup(gui);
+        change(gui);
+        gui.press('u');
+    });
+    let redone = run(&format!("{name}-redone"), |gui| {
+        setup(gui);
+        change(gui);
+        gui.press('u').press('r');
+    });
+    assert!(undone == before, "undo did not restore the screen");
+    assert!(redone == changed, "redo did not restore the screen");
+    changed
+}
@@ -0,0 +1,146 @@
+//! End-to-end tests driving the application through the headless backend.
+mod common;
+
+use common::{assert_undo_restores, run, run_gui};
+use teal_base::image::{Rgb, RgbImage, Rgba, RgbaImage};
+use teal_base::Key;
+use teal_headless::HeadlessGUI;
+
+const RED: Rgb<u8> = Rgb([255, 0, 0]);
+
+/// Select the hard brush by key and set a red color.
+fn red_brush(gui: &mut HeadlessGUI) -> &mut HeadlessGUI {
+    gui.press('b').press('h').color(1.0, 0.0, 0.0, 1.0)
+}
+
+/// Enter key, committing a paste.
+fn enter() -> Key {
+    Key::Sequence {
+        value: '\r',
+        control: false,
+        alt: false,
+    }
+}
+
+/// Number of red pixels in a screen row.
+fn red_in_row(screen: &RgbImage, y: u32) -> usize {
+    (0..screen.width())
+        .filter(|x| *screen.get_pixel(*x, y) == RED)
+        .count()
+}
+
+#[test]
+fn stroke_is_shown_after_resize() {
+    let screen = run("resize", |gui| {
+        red_brush(gui)
+            .drag(&[(10.0, 10.0), (30.0, 10.0)])
+            .resize(80, 60);
+    });
+    assert_eq!((screen.width(), screen.height()), (80, 60));
+    assert_eq!(*screen.get_pixel(20, 10), RED);
+    assert_ne!(*screen.get_pixel(20, 20), RED);
+    assert_ne!(*screen.get_pixel(40, 10), RED);
+    assert_eq!(red_in_row(&screen, 30), 0);
+}
+
+#[test]
+fn zoom_scales_the_stroke() {
+    let stroke = |gui: &mut HeadlessGUI| {
+        red_brush(gui)
+            .drag(&[(10.0, 10.0), (30.0, 10.0)])
+            .resize(80, 60);
+    };
+    let plain = run("unzoomed", stroke);
+    let zoomed = run("zoomed", |gui| {
+        stroke(gui);
+        gui.press('z');
+    });
+    assert!(red_in_row(&zoomed, 10) > red_in_row(&plain, 10));
+}
+
+#[test]
+fn undo_restores_the_screen() {
+    let first_stroke = |gui: &mut HeadlessGUI| {
+        red_brush(gui)
+            .drag(&[(10.0, 10.0), (30.0, 10.0)])
+            .resize(80, 60);
+    };
+    let second_stroke = |gui: &mut HeadlessGUI| {
+        gui.color(0.0, 0.0, 1.0, 1.0)
+            .drag(&[(10.0, 30.0), (30.0, 30.0)]);
+    };
+    let changed = assert_undo_restores("stroke", first_stroke, second_stroke);
+    assert_eq!(*changed.get_pixel(20, 30), Rgb([0, 0, 255]));
+}
+
+#[test]
+fn key_sequences_time_out_by_event_time() {
+    let brush = |wait_ms| {
+        let mut gui = HeadlessGUI::new(64, 48);
+        gui.press('b').wait(wait_ms).press('h');
+        let status = gui.status();
+        run_gui(&format!("timeout-{wait_ms}"), gui);
+        let brush = status.borrow().brush.clone();
+        brush
+    };
+    assert_eq!(brush(1000).as_deref(), Some("hard"));
+    assert_eq!(brush(2000), None);
+}
+
+#[test]
+fn undo_restores_a_fill() {
+    let fill_tool = |gui: &mut HeadlessGUI| {
+        red_brush(gui)
+            .drag(&[(10.0, 10.0), (30.0, 10.0)])
+            .resize(80, 60)
+            .color(0.0, 0.0, 1.0, 1.0)
+            .press('f');
+    };
+    let filled = assert_undo_restores("fill", fill_tool, |gui| {
+        gui.drag(&[(20.0, 25.0)]);
+    });
+    assert_eq!(*filled.get_pixel(20, 25), Rgb([0, 0, 255]));
+    assert_eq!(*filled.get_pixel(20, 10), RED);
+}
+
+#[test]
+fn clipboard_images_are_pasted() {
+    let clipboard = |gui: &mut HeadlessGUI| {
+        gui.clipboard_image(RgbaImage::from_pixel(8, 8, Rgba([0, 255, 0, 255])))
+            .resize(80, 60);
+    };
+    let blank = run("no-paste", clipboard);
+    let pasted = assert_undo_restores("paste", clipboard, |gui| {
+        gui.press('P').key(enter());
+    });
+    assert_eq!(*pasted.get_pixel(0, 0), Rgb([0, 255, 0]));
+    assert_eq!(*pasted.get_pixel(7, 7), Rgb([0, 255, 0]));
+    assert_eq!(*pasted.get_pixel(8, 8), *blank.get_pixel(8, 8));
+}
+
+#[test]
+fn undo_restores_a_transform() {
+    let stroke = |gui: &mut HeadlessGUI| {
+        red_brush(gui)
+            .drag(&[(4.0, 10.0), (28.0, 10.0)])
+            .resize(80, 60);
+    };
+    let rotated = assert_undo_restores("rotate", stroke, |gui| {
+        gui.press('t').command("rotate 90").key(enter());
+    });
+    // The horizontal stroke turns vertical around the image center.
+    assert_eq!(*rotated.get_pixel(21, 16), RED);
+    assert_ne!(*rotated.get_pixel(4, 10), RED);
+}
+
+#[test]
+fn bad_recording_paths_fail_without_panicking() {
+    let dir = common::test_dir("bad-recording");
+    let args = teal_main::Args {
+        fname: dir.join("image.png").display().to_string(),
+        dims: Some((32, 32)),
+        record: Some(dir.join("missing").join("events.jsonl")),
+    };
+    let code = teal_main::run(args, common::config(), HeadlessGUI::new(64, 48));
+    assert_eq!(code, std::process::ExitCode::FAILURE);
+}
@@ -0,0 +1,339 @@
+//! Copying, cutting and pasting image regions.
+//!
+//! Copied pixels are kept in a `Clip`. Pasting a clip creates a
+//! `FloatingPaste`: the pixels are drawn onto a layer right away, but the
+//! layer pixels under them are kept, so the paste can still be moved around
+//! with a `FloatingDrag` and then either committed as a single undoable
+//! operation or cancelled.
+use crate::{DragHandler, PixelOp, RectOp, StrokePoint};
+use std::cell::RefCell;
+use std::rc::Rc;
+use teal_base::{Image, ImageView, LayerId, LayerStack, PaintMode, Selection};
+
+/// Pixels copied from an image.
+#[derive(Clone, Debug)]
+pub struct Clip {
+    /// Copied pixels, with the alpha scaled by the selection they were copied
+    /// with.
+    image: Image,
+
+    /// Image coordinates of the top left corner the pixels were copied from.
+    origin: (u32, u32),
+}
+
+impl Clip {
+    /// Create a clip from a whole image, such as one from the system
+    /// clipboard, placed at the top left corner.
+    pub fn new(image: Image) -> Clip {
+        Clip {
+            image,
+            origin: (0, 0),
+        }
+    }
+
+    /// Copy the selected pixels of the active layer, or the whole layer if
+    /// there is no selection.
+    pub fn copy(layers: &LayerStack) -> Clip {
+        Clip::extract(layers.active_image(), layers.selection())
+    }
+
+    /// Copy the selected pixels of the composite of the visible layers, or
+    /// the whole composite if there is no selection.
+    pub fn copy_merged(layers: &LayerStack) -> Clip {
+        Clip::extract(&layers.flatten(), layers.selection())
+    }
+
+    /// Copy the selected pixels of the active layer like `copy()`, then
+    /// erase them. Returns the clip and the operation to undo the erase, or
+    /// None for the operation if no pixel changed.
+    pub fn cut(layers: &mut LayerStack) -> (Clip, Option<RectOp>) {
+        let clip = Clip::copy(layers);
+        let layer = layers.active().id;
+        let (origin_x, origin_y) = clip.origin;
+        let (width, height) = clip.image.dimensions();
+        let mut op = RectOp::new(layer, origin_x, origin_y, width, height);
+        let (active, selection) = layers
+            .get_mut_with_selection(layer)
+            .expect("missing active layer");
+        let image = &mut active.image;
+        for y in origin_y..origin_y + height {
+            for x in origin_x..origin_x + width {
+                let mask = selection.map_or(1.0, |selection| selection.value(x, y));
+                let pixel = image.get_pixel_mut(x, y);
+                if mask <= 0.0 || pixel.0[3] <= 0.0 {
+                    continue;
+                }
+                op.keep(x, y, *pixel);
+                pixel.0[3] *= 1.0 - mask;
+            }
+        }
+        (clip, (!op.is_empty()).then_some(op))
+    }
+
+    /// Copy the pixels of an image inside the bounds of a selection, scaling
+    /// their alpha by the selection.
+    fn extract(image: &Image, selection: Option<&Selection>) -> Clip {
+        let Some((x, y, width, height)) = selection.and_then(|selection| selection.bounds()) else {
+            return Clip::new(image.clone());
+        };
+        let selection = selection.unwrap();
+        let image = Image::from_fn(width, height, |clip_x, clip_y| {
+            let mut pixel = *image.get_pixel(x + clip_x, y + clip_y);
+            pixel.0[3] *= selection.value(x + clip_x, y + clip_y);
+            pixel
+        });
+        Clip {
+            image,
+            origin: (x, y),
+        }
+    }
+
+    /// Copied pixels.
+    pub fn image(&self) -> &Image {
+        &self.image
+    }
+
+    /// Image coordinates of the top left corner the pixels were copied from.
+    pub fn origin(&self) -> (u32, u32) {
+        self.origin
+    }
+
+    /// Width and height of the clip.
+    pub fn dimensions(&self) -> (u32, u32) {
+        self.image.dimensions()
+    }
+}
+
+/// A pasted clip floating over a layer until it is committed or cancelled.
+pub struct FloatingPaste {
+    clip: Clip,
+
+    /// Layer that the clip is pasted onto.
+    layer: LayerId,
+
+    /// Image coordinates of the top left corner of the clip.
+    position: (f64, f64),
+
+    /// Original pixels of the layer under the clip, or None if the clip is
+    /// off the layer.
+    under: Option<RectOp>,
+}
+
+impl FloatingPaste {
+    /// Paste a clip onto the active layer, at the position it was copied
+    /// from.
+    pub fn new(layers: &mut LayerStack, clip: Clip) -> FloatingPaste {
+        let (x, y) = clip.origin;
+        let mut paste = FloatingPaste {
+            clip,
+            layer: layers.active().id,
+            position: (x as f64, y as f64),
+            under: None,
+        };
+        paste.place(&mut layers.active_mut().image);
+        paste
+    }
+
+    /// Layer that the clip is pasted onto.
+    pub fn layer(&self) -> LayerId {
+        self.layer
+    }
+
+    /// Move the clip by (dx, dy) image pixels on the image of its layer.
+    ///
+    /// The clip is drawn at whole pixel positions, so it is never resampled.
+    pub fn translate(&mut self, image: &mut Image, dx: f64, dy: f64) {
+        self.lift(image);
+        self.position.0 += dx;
+        self.position.1 += dy;
+        self.place(image);
+    }
+
+    /// Keep the pasted pixels, returning the operation to undo the paste, or
+    /// None if no pixel of the layer is covered.
+    pub fn commit(self) -> Option<RectOp> {
+        self.under.filter(|under| !under.is_empty())
+    }
+
+    /// Remove the pasted pixels, restoring the layer.
+    pub fn cancel(mut self, layers: &mut LayerStack) {
+        if let Some(layer) = layers.get_mut(self.layer) {
+            self.lift(&mut layer.image);
+        }
+    }
+
+    /// Restore the original pixels under the clip.
+    fn lift(&mut self, image: &mut Image) {
+        if let Some(under) = self.under.take() {
+            for (x, y, pixel) in under.kept() {
+                image.put_pixel(x, y, *pixel);
+            }
+        }
+    }
+
+    /// Draw the clip over the image at its position, keeping the original
+    /// pixels. Pixels outside the image are left out, but stay in the clip.
+    fn place(&mut self, image: &mut Image) {
+        let left = self.position.0.round() as i64;
+        let top = self.position.1.round() as i64;
+        let (width, height) = self.clip.dimensions();
+
+        // Part of the clip on the image, in image coordinates.
+        let x0 = left.clamp(0, image.width() as i64) as u32;
+        let y0 = top.clamp(0, image.height() as i64) as u32;
+        let x1 = (left + width as i64).clamp(0, image.width() as i64) as u32;
+        let y1 = (top + height as i64).clamp(0, image.height() as i64) as u32;
+        if x0 >= x1 || y0 >= y1 {
+            return;
+        }
+
+        let mut under = RectOp::new(self.layer, x0, y0, x1 - x0, y1 - y0);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let clip_x = (x as i64 - left) as u32;
+                let clip_y = (y as i64 - top) as u32;
+                let source = self.clip.image.get_pixel(clip_x, clip_y);
+                if source.0[3] <= 0.0 {
+                    continue;
+                }
+                let pixel = image.get_pixel_mut(x, y);
+                under.keep(x, y, *pixel);
+                *pixel = PaintMode::Normal.composite(pixel, source, 1.0);
+            }
+        }
+        self.under = Some(under);
+    }
+}
+
+/// Drag handler moving a floating paste.
+pub struct FloatingDrag {
+    paste: Rc<RefCell<FloatingPaste>>,
+}
+
+impl FloatingDrag {
+    /// Create a drag handler moving a floating paste, which must be on the
+    /// layer being dragged on.
+    pub fn new(paste: Rc<RefCell<FloatingPaste>>) -> FloatingDrag {
+        FloatingDrag { paste }
+    }
+}
+
+impl DragHandler for FloatingDrag {
+    /// Move the paste along the line. The selection doesn't mask the paste.
+    fn handle_line(
+        &mut self,
+        image: &mut Image,
+        _selection: Option<&Selection>,
+        _image_view: &mut ImageView,
+        a: StrokePoint,
+        b: StrokePoint,
+    ) {
+        self.paste
+            .borrow_mut()
+            .translate(image, b.x - a.x, b.y - a.y);
+    }
+
+    /// Moves are part of the paste, which is undone as a whole once it is
+    /// committed.
+    fn to_op(&self, _layer: LayerId) -> Option<PixelOp> {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Operation;
+    use teal_base::ImagePixel;
+
+    /// A 4x4 layer with a different opaque color in each pixel.
+    fn layers() -> LayerStack {
+        LayerStack::new(Image::from_fn(4, 4, |x, y| {
+            ImagePixel::from([x as f32 / 4.0, y as f32 / 4.0, 0.5, 1.0])
+        }))
+    }
+
+    /// Select (1, 1) fully and (2, 1) by half.
+    fn select(layers: &mut LayerStack) {
+        let mut mask = vec![0.0; 16];
+        mask[5] = 1.0;
+        mask[6] = 0.5;
+        layers.set_selection(Selection::from_mask(4, 4, mask));
+    }
+
+    #[test]
+    fn copy_scales_alpha_by_the_selection() {
+        let mut layers = layers();
+        select(&mut layers);
+        let clip = Clip::copy(&layers);
+        let image = layers.active_image();
+        assert_eq!(clip.origin(), (1, 1));
+        assert_eq!(clip.dimensions(), (2, 1));
+        assert_eq!(clip.image().get_pixel(0, 0), image.get_pixel(1, 1));
+        let mut half = *image.get_pixel(2, 1);
+        half.0[3] = 0.5;
+        assert_eq!(*clip.image().get_pixel(1, 0), half);
+    }
+
+    #[test]
+    fn cut_erases_by_the_selection_and_undoes() {
+        let mut layers = layers();
+        let original = layers.active_image().clone();
+        select(&mut layers);
+        let copied = Clip::copy(&layers);
+        let (clip, op) = Clip::cut(&mut layers);
+        assert_eq!(clip.image(), copied.image());
+        assert_eq!(clip.origin(), copied.origin());
+        let image = layers.active_image();
+        assert_eq!(image.get_pixel(1, 1).0[3], 0.0);
+        assert_eq!(image.get_pixel(2, 1).0[3], 0.5);
+        assert_eq!(image.get_pixel(0, 1), original.get_pixel(0, 1));
+
+        op.unwrap().undo(&mut layers);
+        assert_eq!(*layers.active_image(), original);
+    }
+
+    #[test]
+    fn translated_paste_commits_as_one_operation() {
+        let mut layers = layers();
+        let original = layers.active_image().clone();
+        select(&mut layers);
+        let clip = Clip::copy(&layers);
+        layers.set_selection(None);
+
+        let mut paste = FloatingPaste::new(&mut layers, clip.clone());
+        paste.translate(&mut layers.active_mut().image, 1.0, 1.0);
+        let pasted = layers.active_image().clone();
+        assert_eq!(pasted.get_pixel(1, 1), original.get_pixel(1, 1));
+        assert_eq!(pasted.get_pixel(2, 2), clip.image().get_pixel(0, 0));
+        let under = original.get_pixel(3, 2);
+        let over = clip.image().get_pixel(1, 0);
+        assert_eq!(
+            *pasted.get_pixel(3, 2),
+            PaintMode::Normal.composite(under, over, 1.0)
+        );
+
+        let mut op = paste.commit().unwrap();
+        assert!(op.kept().all(|(x, y, _)| x >= 2 && y >= 2));
+        op.undo(&mut layers);
+        assert_eq!(*layers.active_image(), original);
+        op.redo(&mut layers);
+        assert_eq!(*layers.active_image(), pasted);
+    }
+
+    #[test]
+    fn cancelled_paste_restores_the_layer() {
+        let mut layers = layers();
+        let original = layers.active_image().clone();
+        let clip = Clip::new(Image::from_pixel(
+            2,
+            2,
+            ImagePixel::from([1.0, 0.0, 0.0, 0.5]),
+        ));
+        let mut paste = FloatingPaste::new(&mut layers, clip);
+        paste.translate(&mut layers.active_mut().image, 2.6, 1.2);
+        assert_ne!(*layers.active_image(), original);
+        paste.cancel(&mut layers);
+        assert_eq!(*layers.active_image(), original);
+    }
+}
@@ -0,0 +1,80 @@
+//! Brush dynamics: stamp spacing and randomization.
+
+/// How the stamps of a brush stroke are placed and varied.
+///
+/// Fractions are from 0.0 - 1.0 unless noted. Random variations come from a
+/// generator seeded by `seed` and the start of the stroke, so replaying the
+/// same input paints the same stroke.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Dynamics {
+    /// Distance between stamps as a fraction of the brush diameter.
+    pub spacing: f32,
+
+    /// Largest random reduction of the size of each stamp.
+    pub size_jitter: f32,
+
+    /// Largest random reduction of the opacity of each stamp.
+    pub opacity_jitter: f32,
+
+    /// Turn the brush to follow the direction of the stroke.
+    pub follow_stroke: bool,
+
+    /// Largest random offset of each stamp as a fraction of the brush
+    /// diameter (may be above 1.0).
+    pub scatter: f32,
+
+    /// Most that a stroke covers a pixel, before pressure.
+    pub opacity: f32,
+
+    /// How much a single stamp adds towards the opacity, before pressure.
+    pub flow: f32,
+
+    /// Seed of the random variations.
+    pub seed: u64,
+}
+
+impl Default for Dynamics {
+    /// Stamps every tenth of the diameter, without randomization.
+    fn default() -> Dynamics {
+        Dynamics {
+            spacing: 0.1,
+            size_jitter: 0.0,
+            opacity_jitter: 0.0,
+            follow_stroke: false,
+            scatter: 0.0,
+            opacity: 1.0,
+            flow: 1.0,
+            seed: 0,
+        }
+    }
+}
+
+/// Small deterministic random number generator (SplitMix64).
+#[derive(Clone, Debug)]
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    /// Create a generator from a seed.
+    pub(crate) fn new(seed: u64) -> Rng {
+        Rng(seed)
+    }
+
+    /// Next random 64 bit value.
+    fn next_u64(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Next random value from 0.0 (inclusive) to 1.0 (exclusive).
+    pub(crate) fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+
+    /// Next random value from -1.0 to 1.0.
+    pub(crate) fn next_signed(&mut self) -> f32 {
+        self.next_f32() * 2.0 - 1.0
+    }
+}
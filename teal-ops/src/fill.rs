@@ -0,0 +1,148 @@
+//! Bucket fill.
+use crate::region::{ColorMetric, Region};
+use crate::RectOp;
+use teal_base::{ImagePixel, LayerStack, PaintMode};
+
+/// Image that a bucket fill or magic wand compares colors in.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum FillSource {
+    /// The active layer
+    #[default]
+    Layer,
+
+    /// The composite of the visible layers
+    Composite,
+}
+
+/// A bucket fill of the active layer, starting from a pixel.
+///
+/// Pixels are filled if their color is within the tolerance of the start
+/// pixel, either anywhere in the image or only where connected to the start
+/// pixel. Colors are compared with premultiplied alpha, so all fully
+/// transparent pixels match each other. Only selected pixels are filled, if
+/// there is a selection.
+#[derive(Clone, Debug)]
+pub struct BucketFill {
+    /// Fill color.
+    color: ImagePixel,
+
+    /// Largest difference of any channel from the start pixel that is still
+    /// filled, from 0.0 - 1.0.
+    tolerance: f32,
+
+    /// Only fill pixels connected to the start pixel.
+    contiguous: bool,
+
+    /// Partly fill the pixels around the filled area by how close they are
+    /// to matching, to keep anti-aliased edges smooth.
+    antialias: bool,
+
+    /// Image that colors are compared in.
+    source: FillSource,
+
+    /// How the color is blended onto the layer.
+    mode: PaintMode,
+}
+
+impl BucketFill {
+    /// Create a contiguous, anti-aliased fill of the active layer with a
+    /// color.
+    pub fn new(color: ImagePixel) -> BucketFill {
+        BucketFill {
+            color,
+            tolerance: 0.1,
+            contiguous: true,
+            antialias: true,
+            source: FillSource::Layer,
+            mode: PaintMode::Normal,
+        }
+    }
+
+    /// Set the color tolerance, from 0.0 - 1.0.
+    pub fn with_tolerance(mut self, tolerance: f32) -> BucketFill {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Set whether only pixels connected to the start pixel are filled.
+    pub fn with_contiguous(mut self, contiguous: bool) -> BucketFill {
+        self.contiguous = contiguous;
+        self
+    }
+
+    /// Set whether the edges of the filled area are anti-aliased.
+    pub fn with_antialias(mut self, antialias: bool) -> BucketFill {
+        self.antialias = antialias;
+        self
+    }
+
+    /// Set the image that colors are compared in.
+    pub fn with_source(mut self, source: FillSource) -> BucketFill {
+        self.source = source;
+        self
+    }
+
+    /// Set how the color is blended onto the layer.
+    pub fn with_mode(mut self, mode: PaintMode) -> BucketFill {
+        self.mode = mode;
+        self
+    }
+
+    /// Fill from image coordinates (x, y), returning the operation to undo
+    /// it, or None if no pixel changed.
+    pub fn apply(&self, layers: &mut LayerStack, x: u32, y: u32) -> Option<RectOp> {
+        if x >= layers.width() || y >= layers.height() {
+            return None;
+        }
+        let region = Region {
+            tolerance: self.tolerance,
+            contiguous: self.contiguous,
+            antialias: self.antialias,
+            metric: ColorMetric::Rgba,
+        };
+        let mut coverage = match self.source {
+            FillSource::Layer => region.coverage(layers.active_image(), x, y),
+            FillSource::Composite => region.coverage(&layers.flatten(), x, y),
+        };
+        if let Some(selection) = layers.selection() {
+            for (value, mask) in coverage.iter_mut().zip(selection.mask()) {
+                *value *= mask;
+            }
+        }
+
+        let width = layers.width() as usize;
+        let coords = |index: usize| ((index % width) as u32, (index / width) as u32);
+        let (mut left, mut top) = (u32::MAX, u32::MAX);
+        let (mut right, mut bottom) = (0, 0);
+        for (px, py) in coverage
+            .iter()
+            .enumerate()
+            .filter(|(_, value)| **value > 0.0)
+            .map(|(index, _)| coords(index))
+        {
+            (left, top) = (left.min(px), top.min(py));
+            (right, bottom) = (right.max(px + 1), bottom.max(py + 1));
+        }
+        if left >= right {
+            return None;
+        }
+
+        let layer = layers.active().id;
+        let image = &mut layers.active_mut().image;
+        let mut op = RectOp::new(layer, left, top, right - left, bottom - top);
+        for (index, value) in coverage.into_iter().enumerate() {
+            if value <= 0.0 {
+                continue;
+            }
+            let (px, py) = coords(index);
+            let pixel = image.get_pixel_mut(px, py);
+            let painted = self.mode.composite(pixel, &self.color, value);
+            if painted != *pixel {
+                op.keep(px, py, *pixel);
+                *pixel = painted;
+            }
+        }
+
+        (!op.is_empty()).then_some(op)
+    }
+}
@@ -0,0 +1,260 @@
+//! Undo/redo history bounded by a byte budget.
+use crate::journal::{self, JournalHeader};
+use crate::Operation;
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use teal_base::LayerStack;
+
+/// History entry along with its encoded size.
+struct Entry {
+    op: Box<dyn Operation>,
+    size: usize,
+}
+
+impl Entry {
+    fn new(op: Box<dyn Operation>) -> Entry {
+        let mut data = vec![];
+        op.encode(&mut data);
+        Entry {
+            op,
+            size: data.len(),
+        }
+    }
+}
+
+/// Undo and redo stacks of operations.
+///
+/// The combined encoded size of all operations is kept below a byte budget
+/// by dropping the oldest undo operations first.
+pub struct History {
+    undo: VecDeque<Entry>,
+    redo: VecDeque<Entry>,
+
+    /// Total encoded size of all operations.
+    size: usize,
+
+    /// Maximum total encoded size.
+    budget: usize,
+}
+
+impl History {
+    /// Create an empty history with a byte budget.
+    pub fn new(budget: usize) -> History {
+        History {
+            undo: VecDeque::new(),
+            redo: VecDeque::new(),
+            size: 0,
+            budget,
+        }
+    }
+
+    /// Number of operations that can be undone.
+    pub fn undo_count(&self) -> usize {
+        self.undo.len()
+    }
+
+    /// Number of operations that can be redone.
+    pub fn redo_count(&self) -> usize {
+        self.redo.len()
+    }
+
+    /// Total encoded size of the history in bytes.
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Push a completed operation, discarding anything that could be redone.
+    pub fn push(&mut self, op: Box<dyn Operation>) {
+        for entry in self.redo.drain(..) {
+            self.size -= entry.size;
+        }
+        let entry = Entry::new(op);
+        self.size += entry.size;
+        self.undo.push_back(entry);
+        self.trim();
+    }
+
+    /// Undo the last operation. Returns false if there was nothing to undo.
+    ///
+    /// Operations on layers that are gone are dropped from the history, and
+    /// the next operation is undone instead.
+    pub fn undo(&mut self, layers: &mut LayerStack) -> bool {
+        while let Some(mut entry) = self.undo.pop_back() {
+            if entry.op.undo(layers) {
+                self.redo.push_back(entry);
+                return true;
+            }
+            self.size -= entry.size;
+        }
+        false
+    }
+
+    /// Redo the last undone operation. Returns false if there was nothing to
+    /// redo.
+    ///
+    /// Like `undo()`, operations on layers that are gone are dropped.
+    pub fn redo(&mut self, layers: &mut LayerStack) -> bool {
+        while let Some(mut entry) = self.redo.pop_back() {
+            if entry.op.redo(layers) {
+                self.undo.push_back(entry);
+                return true;
+            }
+            self.size -= entry.size;
+        }
+        false
+    }
+
+    /// Drop the oldest operations until the history fits in the budget.
+    ///
+    /// The most recent operation is always kept, even if it alone exceeds the
+    /// budget.
+    fn trim(&mut self) {
+        while self.size > self.budget && self.undo.len() > 1 {
+            let entry = self.undo.pop_front().unwrap();
+            self.size -= entry.size;
+        }
+    }
+
+    /// Write the history to a journal.
+    pub fn save<W: Write>(&self, w: W, header: &JournalHeader) -> io::Result<()> {
+        let undo: Vec<&dyn Operation> = self.undo.iter().map(|entry| &*entry.op).collect();
+        let redo: Vec<&dyn Operation> = self.redo.iter().map(|entry| &*entry.op).collect();
+        journal::write(w, header, &undo, &redo)
+    }
+
+    /// Load a history from a journal, returning it with the journal header.
+    pub fn load<R: Read>(r: R, budget: usize) -> io::Result<(History, JournalHeader)> {
+        let journal = journal::read(r)?;
+        let undo: VecDeque<Entry> = journal.undo.into_iter().map(Entry::new).collect();
+        let redo: VecDeque<Entry> = journal.redo.into_iter().map(Entry::new).collect();
+        let size = undo.iter().chain(redo.iter()).map(|entry| entry.size).sum();
+        let mut history = History {
+            undo,
+            redo,
+            size,
+            budget,
+        };
+        history.trim();
+        Ok((history, journal.header))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PixelOp;
+    use teal_base::{Image, ImagePixel, LayerId};
+
+    /// An operation storing one pixel at (x, 0).
+    fn op(x: u32) -> Box<dyn Operation> {
+        let undo_pixels = [((x, 0), ImagePixel::from([1.0, 0.0, 0.0, 1.0]))].into();
+        Box::new(PixelOp {
+            layer: LayerId(0),
+            undo_pixels,
+        })
+    }
+
+    fn op_size() -> usize {
+        let mut data = vec![];
+        op(0).encode(&mut data);
+        data.len()
+    }
+
+    #[test]
+    fn oldest_operations_are_trimmed_to_the_budget() {
+        let mut history = History::new(2 * op_size());
+        for x in 0..3 {
+            history.push(op(x));
+        }
+        assert_eq!(history.undo_count(), 2);
+        assert_eq!(history.size(), 2 * op_size());
+
+        // The dropped operation is the oldest one, at (0, 0).
+        let mut layers = LayerStack::new(Image::new(3, 1));
+        while history.undo(&mut layers) {}
+        let image = layers.active_image();
+        assert_eq!(image.get_pixel(0, 0).0, [0.0; 4]);
+        assert_eq!(image.get_pixel(1, 0).0, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(image.get_pixel(2, 0).0, [1.0, 0.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn latest_operation_is_kept_over_budget() {
+        let mut history = History::new(0);
+        history.push(op(0));
+        history.push(op(1));
+        assert_eq!(history.undo_count(), 1);
+    }
+
+    #[test]
+    fn push_discards_redo() {
+        let mut history = History::new(usize::MAX);
+        let mut layers = LayerStack::new(Image::new(3, 1));
+        history.push(op(0));
+        history.push(op(1));
+        assert!(history.undo(&mut layers));
+        assert_eq!(history.redo_count(), 1);
+        history.push(op(2));
+        assert_eq!(history.redo_count(), 0);
+        assert_eq!(history.size(), 2 * op_size());
+    }
+
+    #[test]
+    fn load_trims_to_the_new_budget() {
+        let mut history = History::new(usize::MAX);
+        for x in 0..3 {
+            history.push(op(x));
+        }
+        let header = JournalHeader {
+            width: 3,
+            height: 1,
+            source_hash: 0,
+        };
+        let mut data = vec![];
+        history.save(&mut data, &header).unwrap();
+        let (loaded, loaded_header) = History::load(&data[..], op_size()).unwrap();
+        assert_eq!(loaded_header, header);
+        assert_eq!(loaded.undo_count(), 1);
+    }
+
+    #[test]
+    fn operations_on_removed_layers_are_skipped_after_reload() {
+        let mut layers = LayerStack::new(Image::new(3, 1));
+        let removed = layers.add_layer("removed");
+        let mut history = History::new(usize::MAX);
+        history.push(op(0));
+        let undo_pixels = [((1, 0), ImagePixel::from([0.0, 0.0, 1.0, 1.0]))].into();
+        history.push(Box::new(PixelOp {
+            layer: removed,
+            undo_pixels,
+        }));
+        let header = JournalHeader {
+            width: 3,
+            height: 1,
+            source_hash: 0,
+        };
+        let mut data = vec![];
+        history.save(&mut data, &header).unwrap();
+
+        // The new layer doesn't take the removed layer's id.
+        layers.remove_active();
+        let added = layers.add_layer("added");
+        assert_ne!(added, removed);
+
+        let (mut history, _) = History::load(&data[..], usize::MAX).unwrap();
+        assert!(history.undo(&mut layers));
+        assert_eq!(history.undo_count(), 0);
+        assert_eq!(history.redo_count(), 1);
+        assert_eq!(history.size(), op_size());
+        let background = layers.layers()[0].image.get_pixel(0, 0);
+        assert_eq!(background.0, [1.0, 0.0, 0.0, 1.0]);
+        assert!(layers
+            .get(added)
+            .unwrap()
+            .image
+            .pixels()
+            .all(|p| p.0 == [0.0; 4]));
+        assert!(!history.undo(&mut layers));
+        assert!(history.redo(&mut layers));
+    }
+}
@@ -0,0 +1,678 @@
+//! Compact binary encoding of operations for the on-disk undo journal.
+//!
+//! Pixel operations are stored as blocks of horizontally adjacent pixels.
+//! Block positions are delta-encoded against the previous block and each
+//! pixel channel is stored as the XOR of its bits with the previous pixel's,
+//! written as a variable-length integer. Neighbouring pixels touched by a
+//! brush are usually similar, so most channels shrink to one or two bytes.
+//! Resize operations store the cropped pixels of each layer the same way.
+//! Rectangle operations store their rectangle and changed pixel bitmask,
+//! followed by the changed pixels in row-major order, encoded the same way.
+//! Layer operations store a removed layer's properties and all of its pixels
+//! in row-major order.
+use crate::{LayerOp, LayerPropsOp, Operation, PixelOp, RectOp, ResizeOp};
+use std::collections::HashMap;
+use std::io::{self, Read, Write};
+use teal_base::{BlendMode, Image, ImagePixel, Layer, LayerId};
+
+/// Journal file magic bytes.
+const MAGIC: &[u8; 8] = b"TEALJRNL";
+
+/// Journal format version.
+const VERSION: u32 = 1;
+
+/// Operation tag for `PixelOp`.
+pub(crate) const TAG_PIXEL_OP: u8 = 1;
+
+/// Operation tag for `ResizeOp`.
+pub(crate) const TAG_RESIZE_OP: u8 = 2;
+
+/// Operation tag for `RectOp`.
+pub(crate) const TAG_RECT_OP: u8 = 3;
+
+/// Operation tag for `LayerOp`.
+pub(crate) const TAG_LAYER_OP: u8 = 4;
+
+/// Operation tag for `LayerPropsOp`.
+pub(crate) const TAG_LAYER_PROPS_OP: u8 = 5;
+
+/// Journal header identifying the image state that the history ends in.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct JournalHeader {
+    /// Image width.
+    pub width: u32,
+
+    /// Image height.
+    pub height: u32,
+
+    /// Hash of the saved image file the journal belongs to.
+    pub source_hash: u64,
+}
+
+/// Hash a byte slice (FNV-1a), used to tie a journal to a saved file.
+pub fn hash_bytes(data: &[u8]) -> u64 {
+    let mut hash: u64 = 0xcbf29ce484222325;
+    for byte in data {
+        hash ^= *byte as u64;
+        hash = hash.wrapping_mul(0x100000001b3);
+    }
+    hash
+}
+
+/// Write a journal containing undo and redo operations, oldest first.
+pub fn write<W: Write>(
+    mut w: W,
+    header: &JournalHeader,
+    undo: &[&dyn Operation],
+    redo: &[&dyn Operation],
+) -> io::Result<()> {
+    let mut out = vec![];
+    out.extend_from_slice(MAGIC);
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    out.extend_from_slice(&header.width.to_le_bytes());
+    out.extend_from_slice(&header.height.to_le_bytes());
+    out.extend_from_slice(&header.source_hash.to_le_bytes());
+    for ops in [undo, redo] {
+        write_varint(&mut out, ops.len() as u64);
+        for op in ops {
+            let mut data = vec![];
+            op.encode(&mut data);
+            write_varint(&mut out, data.len() as u64);
+            out.extend_from_slice(&data);
+        }
+    }
+    w.write_all(&out)
+}
+
+/// Decoded journal contents.
+pub struct Journal {
+    pub header: JournalHeader,
+    pub undo: Vec<Box<dyn Operation>>,
+    pub redo: Vec<Box<dyn Operation>>,
+}
+
+/// Read a journal.
+pub fn read<R: Read>(mut r: R) -> io::Result<Journal> {
+    let mut data = vec![];
+    r.read_to_end(&mut data)?;
+    let mut data = &data[..];
+
+    if take(&mut data, MAGIC.len())? != MAGIC {
+        return Err(invalid("not a teal journal"));
+    }
+    let version = u32::from_le_bytes(take(&mut data, 4)?.try_into().unwrap());
+    if version != VERSION {
+        return Err(invalid("unsupported journal version"));
+    }
+    let width = u32::from_le_bytes(take(&mut data, 4)?.try_into().unwrap());
+    let height = u32::from_le_bytes(take(&mut data, 4)?.try_into().unwrap());
+    let source_hash = u64::from_le_bytes(take(&mut data, 8)?.try_into().unwrap());
+
+    let mut lists = vec![];
+    for _ in 0..2 {
+        let count = read_varint(&mut data)?;
+        let mut ops = vec![];
+        for _ in 0..count {
+            let len = read_varint(&mut data)? as usize;
+            let mut op_data = take(&mut data, len)?;
+            ops.push(decode(&mut op_data)?);
+        }
+        lists.push(ops);
+    }
+    let redo = lists.pop().unwrap();
+    let undo = lists.pop().unwrap();
+
+    Ok(Journal {
+        header: JournalHeader {
+            width,
+            height,
+            source_hash,
+        },
+        undo,
+        redo,
+    })
+}
+
+/// Decode a single operation.
+fn decode(data: &mut &[u8]) -> io::Result<Box<dyn Operation>> {
+    match take(data, 1)?[0] {
+        TAG_PIXEL_OP => Ok(Box::new(decode_pixel_op(data)?)),
+        TAG_RESIZE_OP => Ok(Box::new(decode_resize_op(data)?)),
+        TAG_RECT_OP => Ok(Box::new(decode_rect_op(data)?)),
+        TAG_LAYER_OP => Ok(Box::new(decode_layer_op(data)?)),
+        TAG_LAYER_PROPS_OP => Ok(Box::new(decode_layer_props_op(data)?)),
+        _ => Err(invalid("unknown operation tag")),
+    }
+}
+
+/// Encode a pixel operation.
+pub(crate) fn encode_pixel_op(op: &PixelOp, out: &mut Vec<u8>) {
+    out.push(TAG_PIXEL_OP);
+    write_varint(out, op.layer.0 as u64);
+
+    let mut coords: Vec<&(u32, u32)> = op.undo_pixels.keys().collect();
+    coords.sort_by_key(|(x, y)| (*y, *x));
+
+    // Group the pixels into runs of adjacent pixels in the same row.
+    let mut blocks: Vec<(u32, u32, u32)> = vec![];
+    for (x, y) in coords {
+        match blocks.last_mut() {
+            Some((bx, by, len)) if *by == *y && *bx + *len == *x => *len += 1,
+            _ => blocks.push((*x, *y, 1)),
+        }
+    }
+
+    write_varint(out, blocks.len() as u64);
+    let mut last_y = 0;
+    let mut last_end = 0;
+    for (x, y, len) in blocks.iter() {
+        write_varint(out, (*y - last_y) as u64);
+        if *y != last_y {
+            last_end = 0;
+        }
+        write_varint(out, (*x - last_end) as u64);
+        write_varint(out, *len as u64);
+        last_y = *y;
+        last_end = *x + *len;
+    }
+
+    let mut prev = [0u32; 4];
+    for (x, y, len) in blocks {
+        for x in x..x + len {
+            write_pixel(out, &op.undo_pixels[&(x, y)], &mut prev);
+        }
+    }
+}
+
+/// Decode a pixel operation (after the tag).
+fn decode_pixel_op(data: &mut &[u8]) -> io::Result<PixelOp> {
+    let layer = LayerId(read_u32(data)?);
+
+    let block_count = read_varint(data)?;
+    let mut blocks = vec![];
+    let mut last_y = 0;
+    let mut last_end = 0;
+    for _ in 0..block_count {
+        let y = checked_add(last_y, read_u32(data)?)?;
+        if y != last_y {
+            last_end = 0;
+        }
+        let x = checked_add(last_end, read_u32(data)?)?;
+        let len = read_u32(data)?;
+        blocks.push((x, y, len));
+        last_y = y;
+        last_end = checked_add(x, len)?;
+    }
+
+    let mut undo_pixels = HashMap::new();
+    let mut prev = [0u32; 4];
+    for (x, y, len) in blocks {
+        for x in x..x + len {
+            undo_pixels.insert((x, y), read_pixel(data, &mut prev)?);
+        }
+    }
+
+    Ok(PixelOp { layer, undo_pixels })
+}
+
+/// Encode a rectangle operation.
+pub(crate) fn encode_rect_op(op: &RectOp, out: &mut Vec<u8>) {
+    out.push(TAG_RECT_OP);
+    write_varint(out, op.layer.0 as u64);
+    for value in [op.origin.0, op.origin.1, op.dims.0, op.dims.1] {
+        write_varint(out, value as u64);
+    }
+    out.extend_from_slice(&op.mask);
+    let mut prev = [0u32; 4];
+    for (_, _, pixel) in op.kept() {
+        write_pixel(out, pixel, &mut prev);
+    }
+}
+
+/// Decode a rectangle operation (after the tag).
+fn decode_rect_op(data: &mut &[u8]) -> io::Result<RectOp> {
+    let layer = LayerId(read_u32(data)?);
+    let (x, y) = (read_u32(data)?, read_u32(data)?);
+    let (width, height) = (read_u32(data)?, read_u32(data)?);
+    checked_add(x, width)?;
+    checked_add(y, height)?;
+
+    // The mask is read before allocating the pixels, so a corrupt size can't
+    // allocate more than the journal holds.
+    let len = width as u64 * height as u64;
+    let mask = take(
+        data,
+        usize::try_from(len.div_ceil(8)).map_err(|_| invalid("value out of range"))?,
+    )?;
+    let mut op = RectOp::new(layer, x, y, width, height);
+    op.mask.copy_from_slice(mask);
+    let mut prev = [0u32; 4];
+    for index in 0..op.pixels.len() {
+        if crate::bit(mask, index) {
+            op.pixels[index] = read_pixel(data, &mut prev)?;
+        }
+    }
+    Ok(op)
+}
+
+/// Encode a resize operation.
+pub(crate) fn encode_resize_op(op: &ResizeOp, out: &mut Vec<u8>) {
+    out.push(TAG_RESIZE_OP);
+    write_varint(out, op.dims.0 as u64);
+    write_varint(out, op.dims.1 as u64);
+    write_varint(out, op.cropped.len() as u64);
+    for pixel_op in &op.cropped {
+        encode_pixel_op(pixel_op, out);
+    }
+}
+
+/// Decode a resize operation (after the tag).
+fn decode_resize_op(data: &mut &[u8]) -> io::Result<ResizeOp> {
+    let dims = (read_u32(data)?, read_u32(data)?);
+    let count = read_varint(data)?;
+    let mut cropped = vec![];
+    for _ in 0..count {
+        if take(data, 1)?[0] != TAG_PIXEL_OP {
+            return Err(invalid("invalid resize operation"));
+        }
+        cropped.push(decode_pixel_op(data)?);
+    }
+    Ok(ResizeOp { dims, cropped })
+}
+
+/// Encode a layer operation.
+pub(crate) fn encode_layer_op(op: &LayerOp, out: &mut Vec<u8>) {
+    out.push(TAG_LAYER_OP);
+    write_varint(out, op.index as u64);
+    write_varint(out, op.id.0 as u64);
+    let layer = match &op.layer {
+        Some(layer) => layer,
+        None => {
+            out.push(0);
+            return;
+        }
+    };
+    out.push(1);
+    write_varint(out, layer.name.len() as u64);
+    out.extend_from_slice(layer.name.as_bytes());
+    write_layer_props(out, layer.opacity, layer.visible, layer.blend_mode);
+    out.push(layer.locked as u8);
+    write_varint(out, layer.image.width() as u64);
+    write_varint(out, layer.image.height() as u64);
+    let mut prev = [0u32; 4];
+    for pixel in layer.image.pixels() {
+        write_pixel(out, pixel, &mut prev);
+    }
+}
+
+/// Decode a layer operation (after the tag).
+fn decode_layer_op(data: &mut &[u8]) -> io::Result<LayerOp> {
+    let index = read_u32(data)? as usize;
+    let id = LayerId(read_u32(data)?);
+    if take(data, 1)?[0] == 0 {
+        return Ok(LayerOp {
+            index,
+            id,
+            layer: None,
+        });
+    }
+    let len = read_u32(data)? as usize;
+    let name =
+        String::from_utf8(take(data, len)?.to_vec()).map_err(|_| invalid("invalid layer name"))?;
+    let (opacity, visible, blend_mode) = read_layer_props(data)?;
+    let locked = read_bool(data)?;
+    let (width, height) = (read_u32(data)?, read_u32(data)?);
+
+    // Every pixel takes at least four bytes, so a corrupt size can't
+    // allocate more than the journal holds.
+    if (data.len() as u64) < width as u64 * height as u64 * 4 {
+        return Err(invalid("truncated journal"));
+    }
+    let mut image = Image::new(width, height);
+    let mut prev = [0u32; 4];
+    for pixel in image.pixels_mut() {
+        *pixel = read_pixel(data, &mut prev)?;
+    }
+    Ok(LayerOp {
+        index,
+        id,
+        layer: Some(Layer {
+            id,
+            name,
+            image,
+            opacity,
+            visible,
+            locked,
+            blend_mode,
+        }),
+    })
+}
+
+/// Encode a layer properties operation.
+pub(crate) fn encode_layer_props_op(op: &LayerPropsOp, out: &mut Vec<u8>) {
+    out.push(TAG_LAYER_PROPS_OP);
+    write_varint(out, op.layer.0 as u64);
+    write_layer_props(out, op.opacity, op.visible, op.blend_mode);
+}
+
+/// Decode a layer properties operation (after the tag).
+fn decode_layer_props_op(data: &mut &[u8]) -> io::Result<LayerPropsOp> {
+    let layer = LayerId(read_u32(data)?);
+    let (opacity, visible, blend_mode) = read_layer_props(data)?;
+    Ok(LayerPropsOp {
+        layer,
+        opacity,
+        visible,
+        blend_mode,
+    })
+}
+
+/// Write the opacity, visibility and blend mode (as its index in
+/// `BlendMode::ALL`) of a layer.
+fn write_layer_props(out: &mut Vec<u8>, opacity: f32, visible: bool, blend_mode: BlendMode) {
+    write_varint(out, opacity.to_bits() as u64);
+    out.push(visible as u8);
+    let blend_index = BlendMode::ALL
+        .iter()
+        .position(|mode| *mode == blend_mode)
+        .unwrap();
+    out.push(blend_index as u8);
+}
+
+/// Read the layer properties written by `write_layer_props()`.
+fn read_layer_props(data: &mut &[u8]) -> io::Result<(f32, bool, BlendMode)> {
+    let opacity = f32::from_bits(read_u32(data)?);
+    let visible = read_bool(data)?;
+    let blend_mode = *BlendMode::ALL
+        .get(take(data, 1)?[0] as usize)
+        .ok_or_else(|| invalid("invalid blend mode"))?;
+    Ok((opacity, visible, blend_mode))
+}
+
+fn read_bool(data: &mut &[u8]) -> io::Result<bool> {
+    match take(data, 1)?[0] {
+        0 => Ok(false),
+        1 => Ok(true),
+        _ => Err(invalid("invalid flag")),
+    }
+}
+
+/// Write a pixel as the XOR of its channel bits with the previous pixel's.
+fn write_pixel(out: &mut Vec<u8>, pixel: &ImagePixel, prev: &mut [u32; 4]) {
+    for (channel, prev) in pixel.0.iter().zip(prev.iter_mut()) {
+        let bits = channel.to_bits();
+        write_varint(out, (bits ^ *prev) as u64);
+        *prev = bits;
+    }
+}
+
+/// Read a pixel written by `write_pixel()`.
+fn read_pixel(data: &mut &[u8], prev: &mut [u32; 4]) -> io::Result<ImagePixel> {
+    let mut pixel = [0.0; 4];
+    for (channel, prev) in pixel.iter_mut().zip(prev.iter_mut()) {
+        let bits = read_u32(data)? ^ *prev;
+        *channel = f32::from_bits(bits);
+        *prev = bits;
+    }
+    Ok(ImagePixel::from(pixel))
+}
+
+fn invalid(msg: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> io::Result<&'a [u8]> {
+    if data.len() < len {
+        return Err(invalid("truncated journal"));
+    }
+    let (head, tail) = data.split_at(len);
+    *data = tail;
+    Ok(head)
+}
+
+fn write_varint(out: &mut Vec<u8>, mut value: u64) {
+    while value >= 0x80 {
+        out.push((value as u8) | 0x80);
+        value >>= 7;
+    }
+    out.push(value as u8);
+}
+
+fn read_varint(data: &mut &[u8]) -> io::Result<u64> {
+    let mut value = 0;
+    let mut shift = 0;
+    loop {
+        let byte = take(data, 1)?[0];
+        if shift >= 64 {
+            return Err(invalid("varint overflow"));
+        }
+        value |= ((byte & 0x7f) as u64) << shift;
+        if byte & 0x80 == 0 {
+            return Ok(value);
+        }
+        shift += 7;
+    }
+}
+
+fn checked_add(a: u32, b: u32) -> io::Result<u32> {
+    a.checked_add(b)
+        .ok_or_else(|| invalid("value out of range"))
+}
+
+fn read_u32(data: &mut &[u8]) -> io::Result<u32> {
+    u32::try_from(read_varint(data)?).map_err(|_| invalid("value out of range"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use teal_base::{Image, LayerStack};
+
+    fn pixel_op(pixels: &[((u32, u32), [f32; 4])]) -> PixelOp {
+        PixelOp {
+            layer: LayerId(0),
+            undo_pixels: pixels
+                .iter()
+                .map(|(coords, pixel)| (*coords, ImagePixel::from(*pixel)))
+                .collect(),
+        }
+    }
+
+    fn header() -> JournalHeader {
+        JournalHeader {
+            width: 4,
+            height: 3,
+            source_hash: hash_bytes(b"image"),
+        }
+    }
+
+    #[test]
+    fn round_trip() {
+        let op = pixel_op(&[
+            ((0, 0), [1.0, 0.0, 0.0, 1.0]),
+            ((1, 0), [0.5, 0.25, 0.0, 0.75]),
+            ((3, 2), [0.0, 0.0, 1.0, 0.5]),
+        ]);
+        let resize = ResizeOp {
+            dims: (2, 2),
+            cropped: vec![pixel_op(&[((3, 1), [0.0, 1.0, 0.0, 1.0])])],
+        };
+        let mut data = vec![];
+        write(&mut data, &header(), &[&op, &resize], &[&op]).unwrap();
+        let journal = read(&data[..]).unwrap();
+
+        assert_eq!(journal.header, header());
+        assert_eq!(journal.undo.len(), 2);
+        assert_eq!(journal.redo.len(), 1);
+        for (decoded, original) in
+            journal
+                .undo
+                .iter()
+                .chain(&journal.redo)
+                .zip([&op as &dyn Operation, &resize, &op])
+        {
+            let (mut a, mut b) = (vec![], vec![]);
+            decoded.encode(&mut a);
+            original.encode(&mut b);
+            assert_eq!(a, b);
+        }
+
+        // Undoing the decoded operation restores the stored pixels.
+        let mut layers = LayerStack::new(Image::new(4, 3));
+        let mut decoded = read(&data[..]).unwrap().undo.remove(0);
+        decoded.undo(&mut layers);
+        for ((x, y), pixel) in &op.undo_pixels {
+            assert_eq!(layers.active_image().get_pixel(*x, *y), pixel);
+        }
+    }
+
+    #[test]
+    fn pixel_blocks_are_delta_encoded() {
+        let red = [1.0, 0.0, 0.0, 1.0];
+        let op = pixel_op(&[((1, 0), red), ((2, 0), red), ((3, 0), red), ((0, 2), red)]);
+        let mut data = vec![];
+        encode_pixel_op(&op, &mut data);
+
+        let bits = |value: f32| {
+            let mut out = vec![];
+            write_varint(&mut out, value.to_bits() as u64);
+            out
+        };
+        let mut expected = vec![TAG_PIXEL_OP, 0, 2];
+        // A run of 3 pixels at (1, 0), then one at (0, 2), where x restarts
+        // from the start of the new row.
+        expected.extend_from_slice(&[0, 1, 3, 2, 0, 1]);
+        for channel in red {
+            expected.extend(bits(channel));
+        }
+        // Pixels equal to the previous one take a byte per channel.
+        expected.extend_from_slice(&[0; 12]);
+        assert_eq!(data, expected);
+    }
+
+    #[test]
+    fn rect_ops_round_trip_changed_pixels() {
+        let mut op = RectOp::new(LayerId(0), 1, 1, 3, 2);
+        op.keep(1, 1, ImagePixel::from([1.0, 0.0, 0.0, 1.0]));
+        op.keep(3, 2, ImagePixel::from([0.0, 0.0, 1.0, 0.5]));
+        // Only the first original pixel is kept.
+        op.keep(1, 1, ImagePixel::from([0.0; 4]));
+
+        let mut data = vec![];
+        write(&mut data, &header(), &[&op], &[]).unwrap();
+        let mut decoded = read(&data[..]).unwrap().undo.remove(0);
+        let (mut a, mut b) = (vec![], vec![]);
+        decoded.encode(&mut a);
+        op.encode(&mut b);
+        assert_eq!(a, b);
+
+        let mut layers = LayerStack::new(Image::from_pixel(4, 3, ImagePixel::from([1.0; 4])));
+        decoded.undo(&mut layers);
+        let image = layers.active_image();
+        assert_eq!(image.get_pixel(1, 1).0, [1.0, 0.0, 0.0, 1.0]);
+        assert_eq!(image.get_pixel(3, 2).0, [0.0, 0.0, 1.0, 0.5]);
+        assert_eq!(image.get_pixel(2, 1).0, [1.0; 4]);
+        decoded.redo(&mut layers);
+        assert!(layers
+            .active_image()
+            .pixels()
+            .all(|pixel| pixel.0 == [1.0; 4]));
+    }
+
+    #[test]
+    fn rect_ops_are_smaller_than_pixel_ops() {
+        let pixel = ImagePixel::from([0.5, 0.5, 0.5, 1.0]);
+        let mut rect = RectOp::new(LayerId(0), 0, 0, 64, 64);
+        let mut pixels = HashMap::new();
+        for y in 0..64 {
+            for x in (y % 2..64).step_by(2) {
+                rect.keep(x, y, pixel);
+                pixels.insert((x, y), pixel);
+            }
+        }
+        let (mut a, mut b) = (vec![], vec![]);
+        rect.encode(&mut a);
+        PixelOp {
+            layer: LayerId(0),
+            undo_pixels: pixels,
+        }
+        .encode(&mut b);
+        assert!(a.len() < b.len());
+    }
+
+    #[test]
+    fn truncated_rect_masks_are_rejected() {
+        // A huge rectangle whose mask is cut short.
+        let mut data = vec![TAG_RECT_OP, 0, 0, 0];
+        write_varint(&mut data, 1 << 20);
+        write_varint(&mut data, 1 << 20);
+        data.extend_from_slice(&[0xff; 16]);
+        assert!(decode(&mut &data[..]).is_err());
+    }
+
+    #[test]
+    fn layer_ops_round_trip() {
+        let mut layers = LayerStack::new(Image::new(4, 3));
+        let add = LayerOp::add(&mut layers, "added");
+        let layer = layers.active_mut();
+        layer
+            .image
+            .put_pixel(1, 2, ImagePixel::from([0.0, 1.0, 0.0, 1.0]));
+        let mut props = LayerPropsOp::new(layer);
+        layer.opacity = 0.5;
+        layer.blend_mode = BlendMode::Screen;
+        let remove = LayerOp::remove_active(&mut layers).unwrap();
+
+        let mut data = vec![];
+        write(&mut data, &header(), &[&add, &props, &remove], &[]).unwrap();
+        let mut journal = read(&data[..]).unwrap();
+        for (decoded, original) in
+            journal
+                .undo
+                .iter()
+                .zip([&add as &dyn Operation, &props, &remove])
+        {
+            let (mut a, mut b) = (vec![], vec![]);
+            decoded.encode(&mut a);
+            original.encode(&mut b);
+            assert_eq!(a, b);
+        }
+
+        // Undoing the removal brings back the layer with its pixels and
+        // properties.
+        let mut remove = journal.undo.pop().unwrap();
+        remove.undo(&mut layers);
+        let layer = layers.active();
+        assert_eq!(layer.name, "added");
+        assert_eq!((layer.opacity, layer.blend_mode), (0.5, BlendMode::Screen));
+        assert_eq!(layer.image.get_pixel(1, 2).0, [0.0, 1.0, 0.0, 1.0]);
+
+        props.undo(&mut layers);
+        let layer = layers.active();
+        assert_eq!((layer.opacity, layer.blend_mode), (1.0, BlendMode::Normal));
+
+        let mut add = journal.undo.remove(0);
+        add.undo(&mut layers);
+        assert_eq!(layers.layers().len(), 1);
+        add.redo(&mut layers);
+        assert_eq!(
+            layers.active().image.get_pixel(1, 2).0,
+            [0.0, 1.0, 0.0, 1.0]
+        );
+    }
+
+    #[test]
+    fn invalid_journals_are_rejected() {
+        let mut data = vec![];
+        write(
+            &mut data,
+            &header(),
+            &[&pixel_op(&[((0, 0), [1.0; 4])])],
+            &[],
+        )
+        .unwrap();
+        assert!(read(&data[..data.len() - 1]).is_err());
+        data[0] = b'X';
+        assert!(read(&data[..]).is_err());
+    }
+}
@@ -1,31 +1,71 @@
-use std::collections::HashMap;
-use teal_base::image::Pixel;
-use teal_base::{Brush, Image, ImagePixel, ImageView};
+use std::collections::{HashMap, VecDeque};
+use teal_base::{
+    BlendMode, Brush, Image, ImagePixel, ImageView, InputSample, Layer, LayerId, LayerStack,
+    PaintMode, Selection,
+};
 
-/// An operation to be applied to an image.
+mod clipboard;
+pub use clipboard::{Clip, FloatingDrag, FloatingPaste};
+mod dynamics;
+pub use dynamics::Dynamics;
+use dynamics::Rng;
+mod fill;
+pub use fill::{BucketFill, FillSource};
+mod history;
+pub use history::History;
+pub mod journal;
+mod pressure;
+pub use pressure::{PressureResponse, ResponseCurve};
+mod region;
+pub use region::ColorMetric;
+mod smoothing;
+use smoothing::DragPoint;
+pub use smoothing::{Smoothing, Stabilizer};
+mod transform;
+pub use transform::{Affine, FreeTransform, Grab, Resampling, TransformDrag};
+mod wand;
+pub use wand::MagicWand;
+
+/// An operation to be applied to a layer stack.
+///
+/// Undo and redo return false, changing nothing, if the layer that the
+/// operation applies to is gone.
 pub trait Operation {
     /// Redo the operation.
-    fn redo(&mut self, image: &mut Image);
+    fn redo(&mut self, layers: &mut LayerStack) -> bool;
 
     /// Undo the operation.
-    fn undo(&mut self, image: &mut Image);
+    fn undo(&mut self, layers: &mut LayerStack) -> bool;
+
+    /// Encode the operation for the on-disk journal.
+    fn encode(&self, out: &mut Vec<u8>);
+}
+
+/// A point of a stroke in image coordinates, with its input sample.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct StrokePoint {
+    pub x: f64,
+    pub y: f64,
+    pub sample: InputSample,
 }
 
 /// Trait designed to handle a drag operation consisting of a set of lines
 /// passed one after another.
 pub trait DragHandler {
-    /// Handle a drag line.
+    /// Handle a drag line. Pixels may only be changed as far as the
+    /// selection allows, if there is one.
     fn handle_line(
         &mut self,
         image: &mut Image,
+        selection: Option<&Selection>,
         image_view: &mut ImageView,
-        a: (f64, f64),
-        b: (f64, f64),
+        a: StrokePoint,
+        b: StrokePoint,
         // undo_pixels: &mut HashMap<(u32, u32), ImagePixel>,
     );
 
-    /// Convert to an undoable/redoable operation.
-    fn to_op(&self) -> Option<PixelOp>;
+    /// Convert to an undoable/redoable operation on the given layer.
+    fn to_op(&self, layer: LayerId) -> Option<PixelOp>;
 }
 
 /// Stored representation of a drag input gesture.
@@ -33,8 +73,18 @@ pub struct DragInput {
     /// First screen point.
     start: Option<(f64, f64)>,
 
-    /// Input drag points (stored as offsets from the first screen point).
-    points: Vec<(f64, f64)>,
+    /// Stabilized drag points (stored as offsets from the first screen
+    /// point), with their input samples.
+    points: Vec<DragPoint>,
+
+    /// Smoothing of the drag input.
+    smoothing: Smoothing,
+
+    /// Recent pointer positions, for the stabilizer.
+    recent: VecDeque<DragPoint>,
+
+    /// Layer that the drag operation is applied to.
+    layer: Option<LayerId>,
 
     /// Original pixels that have been overwritten, for undo operation.
     undo_pixels: HashMap<(u32, u32), ImagePixel>,
@@ -49,81 +99,185 @@ impl DragInput {
         DragInput {
             start: None,
             points: vec![],
+            smoothing: Smoothing::default(),
+            recent: VecDeque::new(),
+            layer: None,
             undo_pixels: HashMap::new(),
             drag_handler: Box::new(drag_handler),
         }
     }
 
-    /// Add the first point of a drag operation and update the image.
-    pub fn start(&mut self, image: &mut Image, start_x: f64, start_y: f64) {
+    /// Smooth the drag input.
+    pub fn with_smoothing(mut self, smoothing: Smoothing) -> DragInput {
+        self.smoothing = smoothing;
+        self
+    }
+
+    /// Add the first point of a drag operation on the active layer.
+    pub fn start(
+        &mut self,
+        layers: &mut LayerStack,
+        start_x: f64,
+        start_y: f64,
+        sample: InputSample,
+    ) {
         let _ = self.start.insert((start_x, start_y));
-        self.points.push((0.0, 0.0));
+        let _ = self.layer.insert(layers.active().id);
+        self.points.push((0.0, 0.0, sample));
+        self.recent.push_back((0.0, 0.0, sample));
     }
 
-    /// Add the next point to the drag operation, updating the image.
+    /// Add the next point to the drag operation, updating the layer.
     pub fn update(
         &mut self,
-        image: &mut Image,
+        layers: &mut LayerStack,
         image_view: &mut ImageView,
         off_x: f64,
         off_y: f64,
+        sample: InputSample,
     ) {
-        if self.points.len() == 0 {
+        if self.points.is_empty() {
             panic!("invalid use of BrushOp: start() was not called");
         }
 
-        let (last_off_x, last_off_y) = self.points[self.points.len() - 1];
-        let a = self.get_image_coords(image, image_view, last_off_x, last_off_y);
-        let b = self.get_image_coords(image, image_view, off_x, off_y);
-        self.drag_handler.handle_line(image, image_view, a, b);
-        self.points.push((off_x, off_y));
+        let last = self.points[self.points.len() - 1];
+        let point = (off_x, off_y, sample);
+        if let Some(point) = self
+            .smoothing
+            .stabilizer
+            .apply(&mut self.recent, last, point)
+        {
+            self.add_point(layers, image_view, point);
+        }
     }
 
-    /// Add the final point to the drag operation and update the image.
+    /// Add the final point to the drag operation and update the layer.
     pub fn finish(
         &mut self,
-        image: &mut Image,
+        layers: &mut LayerStack,
         image_view: &mut ImageView,
         off_x: f64,
         off_y: f64,
+        sample: InputSample,
     ) {
-        self.update(image, image_view, off_x, off_y);
+        self.update(layers, image_view, off_x, off_y, sample);
+        let last = self.points[self.points.len() - 1];
+        if self.smoothing.stabilizer.catches_up() && (last.0, last.1) != (off_x, off_y) {
+            self.add_point(layers, image_view, (off_x, off_y, sample));
+        } else if self.points.len() == 1 {
+            // Draw at least a dot.
+            self.add_point(layers, image_view, last);
+        }
+        if self.smoothing.curves {
+            // The last curve has no point after it.
+            self.draw_curve(layers, image_view, self.points.len() - 2);
+        }
     }
 
-    /// Get image coordinates for the given offsets.
+    /// Add a stabilized point, drawing the lines or curves up to it.
+    ///
+    /// Curves are drawn one point behind, since each curve depends on the
+    /// point after it.
+    fn add_point(&mut self, layers: &mut LayerStack, image_view: &mut ImageView, point: DragPoint) {
+        self.points.push(point);
+        let count = self.points.len();
+        if !self.smoothing.curves {
+            let a = self.points[count - 2];
+            self.draw_line(layers, image_view, a, point);
+        } else if count >= 3 {
+            self.draw_curve(layers, image_view, count - 3);
+        }
+    }
+
+    /// Draw the curve from the point at an index to the next one, as a
+    /// series of short lines.
+    fn draw_curve(&mut self, layers: &mut LayerStack, image_view: &mut ImageView, index: usize) {
+        let last = self.points.len() - 1;
+        let p0 = self.points[index.saturating_sub(1)];
+        let p1 = self.points[index];
+        let p2 = self.points[index + 1];
+        let p3 = self.points[(index + 2).min(last)];
+        let chord = ((p2.0 - p1.0).powi(2) + (p2.1 - p1.1).powi(2)).sqrt();
+        let steps = (chord / CURVE_STEP).ceil().clamp(1.0, MAX_CURVE_STEPS) as usize;
+        let mut prev = p1;
+        for step in 1..=steps {
+            let t = step as f64 / steps as f64;
+            let (x, y) =
+                smoothing::catmull_rom([(p0.0, p0.1), (p1.0, p1.1), (p2.0, p2.1), (p3.0, p3.1)], t);
+            let point = (x, y, p1.2.lerp(&p2.2, t));
+            self.draw_line(layers, image_view, prev, point);
+            prev = point;
+        }
+    }
+
+    /// Draw a straight line between two drag points on the layer.
+    fn draw_line(
+        &mut self,
+        layers: &mut LayerStack,
+        image_view: &mut ImageView,
+        a: DragPoint,
+        b: DragPoint,
+    ) {
+        let layer = self.layer.expect("missing drag layer");
+        let (image, selection) = match layers.get_mut_with_selection(layer) {
+            Some((layer, selection)) => (&mut layer.image, selection),
+            // The layer was removed mid-drag, so there is nothing to update.
+            None => return,
+        };
+        let a = self.get_stroke_point(image, image_view, a.0, a.1, a.2);
+        let b = self.get_stroke_point(image, image_view, b.0, b.1, b.2);
+        self.drag_handler
+            .handle_line(image, selection, image_view, a, b);
+    }
+
+    /// Get the stroke point in image coordinates for the given offsets.
     ///
     /// NOTE: These could potentially be outside the bounds of the actual image.
-    fn get_image_coords(
+    fn get_stroke_point(
         &self,
         image: &Image,
         image_view: &ImageView,
         off_x: f64,
         off_y: f64,
-    ) -> (f64, f64) {
+        sample: InputSample,
+    ) -> StrokePoint {
         let (start_x, start_y) = self.start.as_ref().expect("missing start point");
         let screen_x = start_x + off_x;
         let screen_y = start_y + off_y;
-        image_view.get_image_coords_f(image, screen_x, screen_y)
+        let (x, y) = image_view.get_image_coords_f(image, screen_x, screen_y);
+        StrokePoint { x, y, sample }
     }
 
     pub fn to_op(self) -> Option<PixelOp> {
-        self.drag_handler.to_op()
+        let layer = self.layer?;
+        self.drag_handler.to_op(layer)
     }
 }
 
-/// An operation based on updating pixels in the image.
+/// Length in screen pixels of the lines that a smoothed curve is drawn with.
+const CURVE_STEP: f64 = 2.0;
+
+/// Most lines that a single smoothed curve is drawn with.
+const MAX_CURVE_STEPS: f64 = 64.0;
+
+/// An operation based on updating pixels in a layer.
 pub struct PixelOp {
+    layer: LayerId,
     undo_pixels: HashMap<(u32, u32), ImagePixel>,
 }
 
 impl Operation for PixelOp {
-    fn redo(&mut self, image: &mut Image) {
+    fn redo(&mut self, layers: &mut LayerStack) -> bool {
         // Works since self.undo_pixels will contain the redo pixels.
-        self.undo(image);
+        self.undo(layers)
     }
 
-    fn undo(&mut self, image: &mut Image) {
-        println!("undoing operation");
+    fn undo(&mut self, layers: &mut LayerStack) -> bool {
+        let image = match layers.get_mut(self.layer) {
+            Some(layer) => &mut layer.image,
+            // The layer has since been removed.
+            None => return false,
+        };
         let mut redo_pixels = HashMap::new();
         for ((x, y), pixel) in self.undo_pixels.iter() {
             redo_pixels.insert((*x, *y), *image.get_pixel(*x, *y));
@@ -132,6 +286,308 @@ impl Operation for PixelOp {
         // Instead of having separate buffers for undo and redo pixels, just
         // use one.
         self.undo_pixels = redo_pixels;
+        true
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        journal::encode_pixel_op(self, out);
+    }
+}
+
+/// An operation updating pixels inside a rectangle of a layer.
+///
+/// Unlike `PixelOp`, the original pixels are stored densely, which keeps
+/// large areas such as fills small in memory and in the journal.
+pub struct RectOp {
+    layer: LayerId,
+
+    /// Top left corner of the rectangle in image coordinates.
+    origin: (u32, u32),
+
+    /// Width and height of the rectangle.
+    dims: (u32, u32),
+
+    /// Original pixels of the rectangle in row-major order. Only the pixels
+    /// marked in `mask` are used.
+    pixels: Vec<ImagePixel>,
+
+    /// Changed pixels of the rectangle, one bit per pixel in row-major order.
+    mask: Vec<u8>,
+}
+
+impl RectOp {
+    /// Create an operation for a rectangle of a layer, with no pixels kept
+    /// yet.
+    pub(crate) fn new(layer: LayerId, x: u32, y: u32, width: u32, height: u32) -> RectOp {
+        let len = width as usize * height as usize;
+        RectOp {
+            layer,
+            origin: (x, y),
+            dims: (width, height),
+            pixels: vec![ImagePixel::from([0.0; 4]); len],
+            mask: vec![0; len.div_ceil(8)],
+        }
+    }
+
+    /// Keep the original pixel at image coordinates (x, y) inside the
+    /// rectangle, unless one is kept for it already.
+    pub(crate) fn keep(&mut self, x: u32, y: u32, pixel: ImagePixel) {
+        let (left, top) = self.origin;
+        let index = (y - top) as usize * self.dims.0 as usize + (x - left) as usize;
+        if !bit(&self.mask, index) {
+            self.mask[index / 8] |= 1 << (index % 8);
+            self.pixels[index] = pixel;
+        }
+    }
+
+    /// Are no pixels kept?
+    pub(crate) fn is_empty(&self) -> bool {
+        self.mask.iter().all(|bits| *bits == 0)
+    }
+
+    /// Kept pixels with their image coordinates, in row-major order.
+    pub(crate) fn kept(&self) -> impl Iterator<Item = (u32, u32, &ImagePixel)> {
+        let (width, _) = self.dims;
+        let (left, top) = self.origin;
+        self.pixels
+            .iter()
+            .enumerate()
+            .filter(|(index, _)| bit(&self.mask, *index))
+            .map(move |(index, pixel)| {
+                let index = index as u32;
+                (left + index % width, top + index / width, pixel)
+            })
+    }
+}
+
+impl Operation for RectOp {
+    fn redo(&mut self, layers: &mut LayerStack) -> bool {
+        // Like `PixelOp`, the kept pixels are swapped with the layer's.
+        self.undo(layers)
+    }
+
+    fn undo(&mut self, layers: &mut LayerStack) -> bool {
+        let image = match layers.get_mut(self.layer) {
+            Some(layer) => &mut layer.image,
+            // The layer has since been removed.
+            None => return false,
+        };
+        let (width, _) = self.dims;
+        let (left, top) = self.origin;
+        for (index, pixel) in self.pixels.iter_mut().enumerate() {
+            if !bit(&self.mask, index) {
+                continue;
+            }
+            let index = index as u32;
+            let (x, y) = (left + index % width, top + index / width);
+            std::mem::swap(pixel, image.get_pixel_mut(x, y));
+        }
+        true
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        journal::encode_rect_op(self, out);
+    }
+}
+
+/// Is a bit of a bitmask set?
+pub(crate) fn bit(mask: &[u8], index: usize) -> bool {
+    mask[index / 8] & (1 << (index % 8)) != 0
+}
+
+/// An operation resizing the canvas of every layer.
+pub struct ResizeOp {
+    /// Canvas dimensions to apply on the next undo or redo.
+    dims: (u32, u32),
+
+    /// Pixels of each layer cut off by the last resize, restored by the next
+    /// undo or redo.
+    cropped: Vec<PixelOp>,
+}
+
+impl ResizeOp {
+    /// Create a resize operation. The operation is applied by `redo()`.
+    pub fn new(width: u32, height: u32) -> ResizeOp {
+        ResizeOp {
+            dims: (width, height),
+            cropped: vec![],
+        }
+    }
+
+    /// Resize to the stored dimensions and swap in the dimensions and pixels
+    /// to go back. Pixels of removed layers are not restored, so this always
+    /// succeeds.
+    fn apply(&mut self, layers: &mut LayerStack) -> bool {
+        let (width, height) = self.dims;
+        let mut cropped = vec![];
+        for layer in layers.layers() {
+            let undo_pixels: HashMap<(u32, u32), ImagePixel> = layer
+                .image
+                .enumerate_pixels()
+                .filter(|(x, y, _)| *x >= width || *y >= height)
+                .map(|(x, y, pixel)| ((x, y), *pixel))
+                .collect();
+            if !undo_pixels.is_empty() {
+                cropped.push(PixelOp {
+                    layer: layer.id,
+                    undo_pixels,
+                });
+            }
+        }
+
+        self.dims = (layers.width(), layers.height());
+        layers.resize(width, height);
+        for op in &self.cropped {
+            if let Some(layer) = layers.get_mut(op.layer) {
+                for ((x, y), pixel) in op.undo_pixels.iter() {
+                    layer.image.put_pixel(*x, *y, *pixel);
+                }
+            }
+        }
+        self.cropped = cropped;
+        true
+    }
+}
+
+impl Operation for ResizeOp {
+    fn redo(&mut self, layers: &mut LayerStack) -> bool {
+        self.apply(layers)
+    }
+
+    fn undo(&mut self, layers: &mut LayerStack) -> bool {
+        self.apply(layers)
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        journal::encode_resize_op(self, out);
+    }
+}
+
+/// An operation adding or removing a layer.
+pub struct LayerOp {
+    /// Index of the layer in the stack.
+    index: usize,
+
+    /// Identifier of the layer.
+    id: LayerId,
+
+    /// The removed layer, inserted back by the next undo or redo. If None,
+    /// the next undo or redo removes the layer.
+    layer: Option<Layer>,
+}
+
+impl LayerOp {
+    /// Add a new transparent layer above the active one, returning the
+    /// operation that added it.
+    pub fn add(layers: &mut LayerStack, name: &str) -> LayerOp {
+        let id = layers.add_layer(name);
+        LayerOp {
+            index: layers.active_index(),
+            id,
+            layer: None,
+        }
+    }
+
+    /// Remove the active layer, returning the operation that removed it.
+    /// Returns None if it is the last remaining layer.
+    pub fn remove_active(layers: &mut LayerStack) -> Option<LayerOp> {
+        let index = layers.active_index();
+        let layer = layers.remove_layer(index)?;
+        Some(LayerOp {
+            index,
+            id: layer.id,
+            layer: Some(layer),
+        })
+    }
+
+    /// Insert the removed layer back, or remove the layer. Returns false if
+    /// the layer can't be inserted or is gone.
+    fn apply(&mut self, layers: &mut LayerStack) -> bool {
+        match self.layer.take() {
+            Some(layer) => {
+                let index = self.index.min(layers.layers().len());
+                self.layer = layers.insert_layer(index, layer).err();
+                self.layer.is_none()
+            }
+            None => {
+                self.layer = layers
+                    .index_of(self.id)
+                    .and_then(|index| layers.remove_layer(index));
+                self.layer.is_some()
+            }
+        }
+    }
+}
+
+impl Operation for LayerOp {
+    fn redo(&mut self, layers: &mut LayerStack) -> bool {
+        self.apply(layers)
+    }
+
+    fn undo(&mut self, layers: &mut LayerStack) -> bool {
+        self.apply(layers)
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        journal::encode_layer_op(self, out);
+    }
+}
+
+/// An operation changing the opacity, visibility or blend mode of a layer.
+pub struct LayerPropsOp {
+    layer: LayerId,
+
+    /// Properties to apply on the next undo or redo.
+    opacity: f32,
+    visible: bool,
+    blend_mode: BlendMode,
+}
+
+impl LayerPropsOp {
+    /// Create an operation from the properties of a layer before they are
+    /// changed.
+    pub fn new(layer: &Layer) -> LayerPropsOp {
+        LayerPropsOp {
+            layer: layer.id,
+            opacity: layer.opacity,
+            visible: layer.visible,
+            blend_mode: layer.blend_mode,
+        }
+    }
+
+    /// Layer that the operation applies to.
+    pub fn layer(&self) -> LayerId {
+        self.layer
+    }
+
+    /// Are the properties the same as those of the layer?
+    pub fn matches(&self, layer: &Layer) -> bool {
+        self.opacity == layer.opacity
+            && self.visible == layer.visible
+            && self.blend_mode == layer.blend_mode
+    }
+}
+
+impl Operation for LayerPropsOp {
+    fn redo(&mut self, layers: &mut LayerStack) -> bool {
+        // Like `PixelOp`, the stored properties are swapped with the layer's.
+        self.undo(layers)
+    }
+
+    fn undo(&mut self, layers: &mut LayerStack) -> bool {
+        let layer = match layers.get_mut(self.layer) {
+            Some(layer) => layer,
+            // The layer has since been removed.
+            None => return false,
+        };
+        std::mem::swap(&mut self.opacity, &mut layer.opacity);
+        std::mem::swap(&mut self.visible, &mut layer.visible);
+        std::mem::swap(&mut self.blend_mode, &mut layer.blend_mode);
+        true
+    }
+
+    fn encode(&self, out: &mut Vec<u8>) {
+        journal::encode_layer_props_op(self, out);
     }
 }
 
@@ -139,6 +595,36 @@ impl Operation for PixelOp {
 pub struct PaintBrush {
     brush: Brush,
     color: ImagePixel,
+    pressure: PressureResponse,
+
+    /// Brush size factor.
+    size: f32,
+
+    /// Brush rotation in radians.
+    angle: f32,
+
+    /// Stamp spacing and randomization.
+    dynamics: Dynamics,
+
+    /// Random variations of the stroke.
+    rng: Rng,
+
+    /// Distance along the current line to the next stamp, or None before the
+    /// first line of the stroke.
+    next_stamp: Option<f64>,
+
+    /// Direction of the stroke in radians.
+    direction: f32,
+
+    /// How the color is blended onto the image.
+    mode: PaintMode,
+
+    /// Erase instead of painting the color.
+    erase: bool,
+
+    /// Stroke coverage of each painted pixel, from 0.0 to 1.0.
+    coverage: HashMap<(u32, u32), f32>,
+
     undo_pixels: HashMap<(u32, u32), ImagePixel>,
 }
 
@@ -148,75 +634,206 @@ impl PaintBrush {
         PaintBrush {
             brush,
             color,
+            pressure: PressureResponse::default(),
+            size: 1.0,
+            angle: 0.0,
+            dynamics: Dynamics::default(),
+            rng: Rng::new(0),
+            next_stamp: None,
+            direction: 0.0,
+            mode: PaintMode::Normal,
+            erase: false,
+            coverage: HashMap::new(),
             undo_pixels: HashMap::new(),
         }
     }
 
-    /// Fill the brush around the coordinates (x, y).
-    fn fill(&mut self, image: &mut Image, x: i32, y: i32) {
-        for (dx, dy, value) in self.brush.iter_values() {
-            let img_x = x + dx;
-            let img_y = y + dy;
+    /// Set how the brush responds to pen pressure.
+    pub fn with_pressure(mut self, pressure: PressureResponse) -> PaintBrush {
+        self.pressure = pressure;
+        self
+    }
+
+    /// Set the brush size, as a factor of the brush image size.
+    pub fn with_size(mut self, size: f32) -> PaintBrush {
+        self.size = size;
+        self
+    }
+
+    /// Set the brush rotation in radians.
+    pub fn with_angle(mut self, angle: f32) -> PaintBrush {
+        self.angle = angle;
+        self
+    }
+
+    /// Set the brush dynamics.
+    pub fn with_dynamics(mut self, dynamics: Dynamics) -> PaintBrush {
+        self.dynamics = dynamics;
+        self
+    }
+
+    /// Set how the color is blended onto the image.
+    pub fn with_mode(mut self, mode: PaintMode) -> PaintBrush {
+        self.mode = mode;
+        self
+    }
 
+    /// Brush scale for a sample, before any jitter.
+    fn scale(&self, sample: &InputSample) -> f32 {
+        self.size * self.pressure.size.eval(sample.pressure as f32)
+    }
+
+    /// Brush diameter in image pixels at a scale.
+    fn diameter(&self, scale: f32) -> f64 {
+        let (width, height) = self.brush.dimensions();
+        (width.max(height) as f32 * scale) as f64
+    }
+
+    /// Fill the brush around the coordinates (x, y).
+    ///
+    /// Each fill moves the stroke coverage of a pixel towards the opacity for
+    /// the sample's pressure, by the flow. The pixel is then the original
+    /// pixel blended with the color at that coverage in the paint mode, so
+    /// overlapping fills within a stroke never go past the opacity. When
+    /// erasing, the alpha of the original pixel is reduced by the coverage
+    /// instead. Either way, the coverage is scaled by the selection, if any.
+    fn fill(
+        &mut self,
+        image: &mut Image,
+        selection: Option<&Selection>,
+        x: f64,
+        y: f64,
+        sample: &InputSample,
+    ) {
+        let pressure = sample.pressure as f32;
+        let dynamics = &self.dynamics;
+        let base_scale = self.scale(sample);
+        let scale = base_scale * (1.0 - dynamics.size_jitter * self.rng.next_f32());
+        let opacity = (dynamics.opacity * self.pressure.opacity.eval(pressure)).min(1.0)
+            * (1.0 - dynamics.opacity_jitter * self.rng.next_f32());
+        let flow = (dynamics.flow * self.pressure.flow.eval(pressure)).min(1.0);
+        let scatter = dynamics.scatter as f64 * self.diameter(base_scale);
+        let x = x + scatter * self.rng.next_signed() as f64;
+        let y = y + scatter * self.rng.next_signed() as f64;
+        let angle = if dynamics.follow_stroke {
+            self.angle + self.direction
+        } else {
+            self.angle
+        };
+        for (img_x, img_y, value) in self.brush.stamp(x, y, scale, angle) {
             if img_x < 0 || img_y < 0 {
                 continue;
             }
 
             let img_x: u32 = img_x.try_into().unwrap();
             let img_y: u32 = img_y.try_into().unwrap();
+            let mask = selection.map_or(1.0, |selection| selection.value(img_x, img_y));
+            if mask <= 0.0 {
+                continue;
+            }
             if let Some(pixel) = image.get_pixel_mut_checked(img_x, img_y) {
-                let undo_pixel = pixel.clone();
-                pixel.blend(&ImagePixel::from([
-                    self.color.0[0],
-                    self.color.0[1],
-                    self.color.0[2],
-                    self.color.0[3] * value,
-                ]));
-                self.undo_pixels.entry((img_x, img_y)).or_insert(undo_pixel);
+                let coverage = self.coverage.entry((img_x, img_y)).or_insert(0.0);
+                if *coverage >= opacity {
+                    continue;
+                }
+                *coverage += (opacity - *coverage) * flow * value;
+                let mut painted = *self.undo_pixels.entry((img_x, img_y)).or_insert(*pixel);
+                let amount = *coverage * mask;
+                if self.erase {
+                    painted.0[3] *= 1.0 - amount;
+                } else {
+                    painted = self.mode.composite(&painted, &self.color, amount);
+                }
+                *pixel = painted;
             }
         }
     }
 }
 
-/// Increment factor for the paint brush operation.
-const PAINT_BRUSH_INCR_FACTOR: f64 = 0.4;
+/// Smallest distance between paint brush stamps in image pixels.
+const MIN_STAMP_SPACING: f64 = 0.25;
 
 impl DragHandler for PaintBrush {
     fn handle_line(
         &mut self,
         image: &mut Image,
+        selection: Option<&Selection>,
         _image_view: &mut ImageView,
-        a: (f64, f64),
-        b: (f64, f64),
+        a: StrokePoint,
+        b: StrokePoint,
     ) {
-        let alpha = b.0 - a.0;
-        let beta = b.1 - a.1;
+        let alpha = b.x - a.x;
+        let beta = b.y - a.y;
         // Determine the length of the line.
         let line_len = (alpha * alpha + beta * beta).sqrt();
-        // Based on PAINT_BRUSH_INCR_FACTOR, determine what number of times we
-        // should fill the brush radius for a parametric representation of the
-        // line.
-        let count = line_len / PAINT_BRUSH_INCR_FACTOR;
-        let incr = if count > 0.0 { 1.0 / count } else { 1.0 };
+        if line_len > 0.0 {
+            self.direction = beta.atan2(alpha) as f32;
+        }
+
+        // The stroke starts with a stamp, and the distance to the next stamp
+        // carries over from one line to the next, so spacing stays even.
+        let mut pos = match self.next_stamp {
+            Some(pos) => pos,
+            None => {
+                let start = a.x.to_bits().rotate_left(32) ^ a.y.to_bits();
+                self.rng = Rng::new(self.dynamics.seed ^ start);
+                0.0
+            }
+        };
 
         // Now slide along the parametric version of the line.
-        let mut t = 0.0;
-        while t < 1.0 {
-            let x = a.0 + t * alpha;
-            let y = a.1 + t * beta;
-            // fill_dot(image, &self.color, x, y, undo_pixels);
-            self.fill(image, x as i32, y as i32);
-            t += incr;
+        while pos <= line_len {
+            let t = if line_len > 0.0 { pos / line_len } else { 0.0 };
+            let x = a.x + t * alpha;
+            let y = a.y + t * beta;
+            let sample = a.sample.lerp(&b.sample, t);
+            self.fill(image, selection, x, y, &sample);
+            let spacing = self.dynamics.spacing as f64 * self.diameter(self.scale(&sample));
+            pos += spacing.max(MIN_STAMP_SPACING);
         }
+        self.next_stamp = Some(pos - line_len);
     }
 
-    fn to_op(&self) -> Option<PixelOp> {
+    fn to_op(&self, layer: LayerId) -> Option<PixelOp> {
         Some(PixelOp {
+            layer,
             undo_pixels: self.undo_pixels.clone(),
         })
     }
 }
 
+/// An eraser, reducing the alpha of pixels by the strength of the stamps of a
+/// paint brush.
+pub struct Eraser {
+    brush: PaintBrush,
+}
+
+impl Eraser {
+    /// Create an eraser from a paint brush. The color of the paint brush is
+    /// not used.
+    pub fn new(mut brush: PaintBrush) -> Eraser {
+        brush.erase = true;
+        Eraser { brush }
+    }
+}
+
+impl DragHandler for Eraser {
+    fn handle_line(
+        &mut self,
+        image: &mut Image,
+        selection: Option<&Selection>,
+        image_view: &mut ImageView,
+        a: StrokePoint,
+        b: StrokePoint,
+    ) {
+        self.brush.handle_line(image, selection, image_view, a, b);
+    }
+
+    fn to_op(&self, layer: LayerId) -> Option<PixelOp> {
+        self.brush.to_op(layer)
+    }
+}
+
 /// Drag handler used for translating the view.
 pub struct ViewDragHandler;
 
@@ -231,17 +848,18 @@ impl DragHandler for ViewDragHandler {
     fn handle_line(
         &mut self,
         _image: &mut Image,
+        _selection: Option<&Selection>,
         image_view: &mut ImageView,
-        a: (f64, f64),
-        b: (f64, f64),
+        a: StrokePoint,
+        b: StrokePoint,
     ) {
-        let dx = b.0 - a.0;
-        let dy = b.1 - a.1;
+        let dx = b.x - a.x;
+        let dy = b.y - a.y;
         image_view.translate(dx, dy);
     }
 
     /// No operation to undo for the view drag handler.
-    fn to_op(&self) -> Option<PixelOp> {
+    fn to_op(&self, _layer: LayerId) -> Option<PixelOp> {
         None
     }
 }
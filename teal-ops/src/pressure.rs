@@ -0,0 +1,80 @@
+//! Pressure response curves for brushes.
+
+/// Piecewise-linear curve mapping pen pressure (0.0 - 1.0) to a factor.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ResponseCurve {
+    /// Control points (pressure, value), sorted by pressure.
+    points: Vec<(f32, f32)>,
+}
+
+impl ResponseCurve {
+    /// Create a curve with the same value for every pressure.
+    pub fn constant(value: f32) -> ResponseCurve {
+        ResponseCurve {
+            points: vec![(0.0, value)],
+        }
+    }
+
+    /// Create a curve from (pressure, value) control points.
+    ///
+    /// Pressures must be between 0.0 and 1.0 and values must not be
+    /// negative. Pressures outside the first and last points use the value
+    /// of the nearest point.
+    pub fn new(mut points: Vec<(f32, f32)>) -> Result<ResponseCurve, String> {
+        if points.is_empty() {
+            return Err("curve needs at least one point".to_string());
+        }
+        for (pressure, value) in points.iter() {
+            if !(0.0..=1.0).contains(pressure) {
+                return Err(format!("pressure {pressure} is not between 0 and 1"));
+            }
+            if !(value.is_finite() && *value >= 0.0) {
+                return Err(format!("invalid curve value {value}"));
+            }
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Ok(ResponseCurve { points })
+    }
+
+    /// Get the value of the curve for a pressure.
+    pub fn eval(&self, pressure: f32) -> f32 {
+        let first = self.points[0];
+        if pressure <= first.0 {
+            return first.1;
+        }
+        for pair in self.points.windows(2) {
+            let (p0, v0) = pair[0];
+            let (p1, v1) = pair[1];
+            if pressure <= p1 {
+                if p1 == p0 {
+                    return v1;
+                }
+                return v0 + (v1 - v0) * (pressure - p0) / (p1 - p0);
+            }
+        }
+        self.points[self.points.len() - 1].1
+    }
+}
+
+/// How a brush responds to pen pressure.
+///
+/// Each curve gives a factor for the brush size, the stroke opacity (the
+/// most a stroke can cover a pixel) and the flow (how much a single stamp
+/// adds towards that opacity).
+#[derive(Clone, Debug, PartialEq)]
+pub struct PressureResponse {
+    pub size: ResponseCurve,
+    pub opacity: ResponseCurve,
+    pub flow: ResponseCurve,
+}
+
+impl Default for PressureResponse {
+    /// No response to pressure.
+    fn default() -> PressureResponse {
+        PressureResponse {
+            size: ResponseCurve::constant(1.0),
+            opacity: ResponseCurve::constant(1.0),
+            flow: ResponseCurve::constant(1.0),
+        }
+    }
+}
@@ -0,0 +1,303 @@
+//! Regions of similar color, shared by the bucket fill and the magic wand.
+use teal_base::{Image, ImagePixel};
+
+/// How the difference between two colors is measured.
+///
+/// Distances are from 0.0 (same) to about 1.0, so one tolerance works for
+/// every metric.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum ColorMetric {
+    /// Largest difference of the red, green and blue channels, ignoring
+    /// alpha
+    Rgb,
+
+    /// Largest difference of any channel, with the color premultiplied by
+    /// alpha so all fully transparent pixels are the same
+    #[default]
+    Rgba,
+
+    /// Distance in CIE Lab (divided by 100), which follows perceived color
+    /// differences more closely, or the alpha difference if that is larger
+    Lab,
+}
+
+impl ColorMetric {
+    /// Coordinates of a pixel to measure distances between.
+    fn coords(self, pixel: &ImagePixel) -> [f32; 4] {
+        let [r, g, b, a] = pixel.0;
+        match self {
+            ColorMetric::Rgb => [r, g, b, 0.0],
+            ColorMetric::Rgba => [r * a, g * a, b * a, a],
+            ColorMetric::Lab => {
+                let [l, a_star, b_star] = linear_to_lab([r, g, b]);
+                [l / 100.0, a_star / 100.0, b_star / 100.0, a]
+            }
+        }
+    }
+
+    /// Distance between the coordinates of two pixels.
+    fn distance(self, p: &[f32; 4], q: &[f32; 4]) -> f32 {
+        match self {
+            ColorMetric::Rgb | ColorMetric::Rgba => p
+                .iter()
+                .zip(q.iter())
+                .map(|(p, q)| (p - q).abs())
+                .fold(0.0, f32::max),
+            ColorMetric::Lab => {
+                let color =
+                    ((p[0] - q[0]).powi(2) + (p[1] - q[1]).powi(2) + (p[2] - q[2]).powi(2)).sqrt();
+                color.max((p[3] - q[3]).abs())
+            }
+        }
+    }
+}
+
+/// Convert a linear sRGB color to CIE Lab (D65 white point).
+fn linear_to_lab([r, g, b]: [f32; 3]) -> [f32; 3] {
+    let x = (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047;
+    let y = 0.2126 * r + 0.7152 * g + 0.0722 * b;
+    let z = (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883;
+    let f = |t: f32| {
+        if t > 216.0 / 24389.0 {
+            t.cbrt()
+        } else {
+            (24389.0 / 27.0 * t + 16.0) / 116.0
+        }
+    };
+    let (fx, fy, fz) = (f(x), f(y), f(z));
+    [116.0 * fy - 16.0, 500.0 * (fx - fy), 200.0 * (fy - fz)]
+}
+
+/// Settings for finding a region of similar color.
+#[derive(Copy, Clone, Debug)]
+pub(crate) struct Region {
+    /// Largest distance from the start pixel that is still in the region.
+    pub(crate) tolerance: f32,
+
+    /// Only include pixels connected to the start pixel.
+    pub(crate) contiguous: bool,
+
+    /// Partly include the pixels around the region by how close they are
+    /// to matching.
+    pub(crate) antialias: bool,
+
+    /// How color differences are measured.
+    pub(crate) metric: ColorMetric,
+}
+
+impl Region {
+    /// Coverage of each pixel of the image in row order by the region
+    /// around (x, y), from 0.0 - 1.0.
+    pub(crate) fn coverage(&self, image: &Image, x: u32, y: u32) -> Vec<f32> {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let start = self.metric.coords(image.get_pixel(x, y));
+        let distance = |index: usize| {
+            let pixel = image.get_pixel((index % width) as u32, (index / width) as u32);
+            self.metric.distance(&self.metric.coords(pixel), &start)
+        };
+        let matches = |index: usize| distance(index) <= self.tolerance;
+
+        let mut filled = vec![false; width * height];
+        if self.contiguous {
+            // Scanline flood fill: fill the whole run of matching pixels
+            // around each seed, then seed the runs above and below it.
+            let mut seeds = vec![(x as usize, y as usize)];
+            while let Some((sx, sy)) = seeds.pop() {
+                let row = sy * width;
+                if filled[row + sx] || !matches(row + sx) {
+                    continue;
+                }
+                let mut left = sx;
+                while left > 0 && !filled[row + left - 1] && matches(row + left - 1) {
+                    left -= 1;
+                }
+                let mut right = sx;
+                while right + 1 < width && !filled[row + right + 1] && matches(row + right + 1) {
+                    right += 1;
+                }
+                filled[row + left..=row + right].fill(true);
+
+                for ny in [sy.wrapping_sub(1), sy + 1] {
+                    if ny >= height {
+                        continue;
+                    }
+                    let row = ny * width;
+                    let mut in_run = false;
+                    for nx in left..=right {
+                        let open = !filled[row + nx] && matches(row + nx);
+                        if open && !in_run {
+                            seeds.push((nx, ny));
+                        }
+                        in_run = open;
+                    }
+                }
+            }
+        } else {
+            for (index, filled) in filled.iter_mut().enumerate() {
+                *filled = matches(index);
+            }
+        }
+
+        let mut coverage: Vec<f32> = filled
+            .iter()
+            .map(|filled| if *filled { 1.0 } else { 0.0 })
+            .collect();
+        if self.antialias && self.tolerance < 1.0 {
+            // Expand the region by one pixel, covering each edge pixel by how
+            // close it is to the tolerance. An anti-aliased edge pixel that
+            // is half the start color is then about half covered.
+            for py in 0..height {
+                for px in 0..width {
+                    let index = py * width + px;
+                    if filled[index] {
+                        continue;
+                    }
+                    let near_fill = (py.saturating_sub(1)..(py + 2).min(height)).any(|ny| {
+                        (px.saturating_sub(1)..(px + 2).min(width))
+                            .any(|nx| filled[ny * width + nx])
+                    });
+                    if near_fill {
+                        coverage[index] =
+                            ((1.0 - distance(index)) / (1.0 - self.tolerance)).clamp(0.0, 1.0);
+                    }
+                }
+            }
+        }
+        coverage
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::BucketFill;
+    use teal_base::{LayerStack, Selection};
+
+    /// An image from rows of pixels: '.' is white, '#' black, and a digit
+    /// is a gray of that many tenths.
+    fn image(rows: &[&str]) -> Image {
+        let pixel = |c: char| {
+            let value = match c {
+                '.' => 1.0,
+                '#' => 0.0,
+                digit => digit.to_digit(10).unwrap() as f32 / 10.0,
+            };
+            ImagePixel::from([value, value, value, 1.0])
+        };
+        let width = rows[0].len() as u32;
+        Image::from_fn(width, rows.len() as u32, |x, y| {
+            pixel(rows[y as usize].chars().nth(x as usize).unwrap())
+        })
+    }
+
+    fn region(tolerance: f32, contiguous: bool) -> Region {
+        Region {
+            tolerance,
+            contiguous,
+            antialias: false,
+            metric: ColorMetric::Rgba,
+        }
+    }
+
+    /// Coverage drawn like `image()` rows, with '*' for covered pixels.
+    fn covered(coverage: &[f32], width: usize) -> Vec<String> {
+        coverage
+            .chunks(width)
+            .map(|row| {
+                row.iter()
+                    .map(|value| if *value == 1.0 { '*' } else { '-' })
+                    .collect()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn contiguous_regions_stop_at_edges() {
+        let image = image(&["..#..", "..#..", "###.."]);
+        let coverage = region(0.1, true).coverage(&image, 0, 0);
+        assert_eq!(covered(&coverage, 5), ["**---", "**---", "-----"]);
+
+        // The right side is connected around the bottom right corner.
+        let coverage = region(0.1, true).coverage(&image, 4, 0);
+        assert_eq!(covered(&coverage, 5), ["---**", "---**", "---**"]);
+    }
+
+    #[test]
+    fn contiguous_regions_follow_winding_paths() {
+        let image = image(&[".....", "####.", ".....", ".####", "....."]);
+        let coverage = region(0.0, true).coverage(&image, 0, 0);
+        assert_eq!(
+            covered(&coverage, 5),
+            ["*****", "----*", "*****", "*----", "*****"]
+        );
+    }
+
+    #[test]
+    fn global_regions_match_anywhere() {
+        let image = image(&["..#..", "..#..", "###.."]);
+        let coverage = region(0.1, false).coverage(&image, 0, 0);
+        assert_eq!(covered(&coverage, 5), ["**-**", "**-**", "---**"]);
+    }
+
+    #[test]
+    fn tolerance_limits_are_inclusive() {
+        let grays = image(&["02468."]);
+        let covered_by = |tolerance| covered(&region(tolerance, false).coverage(&grays, 0, 0), 6);
+        assert_eq!(covered_by(0.0), ["*-----"]);
+        assert_eq!(covered_by(0.4), ["***---"]);
+        assert_eq!(covered_by(0.39), ["**----"]);
+        assert_eq!(covered_by(1.0), ["******"]);
+
+        // A contiguous region stops at the first pixel out of tolerance.
+        let image = image(&["0608"]);
+        let coverage = region(0.5, true).coverage(&image, 0, 0);
+        assert_eq!(covered(&coverage, 4), ["*---"]);
+    }
+
+    #[test]
+    fn transparent_pixels_match_in_rgba_only() {
+        let red = ImagePixel::from([1.0, 0.0, 0.0, 0.0]);
+        let blue = ImagePixel::from([0.0, 0.0, 1.0, 0.0]);
+        let image = Image::from_fn(2, 1, |x, _| if x == 0 { red } else { blue });
+        let mut region = region(0.0, true);
+        assert_eq!(region.coverage(&image, 0, 0), [1.0, 1.0]);
+        region.metric = ColorMetric::Rgb;
+        assert_eq!(region.coverage(&image, 0, 0), [1.0, 0.0]);
+    }
+
+    #[test]
+    fn antialiased_edges_are_partly_covered() {
+        let image = image(&["#5.#"]);
+        let mut region = region(0.0, true);
+        region.antialias = true;
+        // Only pixels next to the region are covered, by how close they are.
+        assert_eq!(region.coverage(&image, 0, 0), [1.0, 0.5, 0.0, 0.0]);
+    }
+
+    #[test]
+    fn fills_are_masked_by_the_selection() {
+        let mut layers = LayerStack::new(image(&["....", "...."]));
+        let mask = vec![1.0, 0.5, 0.0, 0.0, 1.0, 1.0, 0.0, 0.0];
+        layers.set_selection(Selection::from_mask(4, 2, mask));
+        let black = ImagePixel::from([0.0, 0.0, 0.0, 1.0]);
+        let op = BucketFill::new(black)
+            .with_contiguous(false)
+            .apply(&mut layers, 3, 0)
+            .unwrap();
+        let image = layers.active_image();
+        assert_eq!(image.get_pixel(0, 0).0, [0.0, 0.0, 0.0, 1.0]);
+        assert_eq!(image.get_pixel(1, 0).0, [0.5, 0.5, 0.5, 1.0]);
+        assert_eq!(image.get_pixel(2, 0).0, [1.0; 4]);
+        assert_eq!(image.get_pixel(3, 1).0, [1.0; 4]);
+        // Only the selected pixels are kept for undo.
+        assert_eq!(op.kept().count(), 4);
+
+        // Nothing is filled outside the selection.
+        layers.set_selection(Selection::from_mask(
+            4,
+            2,
+            vec![0.0, 0.0, 0.0, 0.0, 1.0, 0.0, 0.0, 0.0],
+        ));
+        assert!(BucketFill::new(black).apply(&mut layers, 3, 0).is_none());
+    }
+}
@@ -0,0 +1,112 @@
+//! Stabilization and smoothing of drag input.
+use std::collections::VecDeque;
+use teal_base::InputSample;
+
+/// A drag point as an offset from the first screen point, with its input
+/// sample.
+pub(crate) type DragPoint = (f64, f64, InputSample);
+
+/// How the pointer positions of a drag are stabilized.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum Stabilizer {
+    /// Use the pointer positions as they are.
+    None,
+
+    /// Average the last given number of pointer positions.
+    MovingAverage(usize),
+
+    /// Pull the stroke behind the pointer on a rope of the given length in
+    /// screen pixels, so it only moves once the rope is taut.
+    LazyRope(f64),
+}
+
+/// Smoothing settings for drag input.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Smoothing {
+    /// Stabilizer of the pointer positions.
+    pub stabilizer: Stabilizer,
+
+    /// Draw curves through the stabilized points instead of straight lines.
+    pub curves: bool,
+}
+
+impl Default for Smoothing {
+    /// No smoothing.
+    fn default() -> Smoothing {
+        Smoothing {
+            stabilizer: Stabilizer::None,
+            curves: false,
+        }
+    }
+}
+
+impl Stabilizer {
+    /// Stabilize the next pointer position, given the recent pointer
+    /// positions and the last stabilized point.
+    ///
+    /// Returns None if the stroke doesn't move.
+    pub(crate) fn apply(
+        &self,
+        recent: &mut VecDeque<DragPoint>,
+        last: DragPoint,
+        point: DragPoint,
+    ) -> Option<DragPoint> {
+        match *self {
+            Stabilizer::None => Some(point),
+            Stabilizer::MovingAverage(count) => {
+                recent.push_back(point);
+                while recent.len() > count.max(1) {
+                    recent.pop_front();
+                }
+                let len = recent.len() as f64;
+                let x = recent.iter().map(|point| point.0).sum::<f64>() / len;
+                let y = recent.iter().map(|point| point.1).sum::<f64>() / len;
+                Some((x, y, point.2))
+            }
+            Stabilizer::LazyRope(length) => {
+                let dx = point.0 - last.0;
+                let dy = point.1 - last.1;
+                let dist = (dx * dx + dy * dy).sqrt();
+                if dist <= length {
+                    return None;
+                }
+                let t = (dist - length) / dist;
+                Some((last.0 + dx * t, last.1 + dy * t, point.2))
+            }
+        }
+    }
+
+    /// Does the stroke catch up with the pointer when the drag ends?
+    pub(crate) fn catches_up(&self) -> bool {
+        !matches!(self, Stabilizer::LazyRope(_))
+    }
+}
+
+/// Point at t (0.0 - 1.0) on the centripetal Catmull-Rom curve from p[1] to
+/// p[2]. The centripetal form doesn't overshoot or loop at sharp turns.
+pub(crate) fn catmull_rom(p: [(f64, f64); 4], t: f64) -> (f64, f64) {
+    // Knots are spaced by the square root of the distance between points,
+    // kept apart for repeated points.
+    let knot = |a: (f64, f64), b: (f64, f64)| {
+        let dist = ((b.0 - a.0).powi(2) + (b.1 - a.1).powi(2)).sqrt();
+        dist.sqrt().max(1e-4)
+    };
+    let t0 = 0.0;
+    let t1 = t0 + knot(p[0], p[1]);
+    let t2 = t1 + knot(p[1], p[2]);
+    let t3 = t2 + knot(p[2], p[3]);
+    let u = t1 + (t2 - t1) * t;
+    let lerp = |a: (f64, f64), b: (f64, f64), ta: f64, tb: f64| {
+        let wa = (tb - u) / (tb - ta);
+        let wb = (u - ta) / (tb - ta);
+        (a.0 * wa + b.0 * wb, a.1 * wa + b.1 * wb)
+    };
+
+    // Barry and Goldman's pyramidal formulation.
+    let a1 = lerp(p[0], p[1], t0, t1);
+    let a2 = lerp(p[1], p[2], t1, t2);
+    let a3 = lerp(p[2], p[3], t2, t3);
+    let b1 = lerp(a1, a2, t0, t2);
+    let b2 = lerp(a2, a3, t1, t3);
+    lerp(b1, b2, t1, t2)
+}
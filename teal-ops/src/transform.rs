@@ -0,0 +1,643 @@
+//! Free transform of layer pixels.
+//!
+//! A `FreeTransform` takes the selected pixels of a layer (or the whole layer)
+//! and draws them back through an affine transform, keeping the original
+//! pixels so the transform can be changed any number of times. The result is
+//! committed as a single `RectOp` holding the original pixels.
+//!
+//! A `TransformDrag` changes the transform with handles: dragging a corner
+//! handle scales both axes and an edge handle scales one, each around the
+//! opposite handle. Dragging inside the transformed pixels moves them, and
+//! dragging outside rotates them around their center. Scaling past the
+//! opposite handle flips the pixels.
+use crate::{Clip, DragHandler, Operation, PixelOp, RectOp, StrokePoint};
+use std::cell::RefCell;
+use std::rc::Rc;
+use teal_base::{Image, ImagePixel, ImageView, LayerId, LayerStack, PaintMode, Selection};
+
+/// Distance in screen pixels from a handle within which a drag grabs it.
+const HANDLE_GRAB_DISTANCE: f64 = 8.0;
+
+/// How pixels are sampled when drawn through a transform.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq)]
+pub enum Resampling {
+    /// Closest pixel, keeping hard pixel edges
+    Nearest,
+
+    /// Linear interpolation of the 4 closest pixels
+    #[default]
+    Bilinear,
+
+    /// Catmull-Rom interpolation of the 16 closest pixels, keeping more
+    /// detail when enlarging
+    Bicubic,
+}
+
+impl Resampling {
+    /// All resampling methods, in cycling order.
+    pub const ALL: [Resampling; 3] = [
+        Resampling::Nearest,
+        Resampling::Bilinear,
+        Resampling::Bicubic,
+    ];
+
+    /// Next method in cycling order.
+    pub fn next(self) -> Resampling {
+        let index = Resampling::ALL
+            .iter()
+            .position(|method| *method == self)
+            .unwrap_or(0);
+        Resampling::ALL[(index + 1) % Resampling::ALL.len()]
+    }
+
+    /// Name of the method.
+    pub fn name(self) -> &'static str {
+        match self {
+            Resampling::Nearest => "nearest",
+            Resampling::Bilinear => "bilinear",
+            Resampling::Bicubic => "bicubic",
+        }
+    }
+
+    /// Look up a method by name.
+    pub fn from_name(name: &str) -> Option<Resampling> {
+        Resampling::ALL
+            .into_iter()
+            .find(|method| method.name() == name)
+    }
+
+    /// Weights of the pixels from one before to two after the pixel at
+    /// fraction `t` between two pixel centers.
+    fn weights(self, t: f64) -> [f64; 4] {
+        match self {
+            Resampling::Nearest if t < 0.5 => [0.0, 1.0, 0.0, 0.0],
+            Resampling::Nearest => [0.0, 0.0, 1.0, 0.0],
+            Resampling::Bilinear => [0.0, 1.0 - t, t, 0.0],
+            Resampling::Bicubic => [
+                ((-0.5 * t + 1.0) * t - 0.5) * t,
+                (1.5 * t - 2.5) * t * t + 1.0,
+                ((-1.5 * t + 2.0) * t + 0.5) * t,
+                (0.5 * t - 0.5) * t * t,
+            ],
+        }
+    }
+
+    /// Sample values at continuous coordinates (x, y), where pixel (i, j)
+    /// covers [i, i + 1) x [j, j + 1). `get` returns the values of a pixel,
+    /// which should have premultiplied alpha to keep transparent colors from
+    /// bleeding in.
+    fn sample(self, x: f64, y: f64, get: impl Fn(i64, i64) -> [f32; 4]) -> [f32; 4] {
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let weights_x = self.weights(x - x0);
+        let weights_y = self.weights(y - y0);
+        let mut out = [0.0; 4];
+        for (j, weight_y) in weights_y.iter().enumerate() {
+            if *weight_y == 0.0 {
+                continue;
+            }
+            for (i, weight_x) in weights_x.iter().enumerate() {
+                let weight = (weight_x * weight_y) as f32;
+                if weight == 0.0 {
+                    continue;
+                }
+                let values = get(x0 as i64 + i as i64 - 1, y0 as i64 + j as i64 - 1);
+                for (out, value) in out.iter_mut().zip(values) {
+                    *out += weight * value;
+                }
+            }
+        }
+        out
+    }
+}
+
+/// An affine transform of image coordinates.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Affine {
+    /// Matrix rows, mapping (x, y) to (m[0] x + m[1] y + m[2],
+    /// m[3] x + m[4] y + m[5]).
+    m: [f64; 6],
+}
+
+impl Affine {
+    /// The transform that changes nothing.
+    pub const IDENTITY: Affine = Affine {
+        m: [1.0, 0.0, 0.0, 0.0, 1.0, 0.0],
+    };
+
+    /// Move by (dx, dy).
+    pub fn translate(dx: f64, dy: f64) -> Affine {
+        Affine {
+            m: [1.0, 0.0, dx, 0.0, 1.0, dy],
+        }
+    }
+
+    /// Scale by factors along each axis, flipping for negative factors.
+    pub fn scale(sx: f64, sy: f64) -> Affine {
+        Affine {
+            m: [sx, 0.0, 0.0, 0.0, sy, 0.0],
+        }
+    }
+
+    /// Rotate by an angle in radians, clockwise on the screen.
+    pub fn rotate(angle: f64) -> Affine {
+        let (sin, cos) = angle.sin_cos();
+        Affine {
+            m: [cos, -sin, 0.0, sin, cos, 0.0],
+        }
+    }
+
+    /// The same transform, applied around (x, y) instead of the origin.
+    pub fn around(self, x: f64, y: f64) -> Affine {
+        Affine::translate(-x, -y)
+            .then(self)
+            .then(Affine::translate(x, y))
+    }
+
+    /// This transform followed by another one.
+    pub fn then(self, next: Affine) -> Affine {
+        let [a, b, c, d, e, f] = self.m;
+        let [na, nb, nc, nd, ne, nf] = next.m;
+        Affine {
+            m: [
+                na * a + nb * d,
+                na * b + nb * e,
+                na * c + nb * f + nc,
+                nd * a + ne * d,
+                nd * b + ne * e,
+                nd * c + ne * f + nf,
+            ],
+        }
+    }
+
+    /// Transform the point (x, y).
+    pub fn apply(&self, x: f64, y: f64) -> (f64, f64) {
+        let [a, b, c, d, e, f] = self.m;
+        (a * x + b * y + c, d * x + e * y + f)
+    }
+
+    /// The transform undoing this one, or None if it collapses the plane
+    /// onto a line or point.
+    pub fn inverse(&self) -> Option<Affine> {
+        let [a, b, c, d, e, f] = self.m;
+        let det = a * e - b * d;
+        if det.abs() < 1e-12 {
+            return None;
+        }
+        Some(Affine {
+            m: [
+                e / det,
+                -b / det,
+                (b * f - c * e) / det,
+                -d / det,
+                a / det,
+                (c * d - a * f) / det,
+            ],
+        })
+    }
+}
+
+/// What a transform drag grabbed.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+pub enum Grab {
+    /// A handle, by its index in `FreeTransform::handles()`
+    Handle(usize),
+
+    /// The inside of the transformed pixels
+    Move,
+
+    /// The outside of the transformed pixels
+    Rotate,
+}
+
+/// Pixels of a layer drawn through an affine transform until the transform
+/// is committed or cancelled.
+pub struct FreeTransform {
+    /// Transformed pixels, as they were before the transform.
+    clip: Clip,
+
+    /// Layer that the pixels were taken from.
+    layer: LayerId,
+
+    /// Transform from the original image coordinates of the pixels.
+    affine: Affine,
+
+    resampling: Resampling,
+
+    /// Selection the pixels were taken with, if any.
+    selection: Option<Selection>,
+
+    /// Original layer pixels that were taken out for the transform, if any.
+    cut: Option<RectOp>,
+
+    /// Top left corner and layer pixels of the area the transformed pixels
+    /// are drawn over, as they were before being drawn.
+    under: Option<(u32, u32, Image)>,
+}
+
+impl FreeTransform {
+    /// Take the selected pixels of the active layer, or the whole layer if
+    /// there is no selection, for transforming. The selection is put away
+    /// until the transform is committed or cancelled.
+    pub fn new(layers: &mut LayerStack, resampling: Resampling) -> FreeTransform {
+        let selection = layers.selection().cloned();
+        let (clip, op) = Clip::cut(layers);
+        layers.set_selection(None);
+        let mut transform = FreeTransform {
+            clip,
+            layer: layers.active().id,
+            affine: Affine::IDENTITY,
+            resampling,
+            selection,
+            cut: op,
+            under: None,
+        };
+        transform.render(&mut layers.active_mut().image);
+        transform
+    }
+
+    /// Layer that the pixels were taken from.
+    pub fn layer(&self) -> LayerId {
+        self.layer
+    }
+
+    /// Current transform.
+    pub fn affine(&self) -> Affine {
+        self.affine
+    }
+
+    /// Resampling method.
+    pub fn resampling(&self) -> Resampling {
+        self.resampling
+    }
+
+    /// Change the transform, redrawing the pixels on the image of their
+    /// layer.
+    pub fn set_affine(&mut self, image: &mut Image, affine: Affine) {
+        self.affine = affine;
+        self.render(image);
+    }
+
+    /// Change the resampling method, redrawing the pixels.
+    pub fn set_resampling(&mut self, image: &mut Image, resampling: Resampling) {
+        self.resampling = resampling;
+        self.render(image);
+    }
+
+    /// Rotate by an angle in radians around the center of the transformed
+    /// pixels.
+    pub fn rotate(&mut self, image: &mut Image, angle: f64) {
+        let (x, y) = self.center();
+        let affine = self.affine.then(Affine::rotate(angle).around(x, y));
+        self.set_affine(image, affine);
+    }
+
+    /// Scale along the transformed axes around the center of the pixels,
+    /// flipping for negative factors.
+    pub fn scale(&mut self, image: &mut Image, sx: f64, sy: f64) {
+        let (x, y) = self.local_handle(CENTER);
+        let affine = Affine::scale(sx, sy).around(x, y).then(self.affine);
+        self.set_affine(image, affine);
+    }
+
+    /// Flip horizontally or vertically along the transformed axes.
+    pub fn flip(&mut self, image: &mut Image, horizontal: bool) {
+        if horizontal {
+            self.scale(image, -1.0, 1.0);
+        } else {
+            self.scale(image, 1.0, -1.0);
+        }
+    }
+
+    /// Handle positions in image coordinates: the corners and edge midpoints
+    /// of the transformed pixels, clockwise from the top left corner.
+    pub fn handles(&self) -> [(f64, f64); 8] {
+        HANDLES.map(|handle| {
+            let (x, y) = self.local_handle(handle);
+            self.affine.apply(x, y)
+        })
+    }
+
+    /// Center of the transformed pixels in image coordinates.
+    pub fn center(&self) -> (f64, f64) {
+        let (x, y) = self.local_handle(CENTER);
+        self.affine.apply(x, y)
+    }
+
+    /// Find what a drag starting at image coordinates (x, y) grabs. Handles
+    /// are grabbed within `distance` image pixels.
+    pub fn grab(&self, x: f64, y: f64, distance: f64) -> Grab {
+        let nearest = self
+            .handles()
+            .iter()
+            .map(|(hx, hy)| (hx - x).hypot(hy - y))
+            .enumerate()
+            .min_by(|(_, a), (_, b)| a.total_cmp(b));
+        if let Some((index, handle_distance)) = nearest {
+            if handle_distance <= distance {
+                return Grab::Handle(index);
+            }
+        }
+        let Some(inverse) = self.affine.inverse() else {
+            return Grab::Move;
+        };
+        let (local_x, local_y) = inverse.apply(x, y);
+        let (left, top) = self.local_handle((0.0, 0.0));
+        let (right, bottom) = self.local_handle((1.0, 1.0));
+        if (left..=right).contains(&local_x) && (top..=bottom).contains(&local_y) {
+            Grab::Move
+        } else {
+            Grab::Rotate
+        }
+    }
+
+    /// Transform after dragging what was grabbed from `start` to `end`, in
+    /// image coordinates, starting from the transform `initial`.
+    pub fn dragged(
+        &self,
+        initial: Affine,
+        grab: Grab,
+        start: (f64, f64),
+        end: (f64, f64),
+    ) -> Affine {
+        match grab {
+            Grab::Move => initial.then(Affine::translate(end.0 - start.0, end.1 - start.1)),
+            Grab::Rotate => {
+                let (x, y) = self.local_handle(CENTER);
+                let (cx, cy) = initial.apply(x, y);
+                let angle = (end.1 - cy).atan2(end.0 - cx) - (start.1 - cy).atan2(start.0 - cx);
+                initial.then(Affine::rotate(angle).around(cx, cy))
+            }
+            Grab::Handle(index) => {
+                let Some(inverse) = initial.inverse() else {
+                    return initial;
+                };
+                // Scale in the coordinates before the transform, around the
+                // opposite handle.
+                let anchor = self.local_handle(HANDLES[(index + 4) % HANDLES.len()]);
+                let from = inverse.apply(start.0, start.1);
+                let to = inverse.apply(end.0, end.1);
+                let factor = |from: f64, to: f64, anchor: f64| {
+                    if (from - anchor).abs() < 1e-9 {
+                        1.0
+                    } else {
+                        (to - anchor) / (from - anchor)
+                    }
+                };
+                let (handle_x, handle_y) = HANDLES[index];
+                let sx = if handle_x == 0.5 {
+                    1.0
+                } else {
+                    factor(from.0, to.0, anchor.0)
+                };
+                let sy = if handle_y == 0.5 {
+                    1.0
+                } else {
+                    factor(from.1, to.1, anchor.1)
+                };
+                Affine::scale(sx, sy)
+                    .around(anchor.0, anchor.1)
+                    .then(initial)
+            }
+        }
+    }
+
+    /// Selection transformed like the pixels, if they were taken with a
+    /// selection.
+    fn transformed_selection(&self) -> Option<Selection> {
+        let selection = self.selection.as_ref()?;
+        let (width, height) = (selection.width(), selection.height());
+        let inverse = self.affine.inverse()?;
+        let get = |x: i64, y: i64| {
+            if x < 0 || y < 0 {
+                return [0.0; 4];
+            }
+            [selection.value(x as u32, y as u32), 0.0, 0.0, 0.0]
+        };
+        let mut mask = Vec::with_capacity(width as usize * height as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let (sx, sy) = inverse.apply(x as f64 + 0.5, y as f64 + 0.5);
+                let value = self.resampling.sample(sx, sy, get)[0];
+                mask.push(value.clamp(0.0, 1.0));
+            }
+        }
+        Selection::from_mask(width, height, mask)
+    }
+
+    /// Keep the transformed pixels and select them if they were taken with a
+    /// selection. Returns the operation to undo the transform, or None if no
+    /// pixel changed.
+    pub fn commit(self, layers: &mut LayerStack) -> Option<RectOp> {
+        if let Some(selection) = self.transformed_selection() {
+            layers.set_selection(Some(selection));
+        }
+        // The operation covers both the cut pixels and the area drawn over.
+        let rects = [
+            self.cut.as_ref().map(|cut| (cut.origin, cut.dims)),
+            self.under
+                .as_ref()
+                .map(|(x, y, under)| ((*x, *y), under.dimensions())),
+        ];
+        let (mut left, mut top) = (u32::MAX, u32::MAX);
+        let (mut right, mut bottom) = (0, 0);
+        for ((x, y), (width, height)) in rects.into_iter().flatten() {
+            (left, top) = (left.min(x), top.min(y));
+            (right, bottom) = (right.max(x + width), bottom.max(y + height));
+        }
+        if left >= right || top >= bottom {
+            return None;
+        }
+
+        // The cut pixels are the originals where both overlap.
+        let mut op = RectOp::new(self.layer, left, top, right - left, bottom - top);
+        for (x, y, pixel) in self.cut.iter().flat_map(|cut| cut.kept()) {
+            op.keep(x, y, *pixel);
+        }
+        if let (Some((left, top, under)), Some(layer)) = (self.under, layers.get(self.layer)) {
+            for (x, y, pixel) in under.enumerate_pixels() {
+                let (x, y) = (left + x, top + y);
+                if layer.image.get_pixel(x, y) != pixel {
+                    op.keep(x, y, *pixel);
+                }
+            }
+        }
+        (!op.is_empty()).then_some(op)
+    }
+
+    /// Put the original pixels and selection back.
+    pub fn cancel(mut self, layers: &mut LayerStack) {
+        layers.set_selection(self.selection.take());
+        if let Some(layer) = layers.get_mut(self.layer) {
+            self.lift(&mut layer.image);
+        }
+        if let Some(mut cut) = self.cut.take() {
+            cut.undo(layers);
+        }
+    }
+
+    /// Position of a handle in the coordinates before the transform, from
+    /// fractions of the width and height of the pixels.
+    fn local_handle(&self, (fx, fy): (f64, f64)) -> (f64, f64) {
+        let (width, height) = self.clip.dimensions();
+        let (x, y) = self.clip.origin();
+        (x as f64 + fx * width as f64, y as f64 + fy * height as f64)
+    }
+
+    /// Restore the layer pixels under the transformed pixels.
+    fn lift(&mut self, image: &mut Image) {
+        if let Some((left, top, under)) = self.under.take() {
+            for (x, y, pixel) in under.enumerate_pixels() {
+                image.put_pixel(left + x, top + y, *pixel);
+            }
+        }
+    }
+
+    /// Draw the pixels through the transform over the image, keeping the
+    /// layer pixels under them.
+    fn render(&mut self, image: &mut Image) {
+        self.lift(image);
+        let Some(inverse) = self.affine.inverse() else {
+            return;
+        };
+
+        // Area covered by the transformed pixels, with room for the
+        // resampling filter.
+        let corners = HANDLES.map(|handle| {
+            let (x, y) = self.local_handle(handle);
+            self.affine.apply(x, y)
+        });
+        let (min_x, max_x, min_y, max_y) = corners.iter().fold(
+            (
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+                f64::INFINITY,
+                f64::NEG_INFINITY,
+            ),
+            |(min_x, max_x, min_y, max_y), (x, y)| {
+                (min_x.min(*x), max_x.max(*x), min_y.min(*y), max_y.max(*y))
+            },
+        );
+        let clamp = |value: f64, max: u32| value.clamp(0.0, max as f64) as u32;
+        let (left, right) = (
+            clamp(min_x.floor() - 2.0, image.width()),
+            clamp(max_x.ceil() + 2.0, image.width()),
+        );
+        let (top, bottom) = (
+            clamp(min_y.floor() - 2.0, image.height()),
+            clamp(max_y.ceil() + 2.0, image.height()),
+        );
+        if left >= right || top >= bottom {
+            return;
+        }
+
+        let clip = self.clip.image();
+        let (origin_x, origin_y) = self.clip.origin();
+        let get = |x: i64, y: i64| {
+            if x < 0 || y < 0 {
+                return [0.0; 4];
+            }
+            match clip.get_pixel_checked(x as u32, y as u32) {
+                Some(pixel) => {
+                    let [r, g, b, a] = pixel.0;
+                    [r * a, g * a, b * a, a]
+                }
+                None => [0.0; 4],
+            }
+        };
+        let under = Image::from_fn(right - left, bottom - top, |x, y| {
+            *image.get_pixel(left + x, top + y)
+        });
+        for y in top..bottom {
+            for x in left..right {
+                let (sx, sy) = inverse.apply(x as f64 + 0.5, y as f64 + 0.5);
+                let (sx, sy) = (sx - origin_x as f64, sy - origin_y as f64);
+                if sx < -2.0
+                    || sy < -2.0
+                    || sx > clip.width() as f64 + 2.0
+                    || sy > clip.height() as f64 + 2.0
+                {
+                    continue;
+                }
+                let [r, g, b, a] = self.resampling.sample(sx, sy, get);
+                let alpha = a.min(1.0);
+                if alpha <= 0.0 {
+                    continue;
+                }
+                // Bicubic overshoot can make colors negative.
+                let source =
+                    ImagePixel::from([(r / a).max(0.0), (g / a).max(0.0), (b / a).max(0.0), alpha]);
+                let pixel = image.get_pixel_mut(x, y);
+                *pixel = PaintMode::Normal.composite(pixel, &source, 1.0);
+            }
+        }
+        self.under = Some((left, top, under));
+    }
+}
+
+/// Handle positions as fractions of the width and height of the pixels,
+/// clockwise from the top left corner. Opposite handles are 4 apart.
+const HANDLES: [(f64, f64); 8] = [
+    (0.0, 0.0),
+    (0.5, 0.0),
+    (1.0, 0.0),
+    (1.0, 0.5),
+    (1.0, 1.0),
+    (0.5, 1.0),
+    (0.0, 1.0),
+    (0.0, 0.5),
+];
+
+/// Center of the pixels, as fractions of their width and height.
+const CENTER: (f64, f64) = (0.5, 0.5);
+
+/// Drag handler changing a free transform with its handles.
+pub struct TransformDrag {
+    transform: Rc<RefCell<FreeTransform>>,
+
+    /// Start point of the drag in image coordinates, what it grabbed and the
+    /// transform when it started.
+    start: Option<((f64, f64), Grab, Affine)>,
+}
+
+impl TransformDrag {
+    /// Create a drag handler for a free transform, which must be on the
+    /// layer being dragged on.
+    pub fn new(transform: Rc<RefCell<FreeTransform>>) -> TransformDrag {
+        TransformDrag {
+            transform,
+            start: None,
+        }
+    }
+}
+
+impl DragHandler for TransformDrag {
+    /// Change the transform by the drag so far. The selection doesn't mask
+    /// the transformed pixels.
+    fn handle_line(
+        &mut self,
+        image: &mut Image,
+        _selection: Option<&Selection>,
+        image_view: &mut ImageView,
+        a: StrokePoint,
+        b: StrokePoint,
+    ) {
+        let mut transform = self.transform.borrow_mut();
+        let (start, grab, initial) = *self.start.get_or_insert_with(|| {
+            let distance = HANDLE_GRAB_DISTANCE / image_view.zoom();
+            (
+                (a.x, a.y),
+                transform.grab(a.x, a.y, distance),
+                transform.affine(),
+            )
+        });
+        let affine = transform.dragged(initial, grab, start, (b.x, b.y));
+        transform.set_affine(image, affine);
+    }
+
+    /// Changes are part of the transform, which is undone as a whole once it
+    /// is committed.
+    fn to_op(&self, _layer: LayerId) -> Option<PixelOp> {
+        None
+    }
+}
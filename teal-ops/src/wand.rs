@@ -0,0 +1,173 @@
+//! Magic wand selection.
+use crate::region::{ColorMetric, Region};
+use crate::FillSource;
+use teal_base::{LayerStack, Selection};
+
+/// A magic wand, selecting the pixels of similar color to a start pixel.
+#[derive(Clone, Debug)]
+pub struct MagicWand {
+    /// Largest color distance from the start pixel that is still selected,
+    /// from 0.0 - 1.0.
+    tolerance: f32,
+
+    /// Only select pixels connected to the start pixel.
+    contiguous: bool,
+
+    /// Partly select the pixels around the selected area by how close they
+    /// are to matching.
+    antialias: bool,
+
+    /// How color differences are measured.
+    metric: ColorMetric,
+
+    /// Image that colors are compared in.
+    source: FillSource,
+}
+
+impl Default for MagicWand {
+    /// Contiguous with hard edges, comparing colors in the active layer.
+    fn default() -> MagicWand {
+        MagicWand {
+            tolerance: 0.1,
+            contiguous: true,
+            antialias: false,
+            metric: ColorMetric::Rgba,
+            source: FillSource::Layer,
+        }
+    }
+}
+
+impl MagicWand {
+    /// Set the color tolerance, from 0.0 - 1.0.
+    pub fn with_tolerance(mut self, tolerance: f32) -> MagicWand {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Set whether only pixels connected to the start pixel are selected.
+    pub fn with_contiguous(mut self, contiguous: bool) -> MagicWand {
+        self.contiguous = contiguous;
+        self
+    }
+
+    /// Set whether the edges of the selection are anti-aliased.
+    pub fn with_antialias(mut self, antialias: bool) -> MagicWand {
+        self.antialias = antialias;
+        self
+    }
+
+    /// Set how color differences are measured.
+    pub fn with_metric(mut self, metric: ColorMetric) -> MagicWand {
+        self.metric = metric;
+        self
+    }
+
+    /// Set the image that colors are compared in.
+    pub fn with_source(mut self, source: FillSource) -> MagicWand {
+        self.source = source;
+        self
+    }
+
+    /// Select from image coordinates (x, y). Returns None if the point is
+    /// outside the canvas.
+    pub fn select(&self, layers: &LayerStack, x: u32, y: u32) -> Option<Selection> {
+        let (width, height) = (layers.width(), layers.height());
+        if x >= width || y >= height {
+            return None;
+        }
+        let region = Region {
+            tolerance: self.tolerance,
+            contiguous: self.contiguous,
+            antialias: self.antialias,
+            metric: self.metric,
+        };
+        let mask = match self.source {
+            FillSource::Layer => region.coverage(layers.active_image(), x, y),
+            FillSource::Composite => region.coverage(&layers.flatten(), x, y),
+        };
+        Selection::from_mask(width, height, mask)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use teal_base::{Image, ImagePixel};
+
+    /// A single row of opaque linear RGB pixels.
+    fn layers(row: &[[f32; 3]]) -> LayerStack {
+        LayerStack::new(Image::from_fn(row.len() as u32, 1, |x, _| {
+            let [r, g, b] = row[x as usize];
+            ImagePixel::from([r, g, b, 1.0])
+        }))
+    }
+
+    fn selected(wand: &MagicWand, layers: &LayerStack, x: u32) -> Vec<f32> {
+        wand.select(layers, x, 0).unwrap().mask().to_vec()
+    }
+
+    const WHITE: [f32; 3] = [1.0; 3];
+    const BLACK: [f32; 3] = [0.0; 3];
+
+    #[test]
+    fn contiguous_and_global_selections() {
+        let layers = layers(&[WHITE, WHITE, BLACK, WHITE]);
+        let wand = MagicWand::default();
+        assert_eq!(selected(&wand, &layers, 0), [1.0, 1.0, 0.0, 0.0]);
+        assert_eq!(selected(&wand, &layers, 2), [0.0, 0.0, 1.0, 0.0]);
+        let wand = wand.with_contiguous(false);
+        assert_eq!(selected(&wand, &layers, 3), [1.0, 1.0, 0.0, 1.0]);
+    }
+
+    #[test]
+    fn points_outside_the_canvas_select_nothing() {
+        let layers = layers(&[WHITE]);
+        assert!(MagicWand::default().select(&layers, 1, 0).is_none());
+        assert!(MagicWand::default().select(&layers, 0, 1).is_none());
+    }
+
+    #[test]
+    fn lab_tolerates_more_in_light_colors() {
+        // Light grays differ less in perceived lightness than in linear
+        // values.
+        let layers = layers(&[WHITE, [0.7; 3]]);
+        let wand = MagicWand::default().with_tolerance(0.2);
+        let rgb = wand.clone().with_metric(ColorMetric::Rgb);
+        let lab = wand.with_metric(ColorMetric::Lab);
+        assert_eq!(selected(&rgb, &layers, 0), [1.0, 0.0]);
+        assert_eq!(selected(&lab, &layers, 0), [1.0, 1.0]);
+    }
+
+    #[test]
+    fn lab_tolerates_less_in_dark_colors() {
+        // A dark green is far lighter than black to the eye.
+        let layers = layers(&[BLACK, [0.0, 0.1, 0.0]]);
+        let wand = MagicWand::default().with_tolerance(0.2);
+        let rgb = wand.clone().with_metric(ColorMetric::Rgb);
+        let lab = wand.with_metric(ColorMetric::Lab);
+        assert_eq!(selected(&rgb, &layers, 0), [1.0, 1.0]);
+        assert_eq!(selected(&lab, &layers, 0), [1.0, 0.0]);
+    }
+
+    #[test]
+    fn composite_source_compares_visible_layers() {
+        let mut layers = layers(&[WHITE, BLACK]);
+        layers.add_layer("top");
+
+        // The empty active layer is the same everywhere.
+        let wand = MagicWand::default();
+        assert_eq!(selected(&wand, &layers, 0), [1.0, 1.0]);
+        let wand = wand.with_source(FillSource::Composite);
+        assert_eq!(selected(&wand, &layers, 0), [1.0, 0.0]);
+    }
+
+    #[test]
+    fn antialiased_selections_partly_select_the_edge() {
+        let layers = layers(&[WHITE, [0.5; 3], BLACK]);
+        let wand = MagicWand::default()
+            .with_tolerance(0.0)
+            .with_metric(ColorMetric::Rgb)
+            .with_antialias(true);
+        assert_eq!(selected(&wand, &layers, 0), [1.0, 0.5, 0.0]);
+    }
+}
@@ -1,22 +1,49 @@
 //! Teal paint main application
 use std::env;
+use std::path::{Path, PathBuf};
 use std::process::ExitCode;
 use std::error::Error;
-use clap::Parser;
+use std::time::Instant;
+use clap::{Parser, Subcommand};
 
 /// Main input arguments.
 #[derive(Parser, Debug)]
-#[command(version, about)]
+#[command(version, about, subcommand_negates_reqs = true)]
 struct TealArgs {
     /// Image file path.
-    #[arg(short, long)]
-    file_path: String,
+    #[arg(short, long, required = true)]
+    file_path: Option<String>,
 
     /// Optional new image dimensions (in format WIDTHxHEIGHT).
     #[arg(short, long, value_parser = parse_dims)]
     dims: Option<(u32, u32)>,
+
+    /// Record all input events to a JSON lines file.
+    #[arg(long)]
+    record: Option<PathBuf>,
+
+    /// Replay a recorded event file without a display, instead of starting
+    /// the GUI.
+    #[arg(long)]
+    replay: Option<PathBuf>,
+
+    #[command(subcommand)]
+    command: Option<TealCommand>,
+}
+
+/// Subcommands.
+#[derive(Subcommand, Debug)]
+enum TealCommand {
+    /// Run a batch script without a display.
+    Batch {
+        /// Batch script path.
+        script: PathBuf,
+    },
 }
 
+/// Screen dimensions used for replays without a recorded resize.
+const REPLAY_SCREEN_DIMS: (u32, u32) = (800, 600);
+
 fn parse_dims(s: &str) -> Result<(u32, u32), Box<dyn Error + Send + Sync + 'static>> {
     let idx = s
         .find('x')
@@ -26,13 +53,55 @@ fn parse_dims(s: &str) -> Result<(u32, u32), Box<dyn Error + Send + Sync + 'stat
 
 fn main() -> ExitCode {
     let teal_args = TealArgs::parse();
-    let args = teal_main::Args {
-        fname: teal_args.file_path,
-        dims: teal_args.dims,
-    };
     let config_data = std::fs::read_to_string("./teal.toml")
         .expect("failed to read teal config");
     let config: teal_main::Config =
         toml::from_str(&config_data).expect("failed to parse teal config");
+    if let Err(errors) = config.validate() {
+        for err in errors {
+            eprintln!("teal.toml: {err}");
+        }
+        return ExitCode::FAILURE;
+    }
+    if let Some(TealCommand::Batch { script }) = teal_args.command {
+        return teal_main::run_batch(script, config);
+    }
+    let args = teal_main::Args {
+        fname: teal_args.file_path.expect("missing image file path"),
+        dims: teal_args.dims,
+        record: teal_args.record,
+    };
+    if let Some(replay_path) = teal_args.replay {
+        return replay(&replay_path, args, config);
+    }
     teal_main::run(args, config, teal_gui::GtkGUI::new())
 }
+
+/// Replay a recorded event file with the headless GUI and report timing.
+fn replay(replay_path: &Path, args: teal_main::Args, config: teal_main::Config) -> ExitCode {
+    let events = match teal_main::load_recording(replay_path) {
+        Ok(events) => events,
+        Err(err) => {
+            eprintln!("failed to load event recording: {err}");
+            return ExitCode::FAILURE;
+        }
+    };
+    // Start with the screen size of the first recorded resize, if any.
+    let (width, height) = events
+        .iter()
+        .find_map(|recorded| match recorded.event {
+            teal_base::Event::Resize { width, height } => Some((width, height)),
+            _ => None,
+        })
+        .unwrap_or(REPLAY_SCREEN_DIMS);
+    let mut gui = teal_headless::HeadlessGUI::new(width, height);
+    let count = events.len();
+    for recorded in events {
+        gui.event_at(recorded.time_ms, recorded.event);
+    }
+
+    let start = Instant::now();
+    let code = teal_main::run(args, config, gui);
+    println!("replayed {count} events in {:.3?}", start.elapsed());
+    code
+}